@@ -0,0 +1,172 @@
+use std::cell::RefCell;
+use std::sync::{Arc, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+#[cfg(test)]
+use std::sync::{Condvar, Mutex};
+
+/// The time as seen by the services, so that time-dependent behaviour such
+/// as the retry backoffs can be tested without waiting for it.
+pub trait Clock: Send + Sync {
+    /// The monotonic time, which the delays are measured against.
+    fn now(&self) -> Instant;
+
+    /// The wall-clock time, which the status files record.
+    fn system_time(&self) -> SystemTime;
+
+    /// Blocks the calling thread for the given duration.
+    fn sleep(&self, duration: Duration);
+
+    /// The time elapsed since `earlier`, which is zero if it is yet to come.
+    fn elapsed(&self, earlier: Instant) -> Duration {
+        self.now().saturating_duration_since(earlier)
+    }
+}
+
+/// The clock of the operating system.
+pub struct RealClock;
+
+impl Clock for RealClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+/// A clock which only moves when it is advanced. Sleeping threads wake up
+/// once the clock got advanced past the end of their sleep.
+#[cfg(test)]
+pub struct ManualClock {
+    started: Instant,
+    started_at: SystemTime,
+    state: Mutex<ManualState>,
+    changed: Condvar,
+}
+
+#[cfg(test)]
+#[derive(Default)]
+struct ManualState {
+    elapsed: Duration,
+
+    /// The number of threads sleeping until the clock gets advanced.
+    sleepers: usize,
+}
+
+#[cfg(test)]
+impl ManualClock {
+    /// The clock starts at the given number of seconds since the UNIX epoch.
+    pub fn new(epoch_secs: u64) -> Self {
+        ManualClock {
+            started: Instant::now(),
+            started_at: UNIX_EPOCH + Duration::from_secs(epoch_secs),
+            state: Mutex::new(ManualState::default()),
+            changed: Condvar::new(),
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, ManualState> {
+        self.state.lock().expect("the manual clock is poisoned")
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.state().elapsed += duration;
+        self.changed.notify_all();
+    }
+
+    /// Blocks until `count` threads are sleeping, so that the clock is only
+    /// advanced once they are waiting on it.
+    pub fn wait_for_sleepers(&self, count: usize) {
+        let mut state = self.state();
+        while state.sleepers < count {
+            state = self.changed.wait(state).expect("the manual clock is poisoned");
+        }
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.started + self.state().elapsed
+    }
+
+    fn system_time(&self) -> SystemTime {
+        self.started_at + self.state().elapsed
+    }
+
+    fn sleep(&self, duration: Duration) {
+        let mut state = self.state();
+        let wake_up_at = state.elapsed + duration;
+        if state.elapsed >= wake_up_at {
+            return;
+        }
+
+        state.sleepers += 1;
+        self.changed.notify_all();
+        while state.elapsed < wake_up_at {
+            state = self.changed.wait(state).expect("the manual clock is poisoned");
+        }
+        state.sleepers -= 1;
+    }
+}
+
+/// The real clock, in effect unless `with` puts another one in effect.
+static CLOCK: OnceLock<Arc<dyn Clock>> = OnceLock::new();
+
+thread_local! {
+    /// Takes over the clock in effect on the current thread, see `with`.
+    static OVERRIDE: RefCell<Option<Arc<dyn Clock>>> = const { RefCell::new(None) };
+}
+
+/// Returns the clock in effect.
+pub fn current() -> Arc<dyn Clock> {
+    OVERRIDE.with(|o| o.borrow().clone())
+        .unwrap_or_else(|| CLOCK.get_or_init(|| Arc::new(RealClock)).clone())
+}
+
+/// Returns the current time as milliseconds since the UNIX epoch, the unit of
+/// the timestamps in the status files.
+pub fn now_millis() -> u64 {
+    current().system_time().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+/// Runs `f` against the given clock, on the current thread and the service
+/// threads it starts with `utilities::spawn`.
+pub fn with<R>(clock: Arc<dyn Clock>, f: impl FnOnce() -> R) -> R {
+    let previous = OVERRIDE.with(|o| o.replace(Some(clock)));
+    let result = f();
+    OVERRIDE.with(|o| *o.borrow_mut() = previous);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manual_clock() {
+        let clock = Arc::new(ManualClock::new(1_700_000_000));
+        let started = clock.now();
+        clock.sleep(Duration::ZERO);
+
+        let sleeper = {
+            let clock = clock.clone();
+            thread::spawn(move || clock.sleep(Duration::from_secs(3600)))
+        };
+        clock.wait_for_sleepers(1);
+        clock.advance(Duration::from_secs(1800));
+        clock.advance(Duration::from_secs(1799));
+        assert!(!sleeper.is_finished());
+        clock.advance(Duration::from_secs(1));
+        sleeper.join().unwrap();
+
+        assert_eq!(Duration::from_secs(3600), clock.elapsed(started));
+        assert_eq!(1_700_003_600_000, with(clock, now_millis));
+    }
+}
@@ -0,0 +1,64 @@
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::services::admin::{self, AdminConfig};
+use crate::services::processor::{self, ProcessorState};
+
+const USAGE: &str = "usage: archive-daemon [--admin <address>] [--max-lag <seconds>]";
+
+/// Parses the admin server options; the server is only started when an
+/// address is given.
+fn parse_admin_config(args: &[String]) -> Result<Option<AdminConfig>, String> {
+    let mut address = None;
+    let mut max_archive_lag_secs = 300;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--admin" => address = Some(args.next().ok_or(USAGE)?.clone()),
+            "--max-lag" => {
+                max_archive_lag_secs = args.next()
+                    .and_then(|v| v.parse().ok())
+                    .ok_or("--max-lag expects a number of seconds")?;
+            },
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+
+    Ok(address.map(|address| AdminConfig { address, max_archive_lag_secs }))
+}
+
+/// Runs the processor in the background of `archive-push --async`, shipping
+/// segments as soon as they are enqueued, optionally along with the HTTP admin
+/// server.
+pub fn run(config: &Config, args: &[String]) -> i32 {
+    let admin_config = match parse_admin_config(args) {
+        Ok(admin_config) => admin_config,
+        Err(message) => {
+            eprintln!("{}", message);
+            return 2;
+        }
+    };
+
+    let state = Arc::new(ProcessorState::default());
+    let proc_handle = processor::daemon_startup(config, state.clone());
+
+    let admin_handle = match admin_config.map(|config| admin::service_startup(&config, state)).transpose() {
+        Ok(admin_handle) => admin_handle,
+        Err(e) => {
+            eprintln!("Failed to start the admin server: {}", e);
+            return 1;
+        }
+    };
+
+    // the processor runs forever, so returning means it has died; the admin
+    // server keeps reporting it as unhealthy.
+    if proc_handle.join().is_err() {
+        eprintln!("The processor thread has died");
+    }
+    if let Some(admin_handle) = admin_handle {
+        admin_handle.join().unwrap();
+    }
+
+    1
+}
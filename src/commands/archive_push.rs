@@ -0,0 +1,402 @@
+use std::path::Path;
+use std::time::Duration;
+
+use crate::clock;
+use crate::config::Config;
+use crate::destination;
+use crate::filesystem;
+use crate::services::processor::{self, WalResult};
+use crate::spool;
+use crate::utilities::FileEntry;
+use crate::wal::{self, WalFile};
+
+const USAGE: &str = "usage: archive-push [--async] [--look-ahead <n>] [--timeout <seconds>] <segment path> <segment name>";
+
+/// How often the acknowledgement spool is checked while waiting for the daemon.
+const SPOOL_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The options given to the archive-push command.
+#[derive(Debug, PartialEq)]
+struct ArchivePushOptions {
+    /// When set, the segment is left to the processor daemon and the command
+    /// only checks the acknowledgement and error spools. Until the daemon
+    /// acknowledges the segment, the command fails so that PostgreSQL calls it
+    /// again later on.
+    asynchronous: bool,
+
+    /// The number of ready segments following the requested one, which are
+    /// enqueued so that the daemon can ship them ahead of PostgreSQL's requests.
+    look_ahead: usize,
+
+    /// The amount of time to wait for the daemon to acknowledge the segment
+    /// before giving up on this call, none by default.
+    timeout: Duration,
+
+    /// The `%p` argument, the location of the segment.
+    segment_path: String,
+
+    /// The `%f` argument, the name of the segment. History and backup files are
+    /// archived under their own name as well.
+    segment_name: String,
+}
+
+impl ArchivePushOptions {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut asynchronous = false;
+        let mut look_ahead = 8;
+        let mut timeout = Duration::ZERO;
+        let mut positional = Vec::new();
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--async" => asynchronous = true,
+                "--look-ahead" => {
+                    look_ahead = args.next()
+                        .and_then(|v| v.parse().ok())
+                        .ok_or("--look-ahead expects a number of segments")?;
+                },
+                "--timeout" => {
+                    timeout = args.next()
+                        .and_then(|v| v.parse().ok())
+                        .map(Duration::from_secs)
+                        .ok_or("--timeout expects a number of seconds")?;
+                },
+                _ => positional.push(arg.clone()),
+            }
+        }
+
+        match <[String; 2]>::try_from(positional) {
+            Ok([_, segment_name]) if !wal::is_archivable_name(&segment_name) => {
+                Err(format!("{:?} is not the name of a file PostgreSQL archives", segment_name))
+            },
+            Ok([segment_path, segment_name]) => Ok(ArchivePushOptions {
+                asynchronous,
+                look_ahead,
+                timeout,
+                segment_path,
+                segment_name,
+            }),
+            Err(_) => Err(USAGE.to_string()),
+        }
+    }
+}
+
+/// Writes the ready file of the segment unless it is already enqueued, in which
+/// case the existing file is kept as it carries the processor's progress.
+fn enqueue(segment_name: &str, segment_path: &str) -> std::io::Result<WalFile> {
+    let wal_file = WalFile::for_segment(segment_name, segment_path);
+    if !filesystem::current().exists(Path::new(&wal_file.file_name)) {
+        wal_file.flush_to_file()?;
+    }
+
+    Ok(wal_file)
+}
+
+/// Picks the `look_ahead` segments following `segment_name` among the given
+/// `.ready` status file names, in archiving order. History and backup files
+/// are left to PostgreSQL to push.
+fn next_ready_segments(ready_files: Vec<String>, segment_name: &str, look_ahead: usize) -> Vec<String> {
+    let mut segments = ready_files.into_iter()
+        .filter_map(|f| f.strip_suffix(".ready").map(String::from))
+        .filter(|s| wal::parse_segment_name(s).is_some() && s.as_str() > segment_name)
+        .collect::<Vec<String>>();
+    segments.sort();
+    segments.truncate(look_ahead);
+    segments
+}
+
+/// Enqueues the segments PostgreSQL marked as ready in its own archive_status
+/// directory, which sits next to the requested segment.
+fn enqueue_look_ahead(options: &ArchivePushOptions) -> std::io::Result<()> {
+    if options.look_ahead == 0 {
+        return Ok(());
+    }
+
+    let wal_dir = Path::new(&options.segment_path).parent().unwrap_or(Path::new("."));
+    let ready_files = match filesystem::current().read_dir(&wal_dir.join("archive_status")) {
+        Ok(paths) => paths.iter()
+            .filter_map(|p| p.file_name().and_then(|name| name.to_str()).map(str::to_string))
+            .collect::<Vec<String>>(),
+        Err(_) => return Ok(()),
+    };
+
+    for segment in next_ready_segments(ready_files, &options.segment_name, options.look_ahead) {
+        if !spool::is_acknowledged(&segment) {
+            enqueue(&segment, wal_dir.join(&segment).to_str().unwrap())?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Archives the segment in the calling process, through the same function the
+/// processor service uses, which fails the attempt rather than panicking. A
+/// status file the daemon already wrote is kept, along with its attempt history.
+fn run_sync(config: &Config, options: &ArchivePushOptions) -> i32 {
+    let wal_file = match enqueue(&options.segment_name, &options.segment_path) {
+        Ok(wal_file) => wal_file,
+        Err(e) => {
+            eprintln!("Failed to enqueue segment {:?}: {}", options.segment_name, e);
+            return 1;
+        }
+    };
+
+    let ready_file = FileEntry::from_path(wal_file.file_name.clone());
+    match processor::attempt_wal_file(&ready_file, destination::from_config(config).as_ref(), &config.processor) {
+        WalResult::Success(_) => 0,
+        WalResult::Fail { reason, .. } => {
+            eprintln!("Failed to archive segment {:?}: {}", options.segment_name, reason);
+            1
+        }
+    }
+}
+
+/// Leaves the segment to the processor daemon, and checks for its outcome in
+/// either the acknowledgement or the error spool, for up to the timeout. The
+/// segment is left enqueued when there is no outcome yet.
+fn run_async(options: &ArchivePushOptions) -> i32 {
+    if let Err(e) = enqueue(&options.segment_name, &options.segment_path)
+        .and_then(|_| enqueue_look_ahead(options)) {
+        eprintln!("Failed to enqueue segment {:?}: {}", options.segment_name, e);
+        return 1;
+    }
+
+    let clock = clock::current();
+    let started = clock.now();
+    loop {
+        if spool::is_acknowledged(&options.segment_name) {
+            return 0;
+        }
+
+        match spool::take_error(&options.segment_name) {
+            Ok(Some(message)) => {
+                eprintln!("Failed to archive segment {:?}: {}", options.segment_name, message);
+                return 1;
+            },
+            Ok(None) => {},
+            Err(e) => {
+                eprintln!("Failed to read the error spool: {}", e);
+                return 1;
+            }
+        }
+
+        if clock.elapsed(started) >= options.timeout {
+            eprintln!("Segment {:?} is enqueued, not archived yet", options.segment_name);
+            return 1;
+        }
+        clock.sleep(SPOOL_POLL_INTERVAL);
+    }
+}
+
+/// Entrypoint compatible with PostgreSQL's archive_command, invoked as
+/// `archive-push %p %f`. The segment goes through the same WalFile state
+/// machine and destination as the processor service. The returned exit code is
+/// 0 only once the segment is durably archived.
+pub fn run(config: &Config, args: &[String]) -> i32 {
+    let options = match ArchivePushOptions::parse(args) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}", message);
+            return 2;
+        }
+    };
+
+    if spool::is_acknowledged(&options.segment_name) {
+        println!("Segment {:?} is already archived", options.segment_name);
+        return 0;
+    }
+
+    // a failure of the daemon is reported once, then the segment is retried. An
+    // attempt in the calling process replaces the recorded failure with its own
+    // outcome, so it is only reported.
+    let spooled_error = if options.asynchronous {
+        spool::take_error(&options.segment_name)
+    } else {
+        spool::read_error(&options.segment_name)
+    };
+    match spooled_error {
+        Ok(Some(message)) if options.asynchronous => {
+            eprintln!("Failed to archive segment {:?}: {}", options.segment_name, message);
+            return 1;
+        },
+        Ok(Some(message)) => eprintln!("Retrying segment {:?}, which previously failed: {}", options.segment_name, message),
+        Ok(None) => {},
+        Err(e) => {
+            eprintln!("Failed to read the error spool: {}", e);
+            return 1;
+        },
+    }
+
+    if !filesystem::current().exists(Path::new(&options.segment_path)) {
+        eprintln!("Segment {:?} does not exist", options.segment_path);
+        return 1;
+    }
+
+    if options.asynchronous {
+        run_async(&options)
+    } else {
+        run_sync(config, &options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::args;
+    use std::io::Read;
+    use std::sync::Arc;
+    use crate::config::{ConfigArgs, LoadedConfig};
+    use crate::filesystem::{FileSystem, MemoryFileSystem};
+    use crate::utilities;
+    use crate::wal::ArchivedSegment;
+
+    const SEGMENT: &str = "000000010000000000000001";
+    const SEGMENT_PATH: &str = "pg_wal/000000010000000000000001";
+
+    /// A file system holding the layout's directories and the segment.
+    fn file_system() -> Arc<MemoryFileSystem> {
+        let fs = Arc::new(MemoryFileSystem::default());
+        let layout = utilities::layout();
+        for dir in [&layout.source_dir, &layout.status_dir, "pg_wal"] {
+            fs.create_dir_all(Path::new(dir)).unwrap();
+        }
+        fs.write(Path::new(SEGMENT_PATH), b"segment").unwrap();
+        fs
+    }
+
+    fn config() -> Config {
+        LoadedConfig::load(&ConfigArgs::default(), std::iter::empty()).unwrap().config
+    }
+
+    #[test]
+    fn parse_options() {
+        let options = ArchivePushOptions::parse(&args(&["pg_wal/000000010000000000000001", "000000010000000000000001"])).unwrap();
+        assert!(!options.asynchronous);
+        assert_eq!(8, options.look_ahead);
+
+        let options = ArchivePushOptions::parse(&args(&[
+            "--async", "--look-ahead", "2", "--timeout", "5", "pg_wal/000000010000000000000001", "000000010000000000000001"])).unwrap();
+        assert!(options.asynchronous);
+        assert_eq!(2, options.look_ahead);
+        assert_eq!(Duration::from_secs(5), options.timeout);
+        assert_eq!("000000010000000000000001", options.segment_name);
+
+        assert!(ArchivePushOptions::parse(&args(&["000000010000000000000001"])).is_err());
+        assert!(ArchivePushOptions::parse(&args(&["--look-ahead", "x", "a", "b"])).is_err());
+        assert!(ArchivePushOptions::parse(&args(&["pg_wal/segment", "../000000010000000000000001"])).is_err());
+        assert!(ArchivePushOptions::parse(&args(&["pg_wal/00000002.history", "00000002.history"])).is_ok());
+    }
+
+    #[test]
+    fn look_ahead_selection() {
+        let ready_files = args(&[
+            "000000010000000000000004.ready",
+            "000000010000000000000001.ready",
+            "000000010000000000000003.ready",
+            "000000010000000000000002.done",
+            "000000010000000000000005.ready",
+            "00000002.history.ready",
+            "000000010000000000000002.00000028.backup.ready",
+        ]);
+
+        assert_eq!(
+            args(&["000000010000000000000003", "000000010000000000000004"]),
+            next_ready_segments(ready_files.clone(), "000000010000000000000001", 2));
+        assert!(next_ready_segments(ready_files, "000000010000000000000005", 2).is_empty());
+    }
+
+    #[test]
+    fn acknowledgement() {
+        filesystem::with(file_system(), || {
+            let marker = format!("{}/{}.done", utilities::layout().source_dir, SEGMENT);
+            filesystem::current().write(Path::new(&marker), &[]).unwrap();
+            assert!(!spool::is_acknowledged(SEGMENT));
+
+            let mut wal_file = WalFile::for_segment(SEGMENT, SEGMENT_PATH);
+            wal_file.generate_done_file().unwrap();
+            assert!(!spool::is_acknowledged(SEGMENT));
+
+            wal_file.archived = Some(ArchivedSegment { size: 7, sha256: String::new() });
+            wal_file.generate_done_file().unwrap();
+            assert!(spool::is_acknowledged(SEGMENT));
+            assert_eq!(0, run(&config(), &args(&["--async", SEGMENT_PATH, SEGMENT])));
+        });
+    }
+
+    #[test]
+    fn sync_keeps_the_attempt_history() {
+        let config = config();
+        filesystem::with(file_system(), || {
+            let mut wal_file = WalFile::for_segment(SEGMENT, SEGMENT_PATH);
+            wal_file.history.record_attempt(1_700_000_000_000);
+            wal_file.history.failures = 1;
+            wal_file.flush_to_file().unwrap();
+
+            assert_eq!(0, run(&config, &args(&[SEGMENT_PATH, SEGMENT])));
+            let history = WalFile::read(&wal_file.file_name).history;
+            assert_eq!((2, 1), (history.attempts, history.failures));
+            assert_eq!(Some(1_700_000_000_000), history.first_attempt_at);
+        });
+    }
+
+    #[test]
+    fn async_returns_once_enqueued() {
+        let config = config();
+        filesystem::with(file_system(), || {
+            assert_eq!(1, run(&config, &args(&["--async", "--look-ahead", "0", SEGMENT_PATH, SEGMENT])));
+            let ready_file = format!("{}/{}.ready", utilities::layout().status_dir, SEGMENT);
+            assert!(filesystem::current().exists(Path::new(&ready_file)));
+        });
+    }
+
+    #[test]
+    fn spooled_errors() {
+        let config = config();
+        filesystem::with(file_system(), || {
+            spool::record_error(SEGMENT, "destination unavailable").unwrap();
+            assert_eq!(1, run(&config, &args(&["--async", SEGMENT_PATH, SEGMENT])));
+            assert_eq!(None, spool::read_error(SEGMENT).unwrap());
+
+            // the synchronous attempt replaces the recorded failure with its outcome.
+            spool::record_error(SEGMENT, "destination unavailable").unwrap();
+            assert_eq!(0, run(&config, &args(&[SEGMENT_PATH, SEGMENT])));
+            assert_eq!(None, spool::read_error(SEGMENT).unwrap());
+            assert!(spool::is_acknowledged(SEGMENT));
+        });
+    }
+
+    #[test]
+    fn history_and_backup_files() {
+        let config = config();
+        filesystem::with(file_system(), || {
+            let names = [
+                "000000010000000000000001.00000028.backup",
+                "00000002.history",
+                "000000010000000000000001.partial",
+            ];
+            assert_eq!(0, run(&config, &args(&[SEGMENT_PATH, SEGMENT])));
+            for name in names {
+                let path = format!("pg_wal/{}", name);
+                filesystem::current().write(Path::new(&path), name.as_bytes()).unwrap();
+                assert_eq!(0, run(&config, &args(&[&path, name])));
+                assert!(spool::is_acknowledged(name));
+            }
+
+            // every file is archived under its own name, next to the segment.
+            let destination = destination::from_config(&config);
+            let archived = |name| {
+                let mut contents = Vec::new();
+                destination.get(name).unwrap().read_to_end(&mut contents).unwrap();
+                contents
+            };
+            assert_eq!(b"segment".to_vec(), archived(SEGMENT));
+            for name in names {
+                assert_eq!(name.as_bytes().to_vec(), archived(name));
+            }
+            let mut listed = destination.list().unwrap();
+            listed.sort();
+            assert_eq!(vec![SEGMENT, names[0], names[2], names[1]], listed);
+        });
+    }
+}
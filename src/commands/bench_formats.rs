@@ -0,0 +1,106 @@
+use std::io;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use serde::Serialize;
+
+use crate::filesystem;
+use crate::format::Format;
+use crate::simulation::duration;
+use crate::wal::{ArchivedSegment, ErrorClass, FailureReason, WalAction, WalFile};
+
+/// The number of status files written and read per format by default.
+const DEFAULT_FILES: u32 = 2000;
+
+/// The cost of writing and reading the status files in one format.
+#[derive(Serialize, Debug)]
+pub struct FormatCost {
+    pub format: Format,
+
+    /// The average size of a status file in bytes.
+    pub bytes_per_file: f64,
+
+    /// The average time to encode and write a status file.
+    #[serde(with = "duration")]
+    pub write: Duration,
+
+    /// The average time to read, decode and migrate a status file.
+    #[serde(with = "duration")]
+    pub read: Duration,
+}
+
+/// A status file as large as they get: archived after a few failed attempts.
+fn sample(n: u64, dir: &Path) -> WalFile {
+    let mut w = WalFile::generate_wal_file(n, WalAction::Timeout { count: 2 }, Duration::from_millis(40));
+    w.file_name = dir.join(format!("{}.ready", w.segment_name())).to_string_lossy().to_string();
+    w.segment_path = Some(format!("pg_wal/{}", w.segment_name()));
+    w.archived = Some(ArchivedSegment { size: 16 * 1024 * 1024, sha256: format!("{:064x}", n) });
+    w.history.record_attempt(1_792_378_332_950 + n);
+    w.history.record_attempt(1_792_378_334_240 + n);
+    w.history.failures = 1;
+    w.history.last_error = Some(FailureReason {
+        attempt: 1,
+        elapsed: Duration::from_millis(20),
+        ..FailureReason::new(ErrorClass::Retryable, "timed out after 20ms")
+    });
+    w
+}
+
+/// Writes and reads back `files` status files in the format, in a scratch
+/// directory under `root`.
+fn measure(format: Format, files: u32, root: &Path) -> io::Result<FormatCost> {
+    let dir = root.join(format.name());
+    let fs = filesystem::current();
+    fs.create_dir_all(&dir)?;
+    let samples = (0..files).map(|n| sample(n as u64, &dir)).collect::<Vec<_>>();
+
+    let started = Instant::now();
+    for w in samples.iter() {
+        w.flush_to_file_as(format)?;
+    }
+    let write = started.elapsed();
+
+    let started = Instant::now();
+    let mut bytes = 0;
+    for w in samples.iter() {
+        bytes += fs.size(Path::new(&w.file_name))?;
+        WalFile::try_read(&w.file_name)?;
+    }
+    let read = started.elapsed();
+
+    fs.remove_dir_all(&dir)?;
+    Ok(FormatCost {
+        format,
+        bytes_per_file: bytes as f64 / files as f64,
+        write: write / files,
+        read: read / files,
+    })
+}
+
+/// Compares the cost of the status file formats, printing a JSON report.
+pub fn run(args: &[String]) -> i32 {
+    let files = match args {
+        [] => Some(DEFAULT_FILES),
+        [files] => files.parse::<u32>().ok().filter(|n| *n > 0),
+        _ => None,
+    };
+    let Some(files) = files else {
+        eprintln!("usage: bench-formats [<files>]");
+        return 2;
+    };
+
+    let root = std::env::temp_dir().join(format!("wal-bench-formats-{}", std::process::id()));
+    let costs = Format::ALL.iter()
+        .map(|format| measure(*format, files, &root))
+        .collect::<io::Result<Vec<_>>>();
+    let _ = filesystem::current().remove_dir_all(&root);
+    match costs {
+        Ok(costs) => {
+            println!("{}", serde_json::to_string_pretty(&costs).expect("failed to serialize the benchmark report"));
+            0
+        },
+        Err(e) => {
+            eprintln!("Failed to benchmark the status file formats: {}", e);
+            1
+        }
+    }
+}
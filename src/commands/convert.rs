@@ -0,0 +1,92 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+use serde::Serialize;
+
+use crate::commands::migrate;
+use crate::filesystem;
+use crate::format::{self, Format};
+use crate::wal::WalFile;
+
+/// The outcome of converting the status files.
+#[derive(Serialize, Debug, Default)]
+pub struct ConvertReport {
+    /// The format the status files are converted to.
+    pub format: Format,
+
+    /// Whether the status files were left untouched.
+    pub dry_run: bool,
+
+    /// The number of status files rewritten, by the format they had.
+    pub converted: BTreeMap<Format, usize>,
+
+    /// The number of status files already in the format.
+    pub unchanged: usize,
+
+    /// Status files which could not be read, with the reason.
+    pub unreadable: BTreeMap<String, String>,
+}
+
+/// Rewrites the status file in the given format, upgrading it to the current
+/// version on the way. Returns the format it had.
+fn convert_file(path: &str, to: Format, dry_run: bool) -> Result<Format, String> {
+    let contents = filesystem::current().read(Path::new(path)).map_err(|e| e.to_string())?;
+    let (_, from) = format::decode(&contents)?;
+    if from == to || dry_run {
+        return Ok(from);
+    }
+
+    let mut wal_file = WalFile::parse(&contents)?;
+    wal_file.file_name = path.to_string();
+    wal_file.flush_to_file_as(to).map_err(|e| e.to_string())?;
+    Ok(from)
+}
+
+impl ConvertReport {
+    /// Converts the status files of the status and the quarantine directory.
+    pub fn collect(to: Format, dry_run: bool) -> io::Result<Self> {
+        let mut report = ConvertReport { format: to, dry_run, ..ConvertReport::default() };
+        for f in migrate::status_files()?.iter() {
+            match convert_file(&f.full_path, to, dry_run) {
+                Ok(from) if from == to => report.unchanged += 1,
+                Ok(from) => *report.converted.entry(from).or_insert(0) += 1,
+                Err(e) => {
+                    report.unreadable.insert(f.full_path.clone(), e);
+                },
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+/// Rewrites every status file in the given format, printing a JSON report.
+/// Meant to run while the services are stopped, along with setting
+/// `layout.status_format` to the same format. Exits non-zero when any status
+/// file could not be converted.
+pub fn run(args: &[String]) -> i32 {
+    let (to, dry_run) = match args {
+        [name] => (Format::from_name(name), false),
+        [name, flag] if flag == "--dry-run" => (Format::from_name(name), true),
+        _ => (None, false),
+    };
+    let Some(to) = to else {
+        eprintln!("usage: convert <json|msgpack|cbor> [--dry-run]");
+        return 2;
+    };
+
+    let report = match ConvertReport::collect(to, dry_run) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("Failed to convert the status files: {}", e);
+            return 1;
+        }
+    };
+
+    println!("{}", serde_json::to_string_pretty(&report).expect("failed to serialize the convert report"));
+    if report.unreadable.is_empty() {
+        0
+    } else {
+        1
+    }
+}
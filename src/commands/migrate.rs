@@ -0,0 +1,122 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+use serde::Serialize;
+
+use crate::filesystem;
+use crate::format::Format;
+use crate::schema::CURRENT_VERSION;
+use crate::utilities::{self, FileEntry};
+use crate::wal::WalFile;
+
+/// The outcome of migrating the status files.
+#[derive(Serialize, Debug, Default)]
+pub struct MigrateReport {
+    /// The version the status files are migrated to.
+    pub version: u32,
+
+    /// Whether the status files were left untouched.
+    pub dry_run: bool,
+
+    /// The number of status files rewritten, by the version they had.
+    pub migrated: BTreeMap<u32, usize>,
+
+    /// The number of status files already at the current version.
+    pub current: usize,
+
+    /// Status files which could not be read, with the reason.
+    pub unreadable: BTreeMap<String, String>,
+}
+
+/// Upgrades the status file to the current version, unless it already is,
+/// keeping the format it is written in. Returns the version it had.
+fn migrate_file(path: &str, dry_run: bool) -> Result<u32, String> {
+    let contents = filesystem::current().read(Path::new(path)).map_err(|e| e.to_string())?;
+    let (mut wal_file, version) = WalFile::parse_versioned(&contents)?;
+    if version < CURRENT_VERSION && !dry_run {
+        let format = Format::detect(&contents).expect("a parsed status file has a known format");
+        wal_file.file_name = path.to_string();
+        wal_file.flush_to_file_as(format).map_err(|e| e.to_string())?;
+    }
+
+    Ok(version)
+}
+
+/// Lists the status files of the status and the quarantine directory.
+pub fn status_files() -> io::Result<Vec<FileEntry>> {
+    let layout = utilities::layout();
+    let is_status_file = |x: &str| x.ends_with(".ready") || x.ends_with(".done");
+    let mut status_files = utilities::walk_directory(&layout.status_dir, is_status_file)?;
+    if filesystem::current().exists(Path::new(&layout.quarantine_dir)) {
+        status_files.extend(utilities::walk_directory(&layout.quarantine_dir, is_status_file)?);
+    }
+
+    Ok(status_files)
+}
+
+impl MigrateReport {
+    /// Migrates the status files of the status and the quarantine directory.
+    pub fn collect(dry_run: bool) -> io::Result<Self> {
+        let mut report = MigrateReport { version: CURRENT_VERSION, dry_run, ..MigrateReport::default() };
+        for f in status_files()?.iter() {
+            match migrate_file(&f.full_path, dry_run) {
+                Ok(CURRENT_VERSION) => report.current += 1,
+                Ok(version) => *report.migrated.entry(version).or_insert(0) += 1,
+                Err(e) => {
+                    report.unreadable.insert(f.full_path.clone(), e);
+                },
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+/// Rewrites every status file in the newest version of the format, printing a
+/// JSON report. Meant to run while the services are stopped. Exits non-zero
+/// when any status file could not be migrated.
+pub fn run(args: &[String]) -> i32 {
+    let dry_run = match args {
+        [] => false,
+        [flag] if flag == "--dry-run" => true,
+        _ => {
+            eprintln!("usage: migrate [--dry-run]");
+            return 2;
+        }
+    };
+
+    let report = match MigrateReport::collect(dry_run) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("Failed to migrate the status files: {}", e);
+            return 1;
+        }
+    };
+
+    println!("{}", serde_json::to_string_pretty(&report).expect("failed to serialize the migrate report"));
+    if report.unreadable.is_empty() {
+        0
+    } else {
+        1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn migration_keeps_the_format() {
+        let dir = TempDir::new("migrate");
+        let path = dir.join("000000010000000000000001.ready");
+        let path = path.to_str().unwrap();
+        let v4: serde_json::Value = serde_json::from_str(include_str!("../../fixtures/status/v4.json")).unwrap();
+        std::fs::write(path, Format::MessagePack.encode(&v4).unwrap()).unwrap();
+
+        assert_eq!(Ok(4), migrate_file(path, false));
+        let contents = std::fs::read(path).unwrap();
+        assert_eq!(Some(Format::MessagePack), Format::detect(&contents));
+        assert_eq!(Ok(CURRENT_VERSION), migrate_file(path, false));
+    }
+}
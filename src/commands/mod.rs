@@ -0,0 +1,10 @@
+pub mod archive_daemon;
+pub mod archive_push;
+pub mod bench_formats;
+pub mod convert;
+pub mod migrate;
+pub mod s3_server;
+pub mod simulate;
+pub mod status;
+pub mod sweep;
+pub mod verify;
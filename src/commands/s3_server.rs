@@ -0,0 +1,33 @@
+use crate::simulation::s3_server;
+
+const USAGE: &str = "usage: s3-server [--listen <address>]";
+
+/// The address the stand-in listens on by default, the one of the default S3 endpoint.
+const DEFAULT_ADDRESS: &str = "127.0.0.1:9000";
+
+/// Serves a local stand-in for an S3-compatible object store until killed, for
+/// the S3 destination to archive to. The objects are lost when it exits.
+pub fn run(args: &[String]) -> i32 {
+    let address = match args {
+        [] => DEFAULT_ADDRESS,
+        [flag, address] if flag == "--listen" => address.as_str(),
+        _ => {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    };
+
+    let (address, handle) = match s3_server::service_startup(address) {
+        Ok(started) => started,
+        Err(e) => {
+            eprintln!("Failed to start the S3 stand-in on {}: {}", address, e);
+            return 1;
+        }
+    };
+
+    println!("Serving the S3 stand-in on http://{}", address);
+    if handle.join().is_err() {
+        eprintln!("The S3 stand-in has died");
+    }
+    1
+}
@@ -0,0 +1,205 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::config::Config;
+use crate::filesystem::{self, FileSystem, RealFileSystem};
+use crate::services::{consumer, generator, processor};
+use crate::simulation::arrivals::{self, Replay};
+use crate::simulation::faults;
+use crate::simulation::report::Recorder;
+use crate::simulation::scenario::Scenario;
+use crate::simulation::trace;
+use crate::utilities;
+
+const USAGE: &str = "usage: simulate [--scenario <file>] [--replay <trace> | --replay-log <postgresql log>] [--time-scale <factor>] \
+[--json] [--report <file>] [--trace <file>]";
+
+/// The options given to the simulate command.
+#[derive(Debug, Default, PartialEq)]
+struct SimulateOptions {
+    /// The scenario the services follow during the run.
+    scenario_file: Option<String>,
+
+    /// The arrival trace to replay instead of generating segments.
+    replay_file: Option<String>,
+
+    /// The PostgreSQL server log whose archiving is replayed instead of
+    /// generating segments.
+    archive_log_file: Option<String>,
+
+    /// How many times faster than recorded the arrival trace is replayed.
+    time_scale: f64,
+
+    /// Prints the run report as JSON rather than as text.
+    json: bool,
+
+    /// Where to write the run report as JSON.
+    report_file: Option<String>,
+
+    /// Where to write the events of the run in the Chrome trace event format.
+    trace_file: Option<String>,
+}
+
+impl SimulateOptions {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut options = SimulateOptions { time_scale: 1.0, ..SimulateOptions::default() };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--scenario" => options.scenario_file = Some(args.next().ok_or(USAGE)?.clone()),
+                "--replay" => options.replay_file = Some(args.next().ok_or(USAGE)?.clone()),
+                "--replay-log" => options.archive_log_file = Some(args.next().ok_or(USAGE)?.clone()),
+                "--time-scale" => {
+                    options.time_scale = args.next()
+                        .and_then(|v| v.parse().ok())
+                        .filter(|f: &f64| f.is_finite() && *f > 0.0)
+                        .ok_or("--time-scale expects a positive factor")?;
+                },
+                "--json" => options.json = true,
+                "--report" => options.report_file = Some(args.next().ok_or(USAGE)?.clone()),
+                "--trace" => options.trace_file = Some(args.next().ok_or(USAGE)?.clone()),
+                _ => return Err(USAGE.to_string()),
+            }
+        }
+
+        if options.replay_file.is_some() && options.archive_log_file.is_some() {
+            return Err("--replay and --replay-log cannot be given together".to_string());
+        }
+        Ok(options)
+    }
+}
+
+/// Writes an output of the simulator itself, which goes to the real file
+/// system rather than to the one the services run against.
+fn write_file(path: &str, contents: &str, what: &str) -> Result<(), i32> {
+    RealFileSystem.write(Path::new(path), contents.as_bytes()).map_err(|e| {
+        eprintln!("Failed to write the {} to {:?}: {}", what, path, e);
+        1
+    })
+}
+
+/// Runs the generator, the processor and the consumer alongside each other,
+/// following the scenario given with `--scenario` if any. The generator
+/// replays the arrival trace given with `--replay`, or the archiving logged
+/// in the PostgreSQL server log given with `--replay-log`, if any. Then prints the
+/// run report, as JSON with `--json`. The JSON report and the trace of the
+/// run, which can be opened in Perfetto, are optionally written to files.
+pub fn run(config: &Config, args: &[String]) -> i32 {
+    let options = match SimulateOptions::parse(args) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}", message);
+            return 2;
+        }
+    };
+
+    let phases = match options.scenario_file.as_deref().map(Scenario::load).transpose() {
+        Ok(phases) => phases.unwrap_or_default(),
+        Err(message) => {
+            eprintln!("{}", message);
+            return 1;
+        }
+    };
+
+    let entries = match (&options.replay_file, &options.archive_log_file) {
+        (Some(trace_file), _) => Some(arrivals::read_trace(trace_file)),
+        (None, Some(log_file)) => Some(arrivals::read_archive_log(log_file, config.simulation.wal_segment_size)),
+        (None, None) => None,
+    };
+    let replay = match entries.transpose() {
+        Ok(entries) => entries.map(|entries| Replay { entries, time_scale: options.time_scale }),
+        Err(message) => {
+            eprintln!("{}", message);
+            return 1;
+        }
+    };
+
+    match faults::from_config(&config.simulation) {
+        Ok(file_system) => filesystem::install(file_system),
+        Err(e) => {
+            eprintln!("Failed to set up the file system: {}", e);
+            return 1;
+        }
+    }
+
+    let ready_files = utilities::get_ready_files().unwrap();
+    let done_files = utilities::get_done_files().unwrap();
+
+    println!("{:?}", ready_files);
+    println!("{:?}", done_files);
+
+    // the services run alongside each other, as the phases of the scenario
+    // are timed from the start of the run.
+    let recorder = Arc::new(Recorder::new());
+    let scenario = Arc::new(Scenario::new(phases));
+    let generated = Arc::new(AtomicBool::new(false));
+    let mut gen_handle = Some(generator::service_startup(&config.simulation, replay, recorder.clone(), scenario.clone()));
+
+    // the segments the consumer requeues after the processor is done go through it again.
+    loop {
+        let processed = Arc::new(AtomicBool::new(false));
+        let proc_handle = processor::service_startup(config, recorder.clone(), scenario.clone(), generated.clone());
+        let consumer_handle = consumer::service_startup(&config.simulation, recorder.clone(), processed.clone());
+
+        if let Some(gen_handle) = gen_handle.take() {
+            gen_handle.join().unwrap();
+            generated.store(true, Ordering::SeqCst);
+        }
+        proc_handle.join().unwrap();
+        processed.store(true, Ordering::SeqCst);
+
+        let requeued = consumer_handle.join().unwrap();
+        if requeued == 0 {
+            break;
+        }
+        println!("Processing the {} requeued segment(s) again", requeued);
+    }
+
+    let report = recorder.report();
+    let report_json = serde_json::to_string_pretty(&report).expect("failed to serialize the run report");
+    if let Some(report_file) = &options.report_file {
+        if let Err(code) = write_file(report_file, &report_json, "run report") {
+            return code;
+        }
+    }
+
+    if let Some(trace_file) = &options.trace_file {
+        let trace_json = serde_json::to_string(&trace::chrome_trace(&recorder.events()))
+            .expect("failed to serialize the trace");
+        if let Err(code) = write_file(trace_file, &trace_json, "trace") {
+            return code;
+        }
+    }
+
+    if options.json {
+        println!("{}", report_json);
+    } else {
+        print!("{}", report);
+    }
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::args;
+
+    #[test]
+    fn parse_options() {
+        assert_eq!(SimulateOptions { time_scale: 1.0, ..SimulateOptions::default() }, SimulateOptions::parse(&[]).unwrap());
+
+        let options = SimulateOptions::parse(&args(&["--json", "--trace", "run.trace.json"])).unwrap();
+        assert!(options.json);
+        assert_eq!(Some("run.trace.json".to_string()), options.trace_file);
+
+        let options = SimulateOptions::parse(&args(&["--replay", "arrivals.csv", "--time-scale", "10"])).unwrap();
+        assert_eq!((Some("arrivals.csv".to_string()), 10.0), (options.replay_file, options.time_scale));
+
+        assert!(SimulateOptions::parse(&args(&["--replay", "arrivals.csv", "--replay-log", "postgresql.log"])).is_err());
+        assert!(SimulateOptions::parse(&args(&["--time-scale", "0"])).is_err());
+        assert!(SimulateOptions::parse(&args(&["--report"])).is_err());
+        assert!(SimulateOptions::parse(&args(&["--verbose"])).is_err());
+    }
+}
@@ -0,0 +1,289 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::io;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+use serde::Serialize;
+
+use crate::clock;
+use crate::filesystem;
+use crate::utilities::{self, FileEntry};
+use crate::wal::{self, WalFile};
+
+const USAGE: &str = "usage: status [--json]";
+
+/// The oldest segment which is not archived yet.
+#[derive(Serialize, Debug)]
+pub struct OldestSegment {
+    pub segment: String,
+
+    /// Seconds since the segment became ready.
+    pub age_secs: u64,
+}
+
+/// A range of missing segments in the sequence, both ends inclusive.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct SequenceGap {
+    pub first_missing: String,
+    pub last_missing: String,
+}
+
+/// Describes how far behind archiving is.
+#[derive(Serialize, Debug)]
+pub struct StatusReport {
+    /// Segments waiting to be archived by the processor.
+    pub ready: usize,
+
+    /// Segments archived by the processor, not yet acknowledged by the consumer.
+    pub in_progress: usize,
+
+    /// Segments archived and acknowledged.
+    pub archived: usize,
+
+    /// Segments moved aside, which are not attempted anymore.
+    pub quarantined: usize,
+
+    pub oldest_unarchived: Option<OldestSegment>,
+
+    /// Seconds since the UNIX epoch of the latest successful archive.
+    pub last_archived_at: Option<u64>,
+
+    /// The number of failed attempts of the segments that failed at least once.
+    pub failures: BTreeMap<String, u32>,
+
+    pub gaps: Vec<SequenceGap>,
+}
+
+fn modified_secs(path: &str) -> io::Result<u64> {
+    let modified = filesystem::current().modified(Path::new(path))?;
+    Ok(modified.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0))
+}
+
+/// Finds the missing ranges between the first and the last known segment of
+/// every timeline. The last segment of a logical log file is followed by the
+/// first one of the next, see `wal::parse_segment_name`.
+pub(crate) fn find_gaps<'a>(segments: impl Iterator<Item = &'a String>) -> Vec<SequenceGap> {
+    let mut timelines: BTreeMap<u32, BTreeSet<u64>> = BTreeMap::new();
+    for (timeline, n) in segments.filter_map(|s| wal::parse_segment_name(s)) {
+        timelines.entry(timeline).or_default().insert(n);
+    }
+
+    let mut gaps = Vec::new();
+    for (timeline, sequence) in timelines {
+        let mut previous: Option<u64> = None;
+        for n in sequence {
+            if let Some(p) = previous {
+                if n > p + 1 {
+                    gaps.push(SequenceGap {
+                        first_missing: wal::segment_name(timeline, p + 1),
+                        last_missing: wal::segment_name(timeline, n - 1),
+                    });
+                }
+            }
+            previous = Some(n);
+        }
+    }
+
+    gaps
+}
+
+impl StatusReport {
+    /// Builds the report from the status directory and the processor's
+    /// .done markers.
+    pub fn collect() -> io::Result<Self> {
+        let now = clock::current().system_time().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+
+        let markers = utilities::get_done_files()?;
+        let marker_names = markers.iter()
+            .map(|f| f.file_name.clone())
+            .collect::<HashSet<String>>();
+        let (in_progress, ready): (Vec<FileEntry>, Vec<FileEntry>) = utilities::get_ready_files()?
+            .into_iter()
+            .partition(|f| marker_names.contains(&f.file_name));
+        let archived = utilities::walk_directory(&utilities::layout().status_dir, |x| x.ends_with(".done"))?;
+        let quarantined = if filesystem::current().exists(Path::new(&utilities::layout().quarantine_dir)) {
+            utilities::walk_directory(&utilities::layout().quarantine_dir, |x| x.ends_with(".ready"))?
+        } else {
+            Vec::new()
+        };
+
+        let mut oldest_unarchived = None;
+        if let Some(oldest) = ready.iter().min_by(|a, b| a.file_name.cmp(&b.file_name)) {
+            oldest_unarchived = Some(OldestSegment {
+                segment: oldest.file_name.clone(),
+                age_secs: now.saturating_sub(modified_secs(&oldest.full_path)?),
+            });
+        }
+
+        let mut last_archived_at = None;
+        for f in markers.iter().chain(archived.iter()) {
+            last_archived_at = last_archived_at.max(Some(modified_secs(&f.full_path)?));
+        }
+
+        let mut failures = BTreeMap::new();
+        for f in ready.iter() {
+            // the status file may be written concurrently, or not be a WalFile at all.
+            if let Ok(w) = WalFile::try_read(&f.full_path) {
+                if w.history.failures > 0 {
+                    failures.insert(f.file_name.clone(), w.history.failures);
+                }
+            }
+        }
+
+        let known_segments = ready.iter()
+            .chain(in_progress.iter())
+            .chain(archived.iter())
+            .chain(quarantined.iter())
+            .map(|f| &f.file_name);
+
+        Ok(StatusReport {
+            ready: ready.len(),
+            in_progress: in_progress.len(),
+            archived: archived.len(),
+            quarantined: quarantined.len(),
+            oldest_unarchived,
+            last_archived_at,
+            failures,
+            gaps: find_gaps(known_segments),
+        })
+    }
+
+    fn print_human(&self) {
+        println!("ready:        {}", self.ready);
+        println!("in progress:  {}", self.in_progress);
+        println!("archived:     {}", self.archived);
+        println!("quarantined:  {}", self.quarantined);
+
+        match &self.oldest_unarchived {
+            Some(oldest) => println!("oldest unarchived: {} ({}s old)", oldest.segment, oldest.age_secs),
+            None => println!("oldest unarchived: none"),
+        }
+
+        match self.last_archived_at {
+            Some(at) => println!("last archived at:  {} (UNIX time)", at),
+            None => println!("last archived at:  never"),
+        }
+
+        if !self.failures.is_empty() {
+            println!("failures:");
+            for (segment, count) in self.failures.iter() {
+                println!("  {}: {}", segment, count);
+            }
+        }
+
+        if !self.gaps.is_empty() {
+            println!("gaps:");
+            for gap in self.gaps.iter() {
+                println!("  {} - {}", gap.first_missing, gap.last_missing);
+            }
+        }
+    }
+}
+
+/// Prints how far behind archiving is, either human readable or as JSON.
+pub fn run(args: &[String]) -> i32 {
+    let json = match args {
+        [] => false,
+        [flag] if flag == "--json" => true,
+        _ => {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    };
+
+    let report = match StatusReport::collect() {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("Failed to collect the archive status: {}", e);
+            return 1;
+        }
+    };
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report).expect("failed to serialize the status report"));
+    } else {
+        report.print_human();
+    }
+
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use crate::clock::ManualClock;
+    use crate::filesystem::{FileSystem, MemoryFileSystem};
+    use crate::wal::WalAction;
+
+    #[test]
+    fn sequence_gaps() {
+        let segments = [
+            "000000010000000000000001",
+            "000000010000000000000002",
+            "000000010000000000000005",
+            "00000001000000000000000A",
+            "000000020000000000000007",
+            "0000000001",
+            "000000030000000000000100",
+        ].iter().map(|s| s.to_string()).collect::<Vec<String>>();
+
+        assert_eq!(vec![
+            SequenceGap {
+                first_missing: "000000010000000000000003".to_string(),
+                last_missing: "000000010000000000000004".to_string(),
+            },
+            SequenceGap {
+                first_missing: "000000010000000000000006".to_string(),
+                last_missing: "000000010000000000000009".to_string(),
+            },
+        ], find_gaps(segments.iter()));
+    }
+
+    #[test]
+    fn gaps_across_log_files() {
+        let segments = [
+            "0000000100000000000000FE",
+            "0000000100000000000000FF",
+            "000000010000000100000000",
+            "000000010000000100000002",
+        ].iter().map(|s| s.to_string()).collect::<Vec<String>>();
+
+        assert_eq!(vec![
+            SequenceGap {
+                first_missing: "000000010000000100000001".to_string(),
+                last_missing: "000000010000000100000001".to_string(),
+            },
+        ], find_gaps(segments.iter()));
+
+        let segments = ["0000000100000000000000FE", "000000010000000100000001"].iter().map(|s| s.to_string()).collect::<Vec<String>>();
+        assert_eq!(vec![
+            SequenceGap {
+                first_missing: "0000000100000000000000FF".to_string(),
+                last_missing: "000000010000000100000000".to_string(),
+            },
+        ], find_gaps(segments.iter()));
+    }
+
+    #[test]
+    fn collect_from_the_file_system() {
+        let fs = Arc::new(MemoryFileSystem::default());
+        let clock = Arc::new(ManualClock::new(1_700_000_000));
+        let report = clock::with(clock.clone(), || filesystem::with(fs.clone(), || {
+            let layout = utilities::layout();
+            fs.create_dir_all(Path::new(&layout.status_dir)).unwrap();
+            WalFile::generate_wal_file(0xFF, WalAction::Success, Duration::ZERO).flush_to_file().unwrap();
+            clock.advance(Duration::from_secs(90));
+            WalFile::generate_wal_file(0x100, WalAction::Success, Duration::ZERO).flush_to_file().unwrap();
+            WalFile::generate_wal_file(0x100, WalAction::Success, Duration::ZERO).generate_done_file().unwrap();
+            clock.advance(Duration::from_secs(30));
+            StatusReport::collect().unwrap()
+        }));
+
+        assert_eq!((1, 1, 0), (report.ready, report.in_progress, report.archived));
+        let oldest = report.oldest_unarchived.unwrap();
+        assert_eq!(("0000000100000000000000FF", 120), (oldest.segment.as_str(), oldest.age_secs));
+        assert_eq!(Some(1_700_000_090), report.last_archived_at);
+        assert!(report.gaps.is_empty());
+    }
+}
@@ -0,0 +1,373 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::config::{ConfigArgs, LoadedConfig};
+
+const USAGE: &str = "usage: sweep [--seeds <n>] [--seed-base <seed>] [--grid <key>=<v1>,<v2>,...]... \
+[--scenario <file>] [--format csv|json] [--output <file>]";
+
+/// The two-sided 95% critical values of Student's t distribution for 1 to 30
+/// degrees of freedom; the normal approximation is used beyond.
+const T_95: [f64; 30] = [
+    12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228,
+    2.201, 2.179, 2.160, 2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086,
+    2.080, 2.074, 2.069, 2.064, 2.060, 2.056, 2.052, 2.048, 2.045, 2.042,
+];
+
+/// Reads a metric out of a run report.
+type MetricFn = fn(&Value) -> Option<f64>;
+
+/// The metrics taken from every run report, along with how to find them.
+const METRICS: [(&str, MetricFn); 9] = [
+    ("elapsed_secs", |r| r["elapsed_secs"].as_f64()),
+    ("processor_per_sec", |r| stage(r, "processor", "per_sec")),
+    ("processor_bytes_per_sec", |r| stage(r, "processor", "bytes_per_sec")),
+    ("consumer_per_sec", |r| stage(r, "consumer", "per_sec")),
+    ("latency_mean_ms", |r| r["latency"]["mean_ms"].as_f64()),
+    ("latency_p50_ms", |r| r["latency"]["p50_ms"].as_f64()),
+    ("latency_p99_ms", |r| r["latency"]["p99_ms"].as_f64()),
+    ("failed_attempts", |r| r["failed_attempts"].as_f64()),
+    ("peak_backlog", |r| r["peak_backlog"].as_f64()),
+];
+
+fn stage(report: &Value, name: &str, field: &str) -> Option<f64> {
+    report["throughput"].as_array()?
+        .iter()
+        .find(|s| s["stage"] == name)?[field]
+        .as_f64()
+}
+
+#[derive(Debug, PartialEq)]
+enum Format {
+    Csv,
+    Json,
+}
+
+/// The options given to the sweep command.
+#[derive(Debug, PartialEq)]
+struct SweepOptions {
+    /// The number of runs of every point of the grid, each with its own seed.
+    seeds: u64,
+
+    /// The seed of the first run, the configured seed unless given.
+    seed_base: Option<u64>,
+
+    /// The configuration keys to vary, along with their values.
+    grid: Vec<(String, Vec<String>)>,
+
+    /// The scenario every run follows.
+    scenario_file: Option<String>,
+    format: Format,
+    output: Option<String>,
+}
+
+impl SweepOptions {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut options = SweepOptions { seeds: 10, seed_base: None, grid: Vec::new(), scenario_file: None, format: Format::Csv, output: None };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--seeds" => {
+                    options.seeds = args.next()
+                        .and_then(|v| v.parse().ok())
+                        .filter(|n| *n > 0)
+                        .ok_or("--seeds expects a positive number of runs")?;
+                },
+                "--seed-base" => {
+                    options.seed_base = Some(args.next()
+                        .and_then(|v| v.parse().ok())
+                        .ok_or("--seed-base expects a seed")?);
+                },
+                "--grid" => {
+                    let (key, values) = args.next()
+                        .and_then(|v| v.split_once('='))
+                        .ok_or("--grid expects <key>=<v1>,<v2>,...")?;
+                    options.grid.push((key.to_string(), values.split(',').map(String::from).collect()));
+                },
+                "--scenario" => options.scenario_file = Some(args.next().ok_or(USAGE)?.clone()),
+                "--format" => {
+                    options.format = match args.next().map(String::as_str) {
+                        Some("csv") => Format::Csv,
+                        Some("json") => Format::Json,
+                        _ => return Err("--format expects csv or json".to_string()),
+                    };
+                },
+                "--output" => options.output = Some(args.next().ok_or(USAGE)?.clone()),
+                _ => return Err(USAGE.to_string()),
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+/// Expands the grid into every combination of its values.
+fn grid_points(grid: &[(String, Vec<String>)]) -> Vec<Vec<(String, String)>> {
+    let mut points = vec![Vec::new()];
+    for (key, values) in grid {
+        points = points.into_iter()
+            .flat_map(|point| values.iter().map(move |value| {
+                let mut point = point.clone();
+                point.push((key.clone(), value.clone()));
+                point
+            }))
+            .collect();
+    }
+
+    points
+}
+
+/// The mean of a metric over the runs of a grid point, with its 95%
+/// confidence interval.
+#[derive(Serialize, Debug, PartialEq)]
+struct Summary {
+    mean: f64,
+    stddev: f64,
+    ci95_low: f64,
+    ci95_high: f64,
+}
+
+impl Summary {
+    fn new(samples: &[f64]) -> Self {
+        let n = samples.len() as f64;
+        let mean = samples.iter().sum::<f64>() / n;
+        if samples.len() < 2 {
+            return Summary { mean, stddev: 0.0, ci95_low: mean, ci95_high: mean };
+        }
+
+        let variance = samples.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / (n - 1.0);
+        let stddev = variance.sqrt();
+        let t = T_95.get(samples.len() - 2).copied().unwrap_or(1.96);
+        let half_width = t * stddev / n.sqrt();
+        Summary { mean, stddev, ci95_low: mean - half_width, ci95_high: mean + half_width }
+    }
+}
+
+/// The aggregated runs of a grid point.
+#[derive(Serialize, Debug)]
+struct PointResult {
+    parameters: BTreeMap<String, String>,
+    runs: usize,
+    metrics: BTreeMap<&'static str, Summary>,
+}
+
+/// The layout keys pointed at the run's own directory, and the destination
+/// and trace at ones of the run's own name, so that runs do not see each
+/// other's segments.
+fn isolated_layout(root: &Path, run: &str, loaded: &LoadedConfig) -> Vec<(String, String)> {
+    let path = |p: &str| root.join(p).to_string_lossy().into_owned();
+    let mut layout = vec![
+        ("layout.source_dir".to_string(), path("file-source")),
+        ("layout.status_dir".to_string(), path("file-source/file-status")),
+        ("layout.error_spool_dir".to_string(), path("file-source/error-spool")),
+        ("layout.quarantine_dir".to_string(), path("file-source/quarantine")),
+        ("layout.reconciliation_log".to_string(), path("file-source/reconciliation.log")),
+    ];
+    if loaded.values.contains_key("destination.path") {
+        layout.push(("destination.path".to_string(), path("archive")));
+    }
+    if let Some((Value::String(prefix), _)) = loaded.values.get("destination.prefix") {
+        layout.push(("destination.prefix".to_string(), format!("{}{}/", prefix, run)));
+    }
+    if let Some((Value::String(trace_file), _)) = loaded.values.get("processor.trace_file") {
+        layout.push(("processor.trace_file".to_string(), format!("{}.{}", trace_file, run)));
+    }
+
+    layout
+}
+
+/// Runs a single simulation in a child process, as the layout is fixed for
+/// the lifetime of a process, and returns its run report.
+fn run_once(config_args: &ConfigArgs, options: &SweepOptions, settings: &[(String, String)], root: &Path) -> Result<Value, String> {
+    fs::create_dir_all(root.join("file-source/file-status"))
+        .map_err(|e| format!("failed to create {:?}: {}", root, e))?;
+
+    let report_file = root.join("report.json");
+    let mut command = Command::new(env::current_exe().map_err(|e| e.to_string())?);
+    if let Some(file) = &config_args.file {
+        command.args(["--config", file]);
+    }
+    for assignment in config_args.overrides.iter() {
+        command.args(["--set", assignment]);
+    }
+    for (key, value) in settings {
+        command.arg("--set").arg(format!("{}={}", key, value));
+    }
+    command.arg("simulate").arg("--report").arg(&report_file);
+    if let Some(scenario_file) = &options.scenario_file {
+        command.args(["--scenario", scenario_file]);
+    }
+
+    let output = command.stdout(Stdio::null()).stderr(Stdio::piped()).output()
+        .map_err(|e| format!("failed to start the simulation: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("the simulation failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+
+    let report = fs::read_to_string(&report_file)
+        .map_err(|e| format!("failed to read the run report: {}", e))?;
+    serde_json::from_str(&report).map_err(|e| format!("failed to parse the run report: {}", e))
+}
+
+fn sweep(config_args: &ConfigArgs, loaded: &LoadedConfig, options: &SweepOptions) -> Result<Vec<PointResult>, String> {
+    for (key, _) in options.grid.iter() {
+        if !loaded.values.contains_key(key) {
+            return Err(format!("unknown configuration key {:?}", key));
+        }
+    }
+
+    let seed_base = match options.seed_base {
+        Some(seed) => seed,
+        None => loaded.values.get("simulation.seed").and_then(|(v, _)| v.as_u64()).unwrap_or(0),
+    };
+
+    let mut results = Vec::new();
+    for (n, point) in grid_points(&options.grid).into_iter().enumerate() {
+        let mut samples: BTreeMap<&'static str, Vec<f64>> = BTreeMap::new();
+        for seed in seed_base..seed_base + options.seeds {
+            let run = format!("{}-{}", n, seed);
+            let root = env::temp_dir().join(format!("wal-sweep-{}-{}", std::process::id(), run));
+            let mut settings = isolated_layout(&root, &run, loaded);
+            settings.extend(point.iter().cloned());
+            settings.push(("simulation.seed".to_string(), seed.to_string()));
+
+            eprintln!("Running {:?} with seed {}", point, seed);
+            let report = run_once(config_args, options, &settings, &root);
+            let _ = fs::remove_dir_all(&root);
+
+            let report = report?;
+            for (metric, get) in METRICS.iter() {
+                if let Some(value) = get(&report) {
+                    samples.entry(metric).or_default().push(value);
+                }
+            }
+        }
+
+        results.push(PointResult {
+            parameters: point.into_iter().collect(),
+            runs: options.seeds as usize,
+            metrics: samples.iter().map(|(metric, s)| (*metric, Summary::new(s))).collect(),
+        });
+    }
+
+    Ok(results)
+}
+
+fn to_csv(grid: &[(String, Vec<String>)], results: &[PointResult]) -> String {
+    let mut header = grid.iter().map(|(key, _)| key.clone()).collect::<Vec<String>>();
+    header.push("runs".to_string());
+    for (metric, _) in METRICS.iter() {
+        header.extend(["mean", "ci95_low", "ci95_high"].map(|s| format!("{}_{}", metric, s)));
+    }
+
+    let mut csv = header.join(",") + "\n";
+    for result in results {
+        let mut row = grid.iter()
+            .map(|(key, _)| result.parameters[key].clone())
+            .collect::<Vec<String>>();
+        row.push(result.runs.to_string());
+        for (metric, _) in METRICS.iter() {
+            match result.metrics.get(metric) {
+                Some(s) => row.extend([s.mean, s.ci95_low, s.ci95_high].map(|v| v.to_string())),
+                None => row.extend(["", "", ""].map(String::from)),
+            }
+        }
+        csv += &(row.join(",") + "\n");
+    }
+
+    csv
+}
+
+/// Runs the simulation for every seed and every point of the parameter grid,
+/// each in its own temporary directory, and prints the mean of every metric
+/// along with its 95% confidence interval.
+pub fn run(config_args: &ConfigArgs, loaded: &LoadedConfig, args: &[String]) -> i32 {
+    let options = match SweepOptions::parse(args) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}", message);
+            return 2;
+        }
+    };
+
+    let results = match sweep(config_args, loaded, &options) {
+        Ok(results) => results,
+        Err(message) => {
+            eprintln!("{}", message);
+            return 1;
+        }
+    };
+
+    let table = match options.format {
+        Format::Csv => to_csv(&options.grid, &results),
+        Format::Json => serde_json::to_string_pretty(&results).expect("failed to serialize the sweep results"),
+    };
+    match options.output {
+        Some(output) => {
+            if let Err(e) = fs::write(&output, table) {
+                eprintln!("Failed to write the sweep results to {:?}: {}", output, e);
+                return 1;
+            }
+        },
+        None => print!("{}", table),
+    }
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::args;
+
+    #[test]
+    fn parse_grid() {
+        let options = SweepOptions::parse(&args(&[
+            "--seeds", "3", "--grid", "simulation.wal_failure_ratio=0.1,0.5", "--grid", "processor.threads=1,4", "--format", "json"])).unwrap();
+        assert_eq!(3, options.seeds);
+        assert_eq!(Format::Json, options.format);
+
+        let points = grid_points(&options.grid);
+        assert_eq!(4, points.len());
+        assert_eq!(vec![
+            ("simulation.wal_failure_ratio".to_string(), "0.5".to_string()),
+            ("processor.threads".to_string(), "1".to_string()),
+        ], points[2]);
+        assert_eq!(vec![Vec::<(String, String)>::new()], grid_points(&[]));
+
+        assert!(SweepOptions::parse(&args(&["--seeds", "0"])).is_err());
+        assert!(SweepOptions::parse(&args(&["--grid", "processor.threads"])).is_err());
+    }
+
+    #[test]
+    fn runs_are_isolated() {
+        let config_args = ConfigArgs {
+            file: None,
+            overrides: args(&["destination.kind=s3", "destination.prefix=wal/", "processor.trace_file=trace.log"]),
+        };
+        let loaded = LoadedConfig::load(&config_args, std::iter::empty()).unwrap();
+        let settings = isolated_layout(Path::new("/tmp/run"), "0-7", &loaded).into_iter().collect::<BTreeMap<String, String>>();
+
+        assert_eq!("/tmp/run/file-source", settings["layout.source_dir"]);
+        assert_eq!("wal/0-7/", settings["destination.prefix"]);
+        assert_eq!("trace.log.0-7", settings["processor.trace_file"]);
+        assert!(!settings.contains_key("destination.path"));
+    }
+
+    #[test]
+    fn confidence_interval() {
+        let summary = Summary::new(&[1.0, 2.0, 3.0, 4.0]);
+        assert_eq!(2.5, summary.mean);
+        assert!((summary.stddev - 1.290_994).abs() < 1e-6);
+        // t(3) = 3.182, stddev / sqrt(4) = 0.645497
+        assert!((summary.ci95_high - 2.5 - 2.054_000).abs() < 1e-3);
+
+        let single = Summary::new(&[7.0]);
+        assert_eq!((7.0, 7.0), (single.ci95_low, single.ci95_high));
+    }
+}
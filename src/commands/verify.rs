@@ -0,0 +1,299 @@
+use std::collections::{BTreeSet, HashSet};
+use std::io;
+use serde::Serialize;
+
+use crate::commands::status::{self, SequenceGap};
+use crate::config::Config;
+use crate::destination::{self, Destination};
+use crate::utilities;
+use crate::wal::{ArchivedSegment, WalFile};
+
+/// An archived segment whose contents differ from what was recorded.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Mismatch {
+    pub segment: String,
+    pub expected: ArchivedSegment,
+    pub actual: ArchivedSegment,
+}
+
+/// An archived segment which could not be re-read from the destination.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ReadFailure {
+    pub segment: String,
+    pub error: String,
+}
+
+/// The outcome of auditing the archive.
+#[derive(Serialize, Debug, Default)]
+pub struct VerifyReport {
+    /// Segments archived according to the status directory.
+    pub checked: usize,
+
+    /// Segments whose archived copy matches the recorded size and checksum.
+    pub verified: usize,
+
+    /// Archived segments which carried no data, e.g. the simulated ones.
+    pub without_payload: usize,
+
+    /// Segments recorded as archived, missing from the destination.
+    pub missing: Vec<String>,
+
+    /// Segments in the destination, which are not recorded as archived.
+    pub extra: Vec<String>,
+
+    pub mismatched: Vec<Mismatch>,
+
+    /// Segments recorded as archived, which the destination failed to read.
+    pub read_failures: Vec<ReadFailure>,
+
+    /// .done markers in the source directory without a status file.
+    pub orphan_markers: Vec<String>,
+
+    /// Status files which could not be read.
+    pub unreadable: Vec<String>,
+
+    pub gaps: Vec<SequenceGap>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty()
+            && self.extra.is_empty()
+            && self.mismatched.is_empty()
+            && self.read_failures.is_empty()
+            && self.orphan_markers.is_empty()
+            && self.unreadable.is_empty()
+            && self.gaps.is_empty()
+    }
+}
+
+/// The problem found when re-reading an archived segment.
+#[derive(Debug, PartialEq)]
+enum SegmentProblem {
+    Missing,
+    Mismatched(ArchivedSegment),
+}
+
+/// Re-reads the archived segment and compares it against the recorded values.
+fn verify_segment(destination: &dyn Destination, segment: &str, expected: &ArchivedSegment) -> io::Result<Result<(), SegmentProblem>> {
+    let reader = match destination.get(segment) {
+        Ok(reader) => reader,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Err(SegmentProblem::Missing)),
+        Err(e) => return Err(e),
+    };
+
+    let (size, sha256) = utilities::sha256_digest(reader)?;
+    let actual = ArchivedSegment { size, sha256 };
+    if &actual == expected {
+        Ok(Ok(()))
+    } else {
+        Ok(Err(SegmentProblem::Mismatched(actual)))
+    }
+}
+
+impl VerifyReport {
+    /// Walks the status directory, the .done markers in the source directory
+    /// and the destination, re-reading every archived segment.
+    pub fn collect(destination: &dyn Destination) -> io::Result<Self> {
+        let mut report = VerifyReport::default();
+
+        let markers = utilities::get_done_files()?
+            .into_iter()
+            .map(|f| f.file_name)
+            .collect::<HashSet<String>>();
+        let status_files = utilities::walk_directory(&utilities::layout().status_dir, |x| x.ends_with(".ready") || x.ends_with(".done"))?;
+        let known_segments = status_files.iter()
+            .map(|f| f.file_name.clone())
+            .collect::<HashSet<String>>();
+
+        // archived ones are acknowledged by the consumer, or still have the processor's marker.
+        let archived = status_files.iter()
+            .filter(|f| f.file_extension == "done" || markers.contains(&f.file_name))
+            .collect::<Vec<_>>();
+
+        let mut recorded = BTreeSet::new();
+        for f in archived.iter() {
+            report.checked += 1;
+            let wal_file = match WalFile::try_read(&f.full_path) {
+                Ok(wal_file) => wal_file,
+                Err(_) => {
+                    report.unreadable.push(f.file_name.clone());
+                    continue;
+                }
+            };
+
+            let expected = match wal_file.archived {
+                Some(expected) => expected,
+                None => {
+                    report.without_payload += 1;
+                    continue;
+                }
+            };
+
+            recorded.insert(f.file_name.clone());
+            // a failure to read one segment does not stop the audit of the others.
+            match verify_segment(destination, &f.file_name, &expected) {
+                Ok(Ok(())) => report.verified += 1,
+                Ok(Err(SegmentProblem::Missing)) => report.missing.push(f.file_name.clone()),
+                Ok(Err(SegmentProblem::Mismatched(actual))) => report.mismatched.push(Mismatch {
+                    segment: f.file_name.clone(),
+                    expected,
+                    actual,
+                }),
+                Err(e) => report.read_failures.push(ReadFailure { segment: f.file_name.clone(), error: e.to_string() }),
+            }
+        }
+
+        report.extra = destination.list()?
+            .into_iter()
+            .filter(|segment| !recorded.contains(segment))
+            .collect();
+        report.orphan_markers = markers.into_iter()
+            .filter(|segment| !known_segments.contains(segment))
+            .collect();
+        report.gaps = status::find_gaps(archived.iter().map(|f| &f.file_name));
+
+        report.missing.sort();
+        report.extra.sort();
+        report.orphan_markers.sort();
+        report.unreadable.sort();
+        Ok(report)
+    }
+}
+
+/// Audits the archive, printing a JSON report. Exits non-zero when any
+/// problem is found.
+pub fn run(config: &Config, args: &[String]) -> i32 {
+    if !args.is_empty() {
+        eprintln!("usage: verify");
+        return 2;
+    }
+
+    let report = match VerifyReport::collect(destination::from_config(config).as_ref()) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("Failed to verify the archive: {}", e);
+            return 1;
+        }
+    };
+
+    println!("{}", serde_json::to_string_pretty(&report).expect("failed to serialize the verify report"));
+    if report.is_ok() {
+        0
+    } else {
+        1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::Read;
+    use std::path::Path;
+    use std::sync::Arc;
+    use crate::destination::LocalDirectory;
+    use crate::filesystem::{self, FileSystem, MemoryFileSystem};
+    use crate::testing::TempDir;
+    use crate::utilities::FileEntry;
+
+    /// Fails to read the given segment, as an unreachable destination would.
+    struct Unreachable<'a> {
+        inner: &'a dyn Destination,
+        segment: &'a str,
+    }
+
+    impl Destination for Unreachable<'_> {
+        fn put(&self, segment_name: &str, source: &Path) -> io::Result<()> {
+            self.inner.put(segment_name, source)
+        }
+
+        fn get(&self, segment_name: &str) -> io::Result<Box<dyn Read>> {
+            if segment_name == self.segment {
+                return Err(io::Error::from(io::ErrorKind::ConnectionReset));
+            }
+            self.inner.get(segment_name)
+        }
+
+        fn list(&self) -> io::Result<Vec<String>> {
+            self.inner.list()
+        }
+    }
+
+    /// Writes the status file of an archived segment, in the status directory
+    /// once acknowledged or along with the processor's marker otherwise.
+    fn archived(fs: &MemoryFileSystem, segment: &str, contents: &[u8], acknowledged: bool) {
+        let layout = utilities::layout();
+        let (size, sha256) = utilities::sha256_digest(contents).unwrap();
+        let mut w = WalFile::for_segment(segment, "pg_wal/segment");
+        w.archived = Some(ArchivedSegment { size, sha256 });
+        w.flush_to_file().unwrap();
+        if acknowledged {
+            w.mark_done().unwrap();
+        } else {
+            fs.write(Path::new(&format!("{}/{}.done", layout.source_dir, segment)), &[]).unwrap();
+        }
+    }
+
+    #[test]
+    fn verify_archived_segment() {
+        let root = TempDir::new("verify");
+        fs::write(root.join("source"), b"segment contents").unwrap();
+
+        let destination = LocalDirectory::new(root.join("archive"));
+        destination.put("000000010000000000000001", &root.join("source")).unwrap();
+        let (size, sha256) = utilities::sha256_digest(&b"segment contents"[..]).unwrap();
+        let expected = ArchivedSegment { size, sha256 };
+
+        assert_eq!(Ok(()), verify_segment(&destination, "000000010000000000000001", &expected).unwrap());
+        assert_eq!(Err(SegmentProblem::Missing), verify_segment(&destination, "000000010000000000000002", &expected).unwrap());
+
+        fs::write(root.join("archive/000000010000000000000001"), b"corrupted").unwrap();
+        match verify_segment(&destination, "000000010000000000000001", &expected).unwrap() {
+            Err(SegmentProblem::Mismatched(actual)) => assert_eq!(9, actual.size),
+            other => panic!("unexpected outcome: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn collect_finds_every_problem() {
+        let fs = Arc::new(MemoryFileSystem::default());
+        let layout = utilities::layout();
+        fs.create_dir_all(Path::new(&layout.status_dir)).unwrap();
+        fs.create_dir_all(Path::new("pg_wal")).unwrap();
+        fs.write(Path::new("pg_wal/segment"), b"segment").unwrap();
+
+        let destination = LocalDirectory::new("archive");
+        let report = filesystem::with(fs.clone(), || {
+            for segment in ["000000010000000000000001", "000000010000000000000002", "000000010000000000000005", "0000000100000000000000AA"] {
+                destination.put(segment, Path::new("pg_wal/segment")).unwrap();
+            }
+            archived(&fs, "000000010000000000000001", b"segment", true);
+            archived(&fs, "000000010000000000000002", b"segment", false);
+            archived(&fs, "000000010000000000000004", b"segment", true);
+            archived(&fs, "000000010000000000000005", b"another segment", true);
+            fs.write(Path::new(&format!("{}/000000010000000000000009.done", layout.source_dir)), &[]).unwrap();
+
+            let unreachable = Unreachable { inner: &destination, segment: "000000010000000000000002" };
+            VerifyReport::collect(&unreachable).unwrap()
+        });
+
+        assert_eq!((4, 1), (report.checked, report.verified));
+        assert_eq!(vec!["000000010000000000000004"], report.missing);
+        assert_eq!(vec!["0000000100000000000000AA"], report.extra);
+        assert_eq!(vec!["000000010000000000000005"], report.mismatched.iter().map(|m| m.segment.as_str()).collect::<Vec<_>>());
+        assert_eq!(vec!["000000010000000000000002"], report.read_failures.iter().map(|f| f.segment.as_str()).collect::<Vec<_>>());
+        assert_eq!(vec!["000000010000000000000009"], report.orphan_markers);
+        assert_eq!(vec![SequenceGap {
+            first_missing: "000000010000000000000003".to_string(),
+            last_missing: "000000010000000000000003".to_string(),
+        }], report.gaps);
+        assert!(!report.is_ok());
+    }
+
+    #[test]
+    fn extension_of_the_file_name() {
+        let entry = FileEntry::from_path("/var/lib/wal.d/file-status/000000010000000000000001.done".to_string());
+        assert_eq!(("000000010000000000000001", "done"), (entry.file_name.as_str(), entry.file_extension.as_str()));
+    }
+}
@@ -0,0 +1,576 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::time::Duration;
+use serde::{Serialize, Deserialize};
+use serde_json::{Map, Value};
+
+use crate::format::{self, Format};
+use crate::s3::S3Config;
+use crate::simulation::duration;
+use crate::simulation::lib::{self, SimulationConfig};
+use crate::utilities;
+
+/// The configuration files looked up in the working directory, when none is given.
+const DEFAULT_CONFIG_FILES: [&str; 2] = ["wal.toml", "wal.json"];
+
+/// The prefix of the environment variables overriding configuration values.
+/// `processor.threads` is overridden by `WAL_PROCESSOR_THREADS`.
+const ENV_PREFIX: &str = "WAL_";
+
+/// The sections holding one of several kinds, tagged by their "kind" field.
+/// A layer switching to another kind replaces the section as a whole.
+const TAGGED_SECTIONS: [&str; 1] = ["destination"];
+
+/// The simulation defaults, so that the commands which do not simulate
+/// anything run without a simulation config of their own.
+const DEFAULT_SIMULATION: &str = include_str!("simulation/simulation_conf.json");
+
+/// The keys of the simulation section which are left out by default.
+const OPTIONAL_SIMULATION_KEYS: [&str; 3] = [
+    "wal_process_duration_distribution",
+    "wal_generation_interval_distribution",
+    "wal_failure_model",
+];
+
+/// Where the services read and write their files.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Layout {
+    /// Holds the segments and the .done markers generated by the processor.
+    pub source_dir: String,
+
+    /// Holds the .ready and .done status files.
+    pub status_dir: String,
+
+    /// Holds the reason of the latest failed attempt of every segment.
+    pub error_spool_dir: String,
+
+    /// Holds the status files of the segments which are not attempted anymore.
+    pub quarantine_dir: String,
+
+    /// The file the consumer logs its reconciliations to.
+    pub reconciliation_log: String,
+
+    /// The format status files are written in, json, msgpack or cbor. They
+    /// are read in any of them.
+    pub status_format: Format,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Layout {
+            source_dir: utilities::SOURCE_DIR.to_string(),
+            status_dir: utilities::STATUS_DIR.to_string(),
+            error_spool_dir: utilities::ERROR_SPOOL_DIR.to_string(),
+            quarantine_dir: utilities::QUARANTINE_DIR.to_string(),
+            reconciliation_log: utilities::RECONCILIATION_LOG.to_string(),
+            status_format: Format::Json,
+        }
+    }
+}
+
+/// How the processor retries the segments which failed to be archived.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RetryPolicy {
+    /// The number of failed attempts after which a segment is quarantined,
+    /// 0 retries forever.
+    pub max_attempts: u32,
+
+    /// The delay before retrying a segment after its first failure, doubled
+    /// after every further failure.
+    #[serde(with = "duration")]
+    pub initial_backoff: Duration,
+
+    /// The upper bound of the delay between two attempts.
+    #[serde(with = "duration")]
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 0,
+            initial_backoff: Duration::ZERO,
+            max_backoff: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The delay before the next attempt of a segment which failed `failures` times.
+    pub fn backoff(&self, failures: u32) -> Duration {
+        let exponent = failures.saturating_sub(1).min(31);
+        self.initial_backoff
+            .saturating_mul(1 << exponent)
+            .min(self.max_backoff)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ProcessorConfig {
+    /// The number of worker threads archiving segments in parallel.
+    pub threads: u8,
+
+    pub retry: RetryPolicy,
+
+    /// How long the processor waits on an archive attempt before giving up
+    /// on it, which is how long a simulated timeout takes.
+    #[serde(with = "duration", default = "default_attempt_timeout")]
+    pub attempt_timeout: Duration,
+
+    /// When set, the processor appends every segment it is done with to this
+    /// arrival trace, which the simulation can replay.
+    #[serde(default)]
+    pub trace_file: Option<String>,
+}
+
+fn default_attempt_timeout() -> Duration {
+    Duration::from_secs(1)
+}
+
+impl Default for ProcessorConfig {
+    fn default() -> Self {
+        ProcessorConfig {
+            threads: 5,
+            retry: RetryPolicy::default(),
+            attempt_timeout: default_attempt_timeout(),
+            trace_file: None,
+        }
+    }
+}
+
+/// Where the segments are archived to.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum DestinationConfig {
+    /// A directory on the local file system.
+    Local { path: String },
+
+    /// A remote destination simulated on top of a local directory, which
+    /// behaves as `simulation.wal_backend` says.
+    Simulated { path: String },
+
+    /// A bucket of an S3-compatible object store.
+    S3(S3Config),
+}
+
+impl Default for DestinationConfig {
+    fn default() -> Self {
+        DestinationConfig::Local { path: utilities::ARCHIVE_DIR.to_string() }
+    }
+}
+
+/// The configuration of every service and command.
+#[derive(Serialize, Clone, Debug)]
+pub struct Config {
+    pub layout: Layout,
+    pub processor: ProcessorConfig,
+    pub destination: DestinationConfig,
+    pub simulation: SimulationConfig,
+}
+
+/// Where an effective configuration value comes from.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub enum Source {
+    Default,
+    File(String),
+    Env(String),
+    Cli,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::File(path) => write!(f, "file {}", path),
+            Source::Env(name) => write!(f, "env {}", name),
+            Source::Cli => write!(f, "cli --set"),
+        }
+    }
+}
+
+/// Where the configuration is read from, besides the defaults and the environment.
+#[derive(Default, Debug)]
+pub struct ConfigArgs {
+    /// The file given with `--config`.
+    pub file: Option<String>,
+
+    /// The `key=value` overrides given with `--set`.
+    pub overrides: Vec<String>,
+}
+
+impl ConfigArgs {
+    /// Consumes the leading `--config <file>` and `--set <key>=<value>` options,
+    /// returning the remaining arguments.
+    pub fn parse(args: &[String]) -> Result<(Self, &[String]), String> {
+        let mut config_args = ConfigArgs::default();
+        let mut rest = args;
+        loop {
+            match rest {
+                [flag, value, tail @ ..] if flag == "--config" => {
+                    config_args.file = Some(value.clone());
+                    rest = tail;
+                },
+                [flag, value, tail @ ..] if flag == "--set" => {
+                    config_args.overrides.push(value.clone());
+                    rest = tail;
+                },
+                [flag, ..] if flag == "--config" || flag == "--set" => {
+                    return Err(format!("{} expects a value", flag));
+                },
+                _ => return Ok((config_args, rest)),
+            }
+        }
+    }
+}
+
+/// The effective configuration along with the source of every value.
+pub struct LoadedConfig {
+    pub config: Config,
+
+    /// The effective values, keyed by their dotted path, e.g. `processor.threads`.
+    pub values: BTreeMap<String, (Value, Source)>,
+}
+
+/// Lists the leaf values of the tree, keyed by their dotted path.
+fn flatten(value: &Value, prefix: &str, leaves: &mut Vec<(String, Value)>) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                let path = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+                flatten(value, &path, leaves);
+            }
+        },
+        _ => leaves.push((prefix.to_string(), value.clone())),
+    }
+}
+
+/// The layered configuration tree, tracking the source of every leaf.
+struct Layers {
+    tree: Value,
+    sources: BTreeMap<String, Source>,
+}
+
+impl Layers {
+    /// Merges the given tree on top of the current one.
+    fn merge(&mut self, prefix: &str, value: Value, source: &Source) {
+        if prefix.is_empty() {
+            self.remove_other_kinds(&value);
+        }
+        let mut leaves = Vec::new();
+        flatten(&value, prefix, &mut leaves);
+        for (path, value) in leaves {
+            self.set(&path, value, source.clone());
+        }
+    }
+
+    fn set(&mut self, path: &str, value: Value, source: Source) {
+        let mut node = &mut self.tree;
+        for key in path.split('.') {
+            if !node.is_object() {
+                *node = Value::Object(Map::new());
+            }
+            node = node.as_object_mut().unwrap().entry(key).or_insert(Value::Null);
+        }
+        *node = value;
+        self.sources.insert(path.to_string(), source);
+    }
+
+    /// Removes the tagged sections which the given tree replaces with one of
+    /// another kind, as the fields of one kind mean nothing to the others.
+    fn remove_other_kinds(&mut self, value: &Value) {
+        for section in TAGGED_SECTIONS {
+            let kind = value.get(section).and_then(|v| v.get("kind"));
+            if kind.is_none() || self.get(section).and_then(|node| node.get("kind")) == kind {
+                continue;
+            }
+
+            if let Some(tree) = self.tree.as_object_mut() {
+                tree.remove(section);
+            }
+            let nested = format!("{}.", section);
+            self.sources.retain(|path, _| !path.starts_with(&nested));
+        }
+    }
+
+    fn get(&self, path: &str) -> Option<&Value> {
+        path.split('.').try_fold(&self.tree, |node, key| node.get(key))
+    }
+
+    /// Overrides a value of the schema with one given as text, keeping the
+    /// type of the value it replaces, or of the schema's when there is none.
+    fn set_text(&mut self, path: &str, text: &str, source: Source, schema: &BTreeMap<String, Value>) -> Result<(), String> {
+        let template = match self.get(path) {
+            Some(Value::Object(_)) => None,
+            Some(value) => Some(value),
+            None => schema.get(path),
+        };
+        let value = match template {
+            Some(Value::String(_)) => Value::String(text.to_string()),
+            Some(_) => serde_json::from_str(text).unwrap_or_else(|_| Value::String(text.to_string())),
+            None => return Err(format!("unknown configuration key {:?}", path)),
+        };
+
+        if let Some(section) = path.strip_suffix(".kind").filter(|s| TAGGED_SECTIONS.contains(s)) {
+            self.remove_other_kinds(&serde_json::json!({ section: { "kind": value.clone() } }));
+        }
+        self.merge(path, value, &source);
+        Ok(())
+    }
+}
+
+/// Reads a TOML configuration file, or one in any of the status file formats.
+fn read_config_file(path: &str) -> Result<Value, String> {
+    let contents = std::fs::read(path)
+        .map_err(|e| format!("failed to read the config file {:?}: {}", path, e))?;
+    let parsed = if path.ends_with(".toml") {
+        String::from_utf8(contents).map_err(|e| e.to_string())
+            .and_then(|text| toml::from_str(&text).map_err(|e| e.to_string()))
+    } else {
+        format::decode(&contents).map(|(value, _)| value)
+    };
+    parsed.map_err(|e| format!("failed to parse the config file {:?}: {}", path, e))
+}
+
+/// Lists every key of the configuration along with a value of its type: the
+/// defaults, the fields of every kind of the tagged sections and the keys
+/// which are left out by default.
+fn schema(defaults: &Value) -> BTreeMap<String, Value> {
+    let mut leaves = Vec::new();
+    flatten(defaults, "", &mut leaves);
+    let kinds = [
+        DestinationConfig::Simulated { path: utilities::ARCHIVE_DIR.to_string() },
+        DestinationConfig::S3(S3Config::default()),
+    ];
+    for kind in kinds {
+        flatten(&serde_json::json!({ "destination": kind }), "", &mut leaves);
+    }
+    for key in OPTIONAL_SIMULATION_KEYS {
+        leaves.push((format!("simulation.{}", key), Value::Null));
+    }
+    leaves.into_iter().collect()
+}
+
+/// The environment variable overriding the given key.
+fn env_name(key: &str) -> String {
+    format!("{}{}", ENV_PREFIX, key.replace('.', "_").to_uppercase())
+}
+
+fn section<T: for<'de> Deserialize<'de>>(tree: &mut Value, name: &str) -> Result<T, String> {
+    let value = tree.as_object_mut().and_then(|m| m.remove(name)).unwrap_or(Value::Null);
+    serde_json::from_value(value).map_err(|e| format!("invalid {} configuration: {}", name, e))
+}
+
+impl LoadedConfig {
+    /// Builds the configuration from the defaults, the simulation_conf.json
+    /// file, the configuration file, the environment and the command line, each
+    /// layer overriding the previous ones.
+    pub fn load(args: &ConfigArgs, env: impl Iterator<Item = (String, String)>) -> Result<Self, String> {
+        let defaults = serde_json::json!({
+            "layout": Layout::default(),
+            "processor": ProcessorConfig::default(),
+            "destination": DestinationConfig::default(),
+            "simulation": serde_json::from_str::<Value>(DEFAULT_SIMULATION).expect("invalid default simulation config"),
+        });
+        let schema = schema(&defaults);
+        let mut layers = Layers { tree: Value::Object(Map::new()), sources: BTreeMap::new() };
+        layers.merge("", defaults, &Source::Default);
+
+        let simulation_file = Format::ALL.iter()
+            .map(|f| format!("{}/simulation_conf.{}", utilities::SIMULATION_DIR, f))
+            .find(|f| Path::new(f).is_file());
+        if let Some(simulation_file) = simulation_file {
+            let simulation = read_config_file(&simulation_file)?;
+            layers.merge("simulation", simulation, &Source::File(simulation_file));
+        }
+
+        let env = env.collect::<BTreeMap<String, String>>();
+        let config_file = args.file.clone()
+            .or_else(|| env.get("WAL_CONFIG").cloned())
+            .or_else(|| DEFAULT_CONFIG_FILES.iter().find(|f| Path::new(f).is_file()).map(|f| f.to_string()));
+        if let Some(config_file) = config_file {
+            let file = read_config_file(&config_file)?;
+            layers.merge("", file, &Source::File(config_file));
+        }
+
+        // the kinds go first, as switching kinds drops the fields of the previous one.
+        let mut overridden = schema.keys().filter(|key| env.contains_key(&env_name(key))).collect::<Vec<&String>>();
+        overridden.sort_by_key(|key| !key.ends_with(".kind"));
+        for key in overridden {
+            let name = env_name(key);
+            layers.set_text(key, &env[&name], Source::Env(name), &schema)?;
+        }
+
+        for assignment in args.overrides.iter() {
+            let (key, text) = assignment.split_once('=')
+                .ok_or_else(|| format!("--set expects <key>=<value>, got {:?}", assignment))?;
+            layers.set_text(key, text, Source::Cli, &schema)?;
+        }
+
+        let mut values = BTreeMap::new();
+        let mut leaves = Vec::new();
+        flatten(&layers.tree, "", &mut leaves);
+        for (path, value) in leaves {
+            let source = layers.sources.get(&path).cloned().unwrap_or(Source::Default);
+            values.insert(path, (value, source));
+        }
+
+        let mut tree = layers.tree;
+        let config = Config {
+            layout: section(&mut tree, "layout")?,
+            processor: section(&mut tree, "processor")?,
+            destination: section(&mut tree, "destination")?,
+            simulation: SimulationConfig::from_value(
+                tree.as_object_mut().and_then(|m| m.remove("simulation")).unwrap_or(Value::Null))
+                .map_err(|e: lib::ConfigError| e.to_string())?,
+        };
+        if let Some(unknown) = tree.as_object().and_then(|m| m.keys().next()) {
+            return Err(format!("unknown configuration section {:?}", unknown));
+        }
+        if let DestinationConfig::S3(s3) = &config.destination {
+            s3.validate().map_err(|e| format!("invalid destination configuration: {}", e))?;
+        }
+
+        Ok(LoadedConfig { config, values })
+    }
+
+    /// Fails unless the simulation is configured by a file, either the
+    /// simulation_conf file or the simulation section of the config file,
+    /// as the defaults only stand in for the commands which simulate nothing.
+    pub fn require_simulation(&self) -> Result<(), String> {
+        let configured = self.values.iter()
+            .any(|(key, (_, source))| key.starts_with("simulation.") && matches!(source, Source::File(_)));
+        if configured {
+            Ok(())
+        } else {
+            Err(format!("the simulation is not configured, neither by {}/simulation_conf.json nor by \
+                the simulation section of the config file", utilities::SIMULATION_DIR))
+        }
+    }
+}
+
+/// Prints the effective configuration along with the source of every value.
+pub fn show(loaded: &LoadedConfig, args: &[String]) -> i32 {
+    match args {
+        [] => {
+            for (key, (value, source)) in loaded.values.iter() {
+                println!("{} = {}  ({})", key, value, source);
+            }
+        },
+        [flag] if flag == "--json" => {
+            let values = loaded.values.iter()
+                .map(|(key, (value, source))| (key.clone(), serde_json::json!({ "value": value, "source": source.to_string() })))
+                .collect::<Map<String, Value>>();
+            println!("{}", serde_json::to_string_pretty(&values).expect("failed to serialize the configuration"));
+        },
+        _ => {
+            eprintln!("usage: config show [--json]");
+            return 2;
+        }
+    }
+
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::distribution::FailureModel;
+    use crate::testing::args;
+
+    #[test]
+    fn parse_config_args() {
+        let all = args(&["--config", "wal.toml", "--set", "processor.threads=2", "status", "--json"]);
+        let (config_args, rest) = ConfigArgs::parse(&all).unwrap();
+        assert_eq!(Some("wal.toml".to_string()), config_args.file);
+        assert_eq!(args(&["processor.threads=2"]), config_args.overrides);
+        assert_eq!(args(&["status", "--json"]), rest);
+
+        assert!(ConfigArgs::parse(&args(&["--set"])).is_err());
+    }
+
+    #[test]
+    fn layers_override_each_other() {
+        let config_args = ConfigArgs {
+            file: None,
+            overrides: args(&["processor.retry.max_attempts=3", "simulation.wal_consumer_delay=5ms"]),
+        };
+        let env = [
+            ("WAL_PROCESSOR_THREADS".to_string(), "2".to_string()),
+            ("WAL_PROCESSOR_RETRY_MAX_ATTEMPTS".to_string(), "9".to_string()),
+            ("WAL_LAYOUT_SOURCE_DIR".to_string(), "1234".to_string()),
+        ];
+
+        let loaded = LoadedConfig::load(&config_args, env.into_iter()).unwrap();
+        assert_eq!(2, loaded.config.processor.threads);
+        assert_eq!(3, loaded.config.processor.retry.max_attempts);
+        assert_eq!("1234", loaded.config.layout.source_dir);
+        assert_eq!(Duration::from_millis(5), loaded.config.simulation.wal_consumer_delay);
+
+        assert_eq!(Source::Env("WAL_PROCESSOR_THREADS".to_string()), loaded.values["processor.threads"].1);
+        assert_eq!(Source::Cli, loaded.values["processor.retry.max_attempts"].1);
+        assert_eq!(Source::Default, loaded.values["layout.status_dir"].1);
+
+        let unknown = ConfigArgs { file: None, overrides: args(&["processor.thread=2"]) };
+        assert!(LoadedConfig::load(&unknown, std::iter::empty()).is_err());
+    }
+
+    #[test]
+    fn retry_backoff() {
+        let policy = RetryPolicy {
+            max_attempts: 0,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(500),
+        };
+        assert_eq!(Duration::from_millis(100), policy.backoff(1));
+        assert_eq!(Duration::from_millis(400), policy.backoff(3));
+        assert_eq!(Duration::from_millis(500), policy.backoff(10));
+        assert_eq!(Duration::from_millis(500), policy.backoff(u32::MAX));
+    }
+
+    #[test]
+    fn env_overrides_keys_without_a_value() {
+        let env = [
+            ("WAL_DESTINATION_BUCKET".to_string(), "wal".to_string()),
+            ("WAL_DESTINATION_KIND".to_string(), "s3".to_string()),
+            ("WAL_PROCESSOR_TRACE_FILE".to_string(), "trace.log".to_string()),
+            ("WAL_SIMULATION_WAL_FAILURE_MODEL".to_string(), r#"{"kind": "bernoulli", "ratio": 0.5}"#.to_string()),
+        ];
+
+        let loaded = LoadedConfig::load(&ConfigArgs::default(), env.into_iter()).unwrap();
+        assert!(matches!(loaded.config.destination, DestinationConfig::S3(S3Config { ref bucket, .. }) if bucket == "wal"));
+        assert_eq!(Some("trace.log".to_string()), loaded.config.processor.trace_file);
+        assert_eq!(Source::Env("WAL_SIMULATION_WAL_FAILURE_MODEL".to_string()), loaded.values["simulation.wal_failure_model.ratio"].1);
+        assert_eq!(FailureModel::Bernoulli { ratio: 0.5 }, loaded.config.simulation.wal_failure_model);
+    }
+
+    #[test]
+    fn simulation_defaults_stand_alone() {
+        let defaults = serde_json::from_str(DEFAULT_SIMULATION).unwrap();
+        assert!(SimulationConfig::from_value(defaults).is_ok());
+
+        let loaded = LoadedConfig::load(&ConfigArgs::default(), std::iter::empty()).unwrap();
+        assert_eq!(Ok(()), loaded.require_simulation());
+        let values = loaded.values.into_iter()
+            .map(|(key, (value, _))| (key, (value, Source::Default)))
+            .collect();
+        let defaults_only = LoadedConfig { config: loaded.config, values };
+        assert!(defaults_only.require_simulation().is_err());
+    }
+
+    #[test]
+    fn kind_replaces_the_whole_object() {
+        let mut layers = Layers { tree: Value::Object(Map::new()), sources: BTreeMap::new() };
+        layers.merge("", serde_json::json!({ "destination": DestinationConfig::default() }), &Source::Default);
+        let s3 = serde_json::json!({ "destination": { "kind": "s3", "endpoint": "http://127.0.0.1:9000", "bucket": "wal" } });
+        layers.merge("", s3, &Source::File("wal.json".to_string()));
+
+        assert_eq!(None, layers.get("destination.path"));
+        assert!(!layers.sources.contains_key("destination.path"));
+        let destination: DestinationConfig = section(&mut layers.tree, "destination").unwrap();
+        assert!(matches!(destination, DestinationConfig::S3(S3Config { ref bucket, .. }) if bucket == "wal"));
+    }
+}
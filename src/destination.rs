@@ -0,0 +1,121 @@
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::config::{Config, DestinationConfig};
+use crate::filesystem;
+use crate::s3::S3Destination;
+use crate::simulation::backend::SimulatedBackend;
+
+/// Represents a location where processed WAL segments are archived to.
+/// Implementations must only report success once the segment is durably stored.
+pub trait Destination: Send + Sync {
+    /// Stores the segment located at `source` under the given segment name.
+    /// A segment already stored under the name is replaced if the destination
+    /// overwrites, see `overwrites`. Otherwise it is kept: storing the same
+    /// contents again succeeds, storing other contents fails with
+    /// `AlreadyExists`.
+    fn put(&self, segment_name: &str, source: &Path) -> io::Result<()>;
+
+    /// Whether `put` replaces a segment already stored under the name.
+    fn overwrites(&self) -> bool {
+        true
+    }
+
+    /// Opens the archived segment for reading.
+    fn get(&self, segment_name: &str) -> io::Result<Box<dyn Read>>;
+
+    /// Lists the names of all archived segments.
+    fn list(&self) -> io::Result<Vec<String>>;
+}
+
+/// Archives the segments into a directory on the local file system.
+pub struct LocalDirectory {
+    root: PathBuf,
+}
+
+impl LocalDirectory {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        LocalDirectory { root: root.into() }
+    }
+}
+
+impl Destination for LocalDirectory {
+    /// The segment is first copied into a temporary ".tmp" file which is
+    /// renamed once its contents are synced, so a crash never leaves a half
+    /// written segment behind under its final name.
+    fn put(&self, segment_name: &str, source: &Path) -> io::Result<()> {
+        let fs = filesystem::current();
+        fs.create_dir_all(&self.root)?;
+
+        // not ".partial", which PostgreSQL archives segments under after a promotion.
+        let partial_path = self.root.join(format!("{}.tmp", segment_name));
+        let final_path = self.root.join(segment_name);
+
+        fs.copy(source, &partial_path)?;
+        fs.fsync(&partial_path)?;
+        fs.rename(&partial_path, &final_path)?;
+
+        // persist the rename itself.
+        fs.fsync(&self.root)
+    }
+
+    fn get(&self, segment_name: &str) -> io::Result<Box<dyn Read>> {
+        Ok(filesystem::current().open(&self.root.join(segment_name))?)
+    }
+
+    fn list(&self) -> io::Result<Vec<String>> {
+        let files = match filesystem::current().read_dir(&self.root) {
+            Ok(files) => files,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        Ok(files.iter()
+            .filter_map(|f| f.file_name().map(|name| name.to_string_lossy().into_owned()))
+            .filter(|name| !name.ends_with(".tmp"))
+            .collect())
+    }
+}
+
+/// Builds the configured destination, shared by the processor service and the commands.
+pub fn from_config(config: &Config) -> Arc<dyn Destination> {
+    match &config.destination {
+        DestinationConfig::Local { path } => Arc::new(LocalDirectory::new(path)),
+        DestinationConfig::Simulated { path } => Arc::new(SimulatedBackend::new(
+            LocalDirectory::new(path),
+            config.simulation.wal_backend.clone(),
+            config.simulation.seed)),
+        DestinationConfig::S3(s3) => Arc::new(S3Destination::new(s3.clone())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesystem::{FileSystem, MemoryFileSystem};
+
+    #[test]
+    fn local_directory_put() {
+        let fs = Arc::new(MemoryFileSystem::default());
+        filesystem::with(fs.clone(), || local_directory_put_in(fs.as_ref()));
+    }
+
+    fn local_directory_put_in(fs: &dyn FileSystem) {
+        let root = Path::new("wal-destination");
+        let source = root.join("source");
+        fs.create_dir_all(root).unwrap();
+        fs.write(&source, b"segment contents").unwrap();
+
+        let destination = LocalDirectory::new(root.join("archive"));
+        assert!(destination.list().unwrap().is_empty());
+
+        destination.put("000000010000000000000001", &source).unwrap();
+        assert!(!fs.exists(&root.join("archive/000000010000000000000001.tmp")));
+        assert_eq!(vec!["000000010000000000000001".to_string()], destination.list().unwrap());
+
+        let mut contents = Vec::new();
+        destination.get("000000010000000000000001").unwrap().read_to_end(&mut contents).unwrap();
+        assert_eq!(b"segment contents".to_vec(), contents);
+    }
+}
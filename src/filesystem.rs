@@ -0,0 +1,318 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::SystemTime;
+
+use crate::clock;
+
+/// The file operations of the services, so that they can run against the
+/// real file system as well as against an in-memory one.
+pub trait FileSystem: Send + Sync {
+    /// Opens the file for reading.
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>>;
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    /// Creates the file, or replaces its contents.
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()>;
+
+    /// Appends to the file, creating it if need be.
+    fn append(&self, path: &Path, contents: &[u8]) -> io::Result<()>;
+
+    /// Copies the contents of a file to another one, returning their size.
+    fn copy(&self, from: &Path, to: &Path) -> io::Result<u64> {
+        let contents = self.read(from)?;
+        self.write(to, &contents)?;
+        Ok(contents.len() as u64)
+    }
+
+    /// Renames the file, replacing the destination if it exists.
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    /// Removes the file.
+    fn remove(&self, path: &Path) -> io::Result<()>;
+
+    /// Removes the directory along with its contents.
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    /// Lists the files of the directory, leaving out its subdirectories.
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>>;
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    /// Whether the file or the directory exists.
+    fn exists(&self, path: &Path) -> bool;
+
+    /// The size of the file in bytes.
+    fn size(&self, path: &Path) -> io::Result<u64>;
+
+    /// When the file was last written to.
+    fn modified(&self, path: &Path) -> io::Result<SystemTime>;
+
+    /// Flushes the file or the directory to durable storage.
+    fn fsync(&self, path: &Path) -> io::Result<()>;
+}
+
+/// The file system of the operating system.
+pub struct RealFileSystem;
+
+impl FileSystem for RealFileSystem {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>> {
+        Ok(Box::new(File::open(path)?))
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        fs::write(path, contents)
+    }
+
+    fn append(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        OpenOptions::new().create(true).append(true).open(path)?.write_all(contents)
+    }
+
+    fn copy(&self, from: &Path, to: &Path) -> io::Result<u64> {
+        fs::copy(from, to)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::remove_dir_all(path)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            match entry.metadata() {
+                Ok(metadata) if metadata.is_file() => files.push(entry.path()),
+                Ok(_) => {},
+                // the file got removed or renamed since the directory was listed.
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {},
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(files)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn size(&self, path: &Path) -> io::Result<u64> {
+        Ok(fs::metadata(path)?.len())
+    }
+
+    fn modified(&self, path: &Path) -> io::Result<SystemTime> {
+        fs::metadata(path)?.modified()
+    }
+
+    fn fsync(&self, path: &Path) -> io::Result<()> {
+        File::open(path)?.sync_all()
+    }
+}
+
+#[derive(Default)]
+struct MemoryState {
+    files: BTreeMap<PathBuf, Vec<u8>>,
+    dirs: BTreeSet<PathBuf>,
+
+    /// When each file was last written to, according to the clock in effect.
+    modified: BTreeMap<PathBuf, SystemTime>,
+}
+
+impl MemoryState {
+    /// Files can only be created in existing directories, the working
+    /// directory always exists.
+    fn check_parent(&self, path: &Path) -> io::Result<()> {
+        match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() && !self.dirs.contains(parent) => {
+                Err(not_found(parent))
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("{:?} does not exist", path))
+}
+
+/// A file system held in memory, which starts out empty. Nothing is ever
+/// lost, so fsync is a no-op.
+#[derive(Default)]
+pub struct MemoryFileSystem {
+    state: Mutex<MemoryState>,
+}
+
+impl MemoryFileSystem {
+    fn state(&self) -> std::sync::MutexGuard<'_, MemoryState> {
+        self.state.lock().expect("the memory file system is poisoned")
+    }
+}
+
+impl FileSystem for MemoryFileSystem {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>> {
+        Ok(Box::new(Cursor::new(self.read(path)?)))
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.state().files.get(path).cloned().ok_or_else(|| not_found(path))
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        let mut state = self.state();
+        state.check_parent(path)?;
+        state.files.insert(path.to_path_buf(), contents.to_vec());
+        state.modified.insert(path.to_path_buf(), clock::current().system_time());
+        Ok(())
+    }
+
+    fn append(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        let mut state = self.state();
+        state.check_parent(path)?;
+        state.files.entry(path.to_path_buf()).or_default().extend_from_slice(contents);
+        state.modified.insert(path.to_path_buf(), clock::current().system_time());
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut state = self.state();
+        state.check_parent(to)?;
+        let contents = state.files.remove(from).ok_or_else(|| not_found(from))?;
+        state.files.insert(to.to_path_buf(), contents);
+        if let Some(modified) = state.modified.remove(from) {
+            state.modified.insert(to.to_path_buf(), modified);
+        }
+        Ok(())
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        let mut state = self.state();
+        state.modified.remove(path);
+        state.files.remove(path).map(|_| ()).ok_or_else(|| not_found(path))
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        let mut state = self.state();
+        if !state.dirs.contains(path) {
+            return Err(not_found(path));
+        }
+        state.files.retain(|f, _| !f.starts_with(path));
+        state.modified.retain(|f, _| !f.starts_with(path));
+        state.dirs.retain(|d| !d.starts_with(path));
+        Ok(())
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let state = self.state();
+        if !state.dirs.contains(path) {
+            return Err(not_found(path));
+        }
+        Ok(state.files.keys().filter(|f| f.parent() == Some(path)).cloned().collect())
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        let mut state = self.state();
+        for dir in path.ancestors().filter(|d| !d.as_os_str().is_empty()) {
+            if state.files.contains_key(dir) {
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{:?} is a file", dir)));
+            }
+            state.dirs.insert(dir.to_path_buf());
+        }
+        Ok(())
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        let state = self.state();
+        state.files.contains_key(path) || state.dirs.contains(path)
+    }
+
+    fn size(&self, path: &Path) -> io::Result<u64> {
+        self.state().files.get(path).map(|f| f.len() as u64).ok_or_else(|| not_found(path))
+    }
+
+    fn modified(&self, path: &Path) -> io::Result<SystemTime> {
+        self.state().modified.get(path).copied().ok_or_else(|| not_found(path))
+    }
+
+    fn fsync(&self, path: &Path) -> io::Result<()> {
+        if self.exists(path) {
+            Ok(())
+        } else {
+            Err(not_found(path))
+        }
+    }
+}
+
+/// The file system in effect, set once at startup.
+static FILE_SYSTEM: OnceLock<Arc<dyn FileSystem>> = OnceLock::new();
+
+thread_local! {
+    /// Takes over the file system in effect on the current thread, see `with`.
+    static OVERRIDE: RefCell<Option<Arc<dyn FileSystem>>> = const { RefCell::new(None) };
+}
+
+/// Returns the file system in effect, the real one unless `install` was called.
+pub fn current() -> Arc<dyn FileSystem> {
+    OVERRIDE.with(|o| o.borrow().clone())
+        .unwrap_or_else(|| FILE_SYSTEM.get_or_init(|| Arc::new(RealFileSystem)).clone())
+}
+
+/// Sets the file system used by every service, it can only be set before first use.
+pub fn install(file_system: Arc<dyn FileSystem>) {
+    if FILE_SYSTEM.set(file_system).is_err() {
+        println!("The file system is already in use, ignoring the configured one.");
+    }
+}
+
+/// Runs `f` against the given file system, on the current thread and the
+/// service threads it starts with `utilities::spawn`.
+pub fn with<R>(file_system: Arc<dyn FileSystem>, f: impl FnOnce() -> R) -> R {
+    let previous = OVERRIDE.with(|o| o.replace(Some(file_system)));
+    let result = f();
+    OVERRIDE.with(|o| *o.borrow_mut() = previous);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_file_system() {
+        let fs = MemoryFileSystem::default();
+        let ready = Path::new("file-source/file-status/000000010000000000000001.ready");
+        assert_eq!(io::ErrorKind::NotFound, fs.write(ready, b"{}").unwrap_err().kind());
+
+        fs.create_dir_all(Path::new("file-source/file-status")).unwrap();
+        fs.write(ready, b"{}").unwrap();
+        fs.append(Path::new("file-source/reconciliation.log"), b"a").unwrap();
+        fs.append(Path::new("file-source/reconciliation.log"), b"b").unwrap();
+        assert_eq!(b"ab".to_vec(), fs.read(Path::new("file-source/reconciliation.log")).unwrap());
+        assert_eq!(vec![PathBuf::from("file-source/reconciliation.log")], fs.read_dir(Path::new("file-source")).unwrap());
+
+        let done = ready.with_extension("done");
+        fs.rename(ready, &done).unwrap();
+        assert!(!fs.exists(ready) && fs.exists(&done));
+        assert_eq!(2, fs.copy(&done, ready).unwrap());
+        fs.remove(&done).unwrap();
+        assert_eq!(vec![ready.to_path_buf()], fs.read_dir(Path::new("file-source/file-status")).unwrap());
+    }
+}
@@ -0,0 +1,98 @@
+use std::fmt;
+use std::io;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+
+/// The encodings the status files and the configuration files can be written
+/// in. Readers tell them apart by their first byte, so that a status directory
+/// can hold several of them while it is being converted.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Format {
+    #[default]
+    Json,
+    #[serde(rename = "msgpack")]
+    MessagePack,
+    Cbor,
+}
+
+impl Format {
+    pub const ALL: [Format; 3] = [Format::Json, Format::MessagePack, Format::Cbor];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::MessagePack => "msgpack",
+            Format::Cbor => "cbor",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Format> {
+        Format::ALL.into_iter().find(|f| f.name() == name)
+    }
+
+    /// Detects the format of an encoded document, which is always a map.
+    /// JSON starts with `{` after any whitespace, MessagePack with one of its
+    /// map markers (0x80-0x8f, 0xde, 0xdf) and CBOR with major type 5
+    /// (0xa0-0xbb, 0xbf).
+    pub fn detect(bytes: &[u8]) -> Option<Format> {
+        match bytes.iter().find(|b| !b.is_ascii_whitespace())? {
+            b'{' => Some(Format::Json),
+            0x80..=0x8f | 0xde | 0xdf => Some(Format::MessagePack),
+            0xa0..=0xbb | 0xbf => Some(Format::Cbor),
+            _ => None,
+        }
+    }
+
+    pub fn encode<T: Serialize>(&self, value: &T) -> io::Result<Vec<u8>> {
+        let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidData, e);
+        match self {
+            Format::Json => serde_json::to_vec(value).map_err(|e| invalid(e.to_string())),
+            // structs are written as maps, so that they decode like JSON objects.
+            Format::MessagePack => rmp_serde::to_vec_named(value).map_err(|e| invalid(e.to_string())),
+            Format::Cbor => {
+                let mut buffer = Vec::new();
+                ciborium::into_writer(value, &mut buffer).map_err(|e| invalid(e.to_string()))?;
+                Ok(buffer)
+            },
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Decodes a document of any format into a JSON value, which the status file
+/// migrations and the configuration layers work on.
+pub fn decode(bytes: &[u8]) -> Result<(Value, Format), String> {
+    let format = Format::detect(bytes).ok_or("unknown format, expected a JSON, MessagePack or CBOR map")?;
+    let value = match format {
+        Format::Json => serde_json::from_slice(bytes).map_err(|e| e.to_string())?,
+        Format::MessagePack => rmp_serde::from_slice(bytes).map_err(|e| e.to_string())?,
+        Format::Cbor => ciborium::from_reader(bytes).map_err(|e| e.to_string())?,
+    };
+    Ok((value, format))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn round_trip() {
+        let value = json!({ "action": { "Slow": { "factor": 2.5 } }, "duration": 10, "history": { "attempts": 1 }, "tags": ["a"] });
+        for format in Format::ALL {
+            let encoded = format.encode(&value).unwrap();
+            assert_eq!(Ok((value.clone(), format)), decode(&encoded), "{}", format);
+        }
+
+        assert_eq!(Some(Format::Json), Format::detect(b"\n  {}"));
+        assert!(decode(b"[1, 2]").is_err());
+        assert!(decode(b"").is_err());
+        assert_eq!(Some(Format::MessagePack), Format::from_name("msgpack"));
+    }
+}
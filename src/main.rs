@@ -1,25 +1,71 @@
 mod utilities;
+mod clock;
+mod commands;
+mod config;
+mod destination;
+mod filesystem;
+mod format;
 mod services;
+mod s3;
+mod spool;
+mod schema;
 mod wal;
 mod simulation;
+#[cfg(test)]
+mod testing;
 
-use crate::services::{consumer, generator, processor};
+use crate::commands::{archive_daemon, archive_push, bench_formats, convert, migrate, s3_server, simulate, status, sweep, verify};
+use crate::config::{ConfigArgs, LoadedConfig};
 
-fn main() {
-    let simulation_config = simulation::lib::SimulationConfig::get_simulation_config();
-
-    let ready_files = utilities::get_ready_files().unwrap();
-    let done_files = utilities::get_done_files().unwrap();
+const USAGE: &str = "usage: [--config <file>] [--set <key>=<value>]... \
+[simulate | sweep | archive-push | archive-daemon | status | verify | migrate | convert | bench-formats | s3-server | config show]";
 
-    println!("{:?}", ready_files);
-    println!("{:?}", done_files);
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (config_args, args) = match ConfigArgs::parse(&args) {
+        Ok(parsed) => parsed,
+        Err(message) => {
+            eprintln!("{}", message);
+            std::process::exit(2);
+        }
+    };
 
-    let gen_handle = generator::service_startup(&simulation_config);
-    gen_handle.join().unwrap();
+    let loaded = match LoadedConfig::load(&config_args, std::env::vars()) {
+        Ok(loaded) => loaded,
+        Err(message) => {
+            eprintln!("{}", message);
+            std::process::exit(1);
+        }
+    };
+    utilities::set_layout(loaded.config.layout.clone());
 
-    let proc_handle = processor::service_startup(&simulation_config);
-    proc_handle.join().unwrap();
+    let simulates = matches!(args.first().map(String::as_str), None | Some("simulate" | "sweep"));
+    if simulates {
+        if let Err(message) = loaded.require_simulation() {
+            eprintln!("{}", message);
+            std::process::exit(1);
+        }
+    }
 
-    let consumer_handle = consumer::service_startup(&simulation_config);
-    consumer_handle.join().unwrap();
+    let config = &loaded.config;
+    match args.first().map(String::as_str) {
+        Some("archive-push") => std::process::exit(archive_push::run(config, &args[1..])),
+        Some("archive-daemon") => std::process::exit(archive_daemon::run(config, &args[1..])),
+        Some("sweep") => std::process::exit(sweep::run(&config_args, &loaded, &args[1..])),
+        Some("status") => std::process::exit(status::run(&args[1..])),
+        Some("verify") => std::process::exit(verify::run(config, &args[1..])),
+        Some("migrate") => std::process::exit(migrate::run(&args[1..])),
+        Some("convert") => std::process::exit(convert::run(&args[1..])),
+        Some("bench-formats") => std::process::exit(bench_formats::run(&args[1..])),
+        Some("s3-server") => std::process::exit(s3_server::run(&args[1..])),
+        Some("config") if args.get(1).map(String::as_str) == Some("show") => {
+            std::process::exit(config::show(&loaded, &args[2..]))
+        },
+        Some("simulate") => std::process::exit(simulate::run(config, &args[1..])),
+        None => std::process::exit(simulate::run(config, &[])),
+        Some(_) => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    }
 }
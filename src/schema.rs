@@ -0,0 +1,148 @@
+use std::time::Duration;
+use serde_json::{json, Map, Value};
+
+use crate::simulation::duration;
+
+/// The version of the status files written by this build.
+pub const CURRENT_VERSION: u32 = 5;
+
+/// Upgrades the fields of a status file from one version to the next.
+type Migration = fn(&mut Map<String, Value>);
+
+/// The migration from version N to N + 1 is at index N - 1.
+const MIGRATIONS: [Migration; 4] = [v1_to_v2, v2_to_v3, v3_to_v4, v4_to_v5];
+
+/// Version 2 added the optional `segment_path`, `failures` and `archived`
+/// fields for archive-push and verify, which version 1 files do without.
+fn v1_to_v2(_: &mut Map<String, Value>) {}
+
+/// Version 3 added the optional `history` and more actions, which version 2
+/// files do without.
+fn v2_to_v3(_: &mut Map<String, Value>) {}
+
+/// Version 4 records the version, and counts the failed attempts in the
+/// history. Every failure of an older file was an attempt of its own.
+fn v3_to_v4(fields: &mut Map<String, Value>) {
+    let Some(failures) = fields.remove("failures") else {
+        return;
+    };
+
+    let history = fields.entry("history").or_insert_with(|| json!({}));
+    if let Some(history) = history.as_object_mut() {
+        history.entry("attempts").or_insert_with(|| failures.clone());
+        history.insert("failures".to_string(), failures);
+    }
+}
+
+/// Version 5 writes the duration as a duration string, older files count it
+/// in milliseconds.
+fn v4_to_v5(fields: &mut Map<String, Value>) {
+    if let Some(millis) = fields.get("duration").and_then(Value::as_u64) {
+        fields.insert("duration".to_string(), json!(duration::format_duration(&Duration::from_millis(millis))));
+    }
+}
+
+/// Returns the version of a status file. The ones written before the version
+/// got recorded are told apart by the fields introduced along the way.
+fn detect_version(fields: &Map<String, Value>) -> Result<u32, String> {
+    if let Some(version) = fields.get("version") {
+        return match version.as_u64() {
+            Some(v) if (1..=CURRENT_VERSION as u64).contains(&v) => Ok(v as u32),
+            Some(v) if v > CURRENT_VERSION as u64 => {
+                Err(format!("version {} is newer than the supported version {}", v, CURRENT_VERSION))
+            },
+            _ => Err(format!("invalid version {}", version)),
+        };
+    }
+
+    if fields.contains_key("history") {
+        Ok(3)
+    } else if ["segment_path", "failures", "archived"].iter().any(|f| fields.contains_key(*f)) {
+        Ok(2)
+    } else {
+        Ok(1)
+    }
+}
+
+/// Upgrades the fields of a status file to the current version, returning the
+/// version it was written with.
+pub fn migrate(value: &mut Value) -> Result<u32, String> {
+    let fields = value.as_object_mut().ok_or("a status file must be a JSON object")?;
+    let version = detect_version(fields)?;
+    for migration in MIGRATIONS[version as usize - 1..].iter() {
+        migration(fields);
+    }
+
+    fields.insert("version".to_string(), json!(CURRENT_VERSION));
+    Ok(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wal::{ErrorClass, WalAction, WalFile};
+
+    /// The status files as written by every historical version.
+    const FIXTURES: [(u32, &str); 5] = [
+        (1, include_str!("../fixtures/status/v1.json")),
+        (2, include_str!("../fixtures/status/v2.json")),
+        (3, include_str!("../fixtures/status/v3.json")),
+        (4, include_str!("../fixtures/status/v4.json")),
+        (5, include_str!("../fixtures/status/v5.json")),
+    ];
+
+    fn parse(version: u32) -> WalFile {
+        let (_, contents) = FIXTURES.iter().find(|(v, _)| *v == version).unwrap();
+        WalFile::parse(contents.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn detect_fixture_versions() {
+        for (version, contents) in FIXTURES {
+            let mut value: Value = serde_json::from_str(contents).unwrap();
+            assert_eq!(Ok(version), migrate(&mut value), "fixture of version {}", version);
+            assert_eq!(CURRENT_VERSION as u64, value["version"]);
+        }
+    }
+
+    #[test]
+    fn migrate_v1() {
+        let w = parse(1);
+        assert_eq!(WalAction::Fail { count: 5 }, w.action);
+        assert_eq!((Duration::from_millis(4094), 0, 0), (w.duration, w.history.attempts, w.history.failures));
+    }
+
+    #[test]
+    fn migrate_v2() {
+        let w = parse(2);
+        assert_eq!(Some("pg_wal/000000010000000000000003".to_string()), w.segment_path);
+        assert_eq!((2, 2), (w.history.attempts, w.history.failures));
+        assert_eq!(16, w.archived.unwrap().size);
+    }
+
+    #[test]
+    fn migrate_v3() {
+        let w = parse(3);
+        assert_eq!(WalAction::Timeout { count: 1 }, w.action);
+        assert_eq!((3, 2), (w.history.attempts, w.history.failures));
+        assert_eq!(ErrorClass::Retryable, w.history.last_error.unwrap().class);
+    }
+
+    #[test]
+    fn migrate_v4() {
+        let w = parse(4);
+        assert_eq!(Duration::from_millis(3), w.duration);
+        assert_eq!((3, 2, Some(1792378334240)), (w.history.attempts, w.history.failures, w.history.next_attempt_at));
+    }
+
+    #[test]
+    fn current_version() {
+        let w = parse(5);
+        assert_eq!(Duration::from_micros(2500), w.duration);
+        assert_eq!((1, 1, Some(1792378333050)), (w.history.attempts, w.history.failures, w.history.next_attempt_at));
+
+        let mut newer = json!({ "version": CURRENT_VERSION + 1, "action": "Success", "duration": "0s" });
+        assert!(migrate(&mut newer).unwrap_err().contains("newer"));
+        assert!(migrate(&mut json!([1, 2])).is_err());
+    }
+}
@@ -0,0 +1,255 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crate::commands::status::StatusReport;
+use crate::services::processor::ProcessorState;
+use crate::utilities;
+use crate::wal::ErrorClass;
+
+/// How long a client may take to send its request. Connections are served
+/// one at a time, so a stalled client must not hold up the others for long.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Configures the admin service.
+#[derive(Clone)]
+pub struct AdminConfig {
+    /// The address the HTTP server listens on, e.g. "127.0.0.1:8080".
+    pub address: String,
+
+    /// The service reports unhealthy once the oldest unarchived segment is
+    /// older than this many seconds.
+    pub max_archive_lag_secs: u64,
+}
+
+/// A response produced by one of the endpoints.
+#[derive(Debug, PartialEq)]
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn text(status: u16, body: &str) -> Self {
+        Response { status, content_type: "text/plain", body: format!("{}\n", body) }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            500 => "Internal Server Error",
+            _ => "Service Unavailable",
+        }
+    }
+}
+
+/// Returns the reason the services are unhealthy, if they are.
+fn unhealthy_reason(state: &ProcessorState, report: &StatusReport, config: &AdminConfig) -> Option<String> {
+    if !state.running.load(Ordering::SeqCst) {
+        return Some("processor is not running".to_string());
+    }
+
+    match &report.oldest_unarchived {
+        Some(oldest) if oldest.age_secs > config.max_archive_lag_secs => Some(format!(
+            "archive lag of {}s exceeds {}s, oldest unarchived segment is {}",
+            oldest.age_secs, config.max_archive_lag_secs, oldest.segment)),
+        _ => None,
+    }
+}
+
+/// Formats the metrics in the Prometheus text exposition format.
+fn metrics(state: &ProcessorState, report: &StatusReport) -> String {
+    let gauges = [
+        ("wal_processor_up", "Whether the processor thread is alive.", state.running.load(Ordering::SeqCst) as u64),
+        ("wal_processor_paused", "Whether the processor is paused.", state.paused.load(Ordering::SeqCst) as u64),
+        ("wal_segments_ready", "Segments waiting to be archived.", report.ready as u64),
+        ("wal_segments_in_progress", "Segments archived, not yet acknowledged.", report.in_progress as u64),
+        ("wal_segments_archived", "Segments archived and acknowledged.", report.archived as u64),
+        ("wal_segments_quarantined", "Segments which are not attempted anymore.", report.quarantined as u64),
+        ("wal_archive_lag_seconds", "Age of the oldest unarchived segment.",
+            report.oldest_unarchived.as_ref().map(|o| o.age_secs).unwrap_or(0)),
+    ];
+    let counters = [
+        ("wal_processor_iterations_total", "Processing iterations completed.", state.iterations.load(Ordering::SeqCst)),
+        ("wal_processor_archived_total", "Segments archived by the processor.", state.archived.load(Ordering::SeqCst)),
+        ("wal_processor_failures_total", "Failed archive attempts.", state.failures.load(Ordering::SeqCst)),
+    ];
+
+    let mut out = String::new();
+    for (kind, metrics) in [("gauge", &gauges[..]), ("counter", &counters[..])] {
+        for (name, help, value) in metrics {
+            out.push_str(&format!("# HELP {} {}\n# TYPE {} {}\n{} {}\n", name, help, name, kind, name, value));
+        }
+    }
+
+    let name = "wal_processor_failures_by_class_total";
+    out.push_str(&format!("# HELP {} Failed archive attempts by error class.\n# TYPE {} counter\n", name, name));
+    for (class, count) in ErrorClass::ALL.iter().zip(state.failures_by_class.iter()) {
+        out.push_str(&format!("{}{{class=\"{}\"}} {}\n", name, class, count.load(Ordering::SeqCst)));
+    }
+
+    out
+}
+
+/// Routes a request to its endpoint. The status report is only collected
+/// for the endpoints which need it.
+fn handle(method: &str, path: &str, state: &ProcessorState, config: &AdminConfig) -> Response {
+    let collect = || StatusReport::collect().map_err(|e| Response::text(500, &format!("failed to collect the status: {}", e)));
+
+    let response = match (method, path) {
+        ("GET", "/health") => collect().map(|report| match unhealthy_reason(state, &report, config) {
+            Some(reason) => Response::text(503, &reason),
+            None => Response::text(200, "healthy"),
+        }),
+        ("GET", "/ready") => Ok(if !state.running.load(Ordering::SeqCst) {
+            Response::text(503, "processor is not running")
+        } else if state.paused.load(Ordering::SeqCst) {
+            Response::text(503, "processor is paused")
+        } else {
+            Response::text(200, "ready")
+        }),
+        ("GET", "/status") => collect().map(|report| Response {
+            status: 200,
+            content_type: "application/json",
+            body: serde_json::to_string_pretty(&report).expect("failed to serialize the status report"),
+        }),
+        ("GET", "/metrics") => collect().map(|report| Response {
+            status: 200,
+            content_type: "text/plain; version=0.0.4",
+            body: metrics(state, &report),
+        }),
+        ("POST", "/pause") => {
+            state.paused.store(true, Ordering::SeqCst);
+            Ok(Response::text(200, "paused"))
+        },
+        ("POST", "/resume") => {
+            state.paused.store(false, Ordering::SeqCst);
+            Ok(Response::text(200, "resumed"))
+        },
+        (_, "/health" | "/ready" | "/status" | "/metrics" | "/pause" | "/resume") => {
+            Ok(Response::text(405, "method not allowed"))
+        },
+        _ => Ok(Response::text(404, "not found")),
+    };
+
+    response.unwrap_or_else(|error_response| error_response)
+}
+
+fn serve_connection(stream: TcpStream, state: &ProcessorState, config: &AdminConfig) -> io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    // the endpoints take no input, so the headers are only drained.
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let path = parts.next().unwrap_or_default();
+    let response = handle(method, path, state, config);
+
+    let mut stream = &stream;
+    write!(stream, "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status, response.reason(), response.content_type, response.body.len(), response.body)?;
+    stream.flush()
+}
+
+fn admin_server_internal(listener: TcpListener, state: Arc<ProcessorState>, config: AdminConfig) {
+    for stream in listener.incoming() {
+        let result = stream.and_then(|stream| serve_connection(stream, &state, &config));
+        if let Err(e) = result {
+            println!("Failed to serve an admin request: {}", e);
+        }
+    }
+}
+
+/// Starts the HTTP admin server, exposing the health, readiness, status and
+/// metrics of the processor and allowing to pause/resume it.
+pub fn service_startup(config: &AdminConfig, state: Arc<ProcessorState>) -> io::Result<JoinHandle<()>> {
+    let listener = TcpListener::bind(&config.address)?;
+    let config = config.clone();
+    Ok(utilities::spawn(move || {
+        admin_server_internal(listener, state, config);
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use crate::clock::{self, ManualClock};
+    use crate::filesystem::{self, FileSystem, MemoryFileSystem};
+    use crate::utilities;
+    use crate::wal::{WalAction, WalFile};
+
+    fn config() -> AdminConfig {
+        AdminConfig { address: "127.0.0.1:0".to_string(), max_archive_lag_secs: 60 }
+    }
+
+    #[test]
+    fn pause_and_resume() {
+        let state = ProcessorState::default();
+        state.running.store(true, Ordering::SeqCst);
+        assert_eq!(200, handle("GET", "/ready", &state, &config()).status);
+
+        assert_eq!(200, handle("POST", "/pause", &state, &config()).status);
+        assert!(state.paused.load(Ordering::SeqCst));
+        assert_eq!(503, handle("GET", "/ready", &state, &config()).status);
+
+        assert_eq!(200, handle("POST", "/resume", &state, &config()).status);
+        assert!(!state.paused.load(Ordering::SeqCst));
+        assert_eq!(200, handle("GET", "/ready", &state, &config()).status);
+
+        state.running.store(false, Ordering::SeqCst);
+        assert_eq!(503, handle("GET", "/ready", &state, &config()).status);
+    }
+
+    #[test]
+    fn unknown_routes() {
+        let state = ProcessorState::default();
+        assert_eq!(404, handle("GET", "/unknown", &state, &config()).status);
+        assert_eq!(405, handle("GET", "/pause", &state, &config()).status);
+        assert_eq!(405, handle("DELETE", "/status", &state, &config()).status);
+    }
+
+    #[test]
+    fn dead_processor_is_unhealthy() {
+        let fs = Arc::new(MemoryFileSystem::default());
+        fs.create_dir_all(Path::new(&utilities::layout().status_dir)).unwrap();
+        let state = ProcessorState::default();
+        let response = filesystem::with(fs, || handle("GET", "/health", &state, &config()));
+
+        assert_eq!(Response::text(503, "processor is not running"), response);
+    }
+
+    #[test]
+    fn archive_lag_over_the_limit_is_unhealthy() {
+        let fs = Arc::new(MemoryFileSystem::default());
+        let clock = Arc::new(ManualClock::new(1_700_000_000));
+        let state = ProcessorState::default();
+        state.running.store(true, Ordering::SeqCst);
+
+        let (healthy, lagging) = clock::with(clock.clone(), || filesystem::with(fs.clone(), || {
+            fs.create_dir_all(Path::new(&utilities::layout().status_dir)).unwrap();
+            WalFile::generate_wal_file(1, WalAction::Success, Duration::ZERO).flush_to_file().unwrap();
+            clock.advance(Duration::from_secs(60));
+            let healthy = handle("GET", "/health", &state, &config());
+            clock.advance(Duration::from_secs(1));
+            (healthy, handle("GET", "/health", &state, &config()))
+        }));
+
+        assert_eq!(200, healthy.status);
+        assert_eq!(Response::text(503,
+            "archive lag of 61s exceeds 60s, oldest unarchived segment is 000000010000000000000001"), lagging);
+    }
+}
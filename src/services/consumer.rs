@@ -1,14 +1,143 @@
-use std::time::Duration;
-use std::thread::{self, JoinHandle};
-use std::fs;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, UNIX_EPOCH};
+use std::thread::JoinHandle;
+use std::io;
+use std::path::Path;
+use serde::Serialize;
 
+use crate::clock;
+use crate::filesystem;
 use crate::simulation::lib::SimulationConfig;
+use crate::simulation::report::{EventKind, Recorder};
 use crate::utilities;
 use crate::wal::*;
 
-fn wal_consumer_internal(simulation_config: SimulationConfig) {
+/// The number of times a status file is re-read before the consumer decides
+/// how to reconcile it.
+const RECONCILE_ATTEMPTS: u32 = 3;
+
+/// How an inconsistent segment, listed as done by the processor while its
+/// status file does not say it succeeded, got reconciled.
+#[derive(Serialize, Debug)]
+enum Resolution {
+    /// Re-reading the status file showed the segment succeeded, the processor's
+    /// write was observed half way.
+    Acknowledged,
+
+    /// The status file still says it fails, so the stale .done marker is removed
+    /// and the segment is due to be attempted by the processor again.
+    Requeued,
+
+    /// The status file could not be read, the segment is reported and skipped.
+    Reported,
+}
+
+/// An entry of the reconciliation log.
+#[derive(Serialize)]
+struct ReconciliationEntry<'a> {
+    /// Seconds since the UNIX epoch.
+    at: u64,
+    segment: &'a str,
+    observed: String,
+    resolution: Resolution,
+}
+
+fn describe(wal_file: &io::Result<WalFile>) -> String {
+    match wal_file {
+        Ok(w) => format!("{:?}", w.action),
+        Err(e) => format!("unreadable: {}", e),
+    }
+}
+
+fn append_to_reconciliation_log(entry: &ReconciliationEntry) -> io::Result<()> {
+    let line = serde_json::to_string(entry).expect("failed to serialize the reconciliation entry");
+    filesystem::current().append(Path::new(&utilities::layout().reconciliation_log), format!("{}\n", line).as_bytes())
+}
+
+/// Removes the processor's .done marker of the segment.
+fn remove_marker(segment_name: &str) -> io::Result<()> {
+    filesystem::current().remove(Path::new(&format!("{}/{}.done", utilities::layout().source_dir, segment_name)))
+}
+
+/// Makes the segment due right away and removes the processor's marker, upon
+/// which the processor no longer counts it as processed.
+fn requeue(w: &mut WalFile, segment_name: &str) -> io::Result<()> {
+    w.history.next_attempt_at = None;
+    w.flush_to_file()?;
+    remove_marker(segment_name)
+}
+
+/// Turns the status file into a .done one and removes the processor's marker.
+/// A failure is left for the next pass of the consumer.
+fn acknowledge(w: &WalFile, segment_name: &str) -> io::Result<()> {
+    w.mark_done()?;
+    remove_marker(segment_name)
+}
+
+/// Reconciles a segment which has a .done marker from the processor, while its
+/// status file does not say it succeeded. The status file is re-read a few
+/// times, `retry_delay` apart, in case it is being written, then the segment
+/// is either acknowledged, requeued or reported. Returns the resolution, which
+/// is always recorded in the reconciliation log.
+fn reconcile(wal_file_path: &utilities::FileEntry, retry_delay: Duration, recorder: &Recorder) -> Resolution {
+    let mut wal_file = WalFile::try_read(&wal_file_path.full_path);
+    for _ in 1..RECONCILE_ATTEMPTS {
+        if let Ok(WalFile { action: WalAction::Success, .. }) = wal_file {
+            break;
+        }
+        clock::current().sleep(retry_delay);
+        wal_file = WalFile::try_read(&wal_file_path.full_path);
+    }
+
+    let observed = describe(&wal_file);
+    let resolution = match &mut wal_file {
+        Ok(w @ WalFile { action: WalAction::Success, .. }) => match acknowledge(w, &wal_file_path.file_name) {
+            Ok(()) => {
+                recorder.record(&wal_file_path.file_name, EventKind::Acknowledged);
+                Resolution::Acknowledged
+            },
+            Err(e) => {
+                println!("Failed to acknowledge {:?}: {}", wal_file_path.file_name, e);
+                Resolution::Reported
+            },
+        },
+        Ok(w) => match requeue(w, &wal_file_path.file_name) {
+            Ok(()) => Resolution::Requeued,
+            Err(e) => {
+                println!("Failed to requeue {:?}: {}", wal_file_path.file_name, e);
+                Resolution::Reported
+            },
+        },
+        Err(_) => Resolution::Reported,
+    };
+
+    println!("Reconciled {:?}: {:?}", wal_file_path.file_name, resolution);
+    let entry = ReconciliationEntry {
+        at: clock::current().system_time().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+        segment: &wal_file_path.file_name,
+        observed,
+        resolution,
+    };
+    if let Err(e) = append_to_reconciliation_log(&entry) {
+        println!("Failed to write the reconciliation log: {}", e);
+    }
+
+    entry.resolution
+}
+
+/// Acknowledges the segments the processor is done with, until `processed` is
+/// set and there are none left. Returns the number of segments it requeued to
+/// the processor.
+fn wal_consumer_internal(simulation_config: SimulationConfig, recorder: Arc<Recorder>, processed: Arc<AtomicBool>) -> usize {
+    // segments which could not be reconciled are skipped from then on.
+    let mut unresolved: HashSet<String> = HashSet::new();
+    let mut requeued = 0;
+    let clock = clock::current();
     loop {
-        thread::sleep(Duration::from_nanos(simulation_config.wal_consumer_delay));
+        clock.sleep(simulation_config.wal_consumer_delay);
+        let done = processed.load(Ordering::SeqCst);
         let done_files = utilities::get_done_files()
             .expect("failed to acquire .done files generated by the processor.")
             .into_iter()
@@ -17,48 +146,141 @@ fn wal_consumer_internal(simulation_config: SimulationConfig) {
             })
             .collect::<Vec<String>>(); 
 
-        let filter_fn = |file_name: &str| {
-            if !file_name.ends_with(".ready") {
-                return false;
-            }
-
-            let file_name = file_name.split(".")
-                .take(1)
-                .collect::<String>();
-            done_files.contains(&file_name)
+        let filter_fn = |file_name: &str| match file_name.strip_suffix(".ready") {
+            Some(file_name) => done_files.iter().any(|f| f == file_name) && !unresolved.contains(file_name),
+            None => false,
         };
 
-        let files_to_mark_done = utilities::walk_directory(utilities::STATUS_DIR, filter_fn)
+        let files_to_mark_done = utilities::walk_directory(&utilities::layout().status_dir, filter_fn)
             .expect("Failed to acquire WAL files to be marked as done");
-        if files_to_mark_done.len() == 0 {
+        if files_to_mark_done.is_empty() {
+            if !done {
+                continue;
+            }
             println!("No work to do for WAL consumer");
-            break;
+            return requeued;
         }
 
         for wal_file_path in files_to_mark_done {
-            let wal_file = WalFile::read(&wal_file_path.full_path);
-            match wal_file.action {
-                WalAction::Fail { count: _ } => {
-                    panic!("The WAL file was listed to mark as done but its contents are not succeeded: {:?}", wal_file_path);
+            match WalFile::try_read(&wal_file_path.full_path) {
+                Ok(wal_file @ WalFile { action: WalAction::Success, .. }) => {
+                    match acknowledge(&wal_file, &wal_file_path.file_name) {
+                        Ok(()) => recorder.record(&wal_file_path.file_name, EventKind::Acknowledged),
+                        Err(e) => println!("Failed to acknowledge {:?}: {}", wal_file_path.file_name, e),
+                    }
                 },
 
-                _ => {
-                    wal_file.mark_done().expect("Failed to mark the WAL file as done.");
-                    
-                    // remove the corresponding marker file from the source directory.
-                    fs::remove_file(format!("{}/{}.done", utilities::SOURCE_DIR, wal_file_path.file_name))
-                        .expect("Failed to remove a status file");
+                // a failed or unreadable status file with a .done marker is a race
+                // between the services, which is reconciled instead of being fatal.
+                _ => match reconcile(&wal_file_path, simulation_config.wal_consumer_delay, &recorder) {
+                    Resolution::Acknowledged => {},
+                    Resolution::Requeued => requeued += 1,
+                    Resolution::Reported => {
+                        unresolved.insert(wal_file_path.file_name);
+                    },
                 }
             }
         }
     }
 }
 
-pub fn service_startup(simulation_config: &SimulationConfig) -> JoinHandle<()> {
+/// Starts the consumer alongside the processor, it terminates once `processed`
+/// is set and there is nothing left to acknowledge. Its thread returns the
+/// number of segments it requeued to the processor.
+pub fn service_startup(simulation_config: &SimulationConfig, recorder: Arc<Recorder>, processed: Arc<AtomicBool>) -> JoinHandle<usize> {
     let x = simulation_config.clone();
-    let join_handle = thread::spawn(move || {
-        wal_consumer_internal(x);
+    let join_handle = utilities::spawn(move || {
+        wal_consumer_internal(x, recorder, processed)
     });
 
     join_handle
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ConfigArgs, LoadedConfig};
+    use crate::filesystem::{FileSystem, MemoryFileSystem};
+
+    const SEGMENT: &str = "000000010000000000000001";
+
+    /// Reconciles the segment whose status file holds `status`, along with the
+    /// processor's marker. Returns the resolution and the file system.
+    fn reconcile_status(status: &[u8]) -> (Resolution, Arc<MemoryFileSystem>) {
+        let fs = Arc::new(MemoryFileSystem::default());
+        let layout = utilities::layout();
+        fs.create_dir_all(Path::new(&layout.status_dir)).unwrap();
+        let ready_file = format!("{}/{}.ready", layout.status_dir, SEGMENT);
+        fs.write(Path::new(&ready_file), status).unwrap();
+        fs.write(Path::new(&format!("{}/{}.done", layout.source_dir, SEGMENT)), &[]).unwrap();
+
+        let entry = utilities::FileEntry::from_path(ready_file);
+        let resolution = filesystem::with(fs.clone(), || reconcile(&entry, Duration::ZERO, &Recorder::disabled()));
+        (resolution, fs)
+    }
+
+    fn status(action: WalAction) -> Vec<u8> {
+        serde_json::to_vec(&WalFile::simulated(SEGMENT, action, Duration::ZERO)).unwrap()
+    }
+
+    fn logged(fs: &MemoryFileSystem) -> serde_json::Value {
+        let log = fs.read(Path::new(&utilities::layout().reconciliation_log)).unwrap();
+        serde_json::from_slice(&log).unwrap()
+    }
+
+    fn exists(fs: &MemoryFileSystem, dir: &str, extension: &str) -> bool {
+        fs.exists(Path::new(&format!("{}/{}.{}", dir, SEGMENT, extension)))
+    }
+
+    #[test]
+    fn acknowledged() {
+        let (resolution, fs) = reconcile_status(&status(WalAction::Success));
+        let layout = utilities::layout();
+
+        assert!(matches!(resolution, Resolution::Acknowledged));
+        assert!(exists(&fs, &layout.status_dir, "done"));
+        assert!(!exists(&fs, &layout.source_dir, "done"));
+        assert_eq!(("Success", "Acknowledged"), (logged(&fs)["observed"].as_str().unwrap(), logged(&fs)["resolution"].as_str().unwrap()));
+    }
+
+    #[test]
+    fn requeued() {
+        let (resolution, fs) = reconcile_status(&status(WalAction::Fail { count: 1 }));
+        let layout = utilities::layout();
+
+        assert!(matches!(resolution, Resolution::Requeued));
+        assert!(exists(&fs, &layout.status_dir, "ready"));
+        assert!(!exists(&fs, &layout.source_dir, "done"));
+        assert_eq!(("Fail { count: 1 }", "Requeued"), (logged(&fs)["observed"].as_str().unwrap(), logged(&fs)["resolution"].as_str().unwrap()));
+    }
+
+    #[test]
+    fn reported() {
+        let (resolution, fs) = reconcile_status(b"not a status file");
+        let layout = utilities::layout();
+
+        assert!(matches!(resolution, Resolution::Reported));
+        assert!(exists(&fs, &layout.status_dir, "ready"));
+        assert!(exists(&fs, &layout.source_dir, "done"));
+        assert!(logged(&fs)["observed"].as_str().unwrap().starts_with("unreadable"));
+        assert_eq!("Reported", logged(&fs)["resolution"]);
+    }
+
+    #[test]
+    fn service_thread_uses_the_file_system() {
+        let fs = Arc::new(MemoryFileSystem::default());
+        let layout = utilities::layout();
+        fs.create_dir_all(Path::new(&layout.status_dir)).unwrap();
+        fs.write(Path::new(&format!("{}/{}.ready", layout.status_dir, SEGMENT)), &status(WalAction::Success)).unwrap();
+        fs.write(Path::new(&format!("{}/{}.done", layout.source_dir, SEGMENT)), &[]).unwrap();
+
+        let simulation_config = LoadedConfig::load(&ConfigArgs::default(), std::iter::empty()).unwrap().config.simulation;
+        let requeued = filesystem::with(fs.clone(), || {
+            service_startup(&simulation_config, Arc::new(Recorder::disabled()), Arc::new(AtomicBool::new(true))).join().unwrap()
+        });
+
+        assert_eq!(0, requeued);
+        assert!(exists(&fs, &layout.status_dir, "done"));
+        assert!(!exists(&fs, &layout.source_dir, "done"));
+    }
+}
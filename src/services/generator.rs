@@ -1,34 +1,115 @@
-use std::thread::{self, JoinHandle};
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread::JoinHandle;
 use std::time::Duration;
 use rand::prelude::*;
 
-use crate::simulation::lib::SimulationConfig;
+use crate::clock;
+use crate::simulation::arrivals::{self, Replay};
+use crate::simulation::distribution::FailureChain;
+use crate::simulation::lib::{RngStream, SimulationConfig};
+use crate::simulation::payload::{self, PayloadKind};
+use crate::simulation::report::{EventKind, Recorder};
+use crate::simulation::scenario::Scenario;
+use crate::utilities;
 use crate::wal::{WalAction, WalFile};
 
-fn file_generator_internal(simulation_config: SimulationConfig) {
+/// The delay before the generator retries a failed write.
+const WRITE_RETRY_DELAY: Duration = Duration::from_millis(10);
+
+/// Retries a write until it goes through, as PostgreSQL does not give up on
+/// its WAL either.
+fn retry_write(what: &str, mut write: impl FnMut() -> io::Result<()>) {
+    while let Err(e) = write() {
+        println!("Failed to write {}, retrying: {}", what, e);
+        clock::current().sleep(WRITE_RETRY_DELAY);
+    }
+}
+
+/// Writes the data file of the segment, ahead of its status file so that the
+/// processor never sees a ready segment without its data. Returns the number
+/// of bytes written.
+fn write_segment_data(simulation_config: &SimulationConfig, m: &WalFile, size: u64, segment_number: u64, rng: &mut impl Rng) -> u64 {
+    if simulation_config.wal_payload == PayloadKind::None || size == 0 {
+        return 0;
+    }
+
+    let path = Path::new(&utilities::layout().source_dir).join(m.segment_name());
+    retry_write("the data of a segment", || payload::write_payload(&path, size, simulation_config.wal_payload, segment_number, rng));
+    size
+}
+
+fn file_generator_internal(simulation_config: SimulationConfig, recorder: Arc<Recorder>, scenario: Arc<Scenario>) {
     let mut num_files_generated = 0;
-    let mut rng = simulation_config.rng.unwrap().clone();
+    let mut failure_rng = simulation_config.rng(RngStream::Failures);
+    let mut duration_rng = simulation_config.rng(RngStream::Durations);
+    let mut arrival_rng = simulation_config.rng(RngStream::Arrivals);
+    let mut payload_rng = simulation_config.rng(RngStream::Payloads);
+    let mut outcome_rng = simulation_config.rng(RngStream::Outcomes);
+    let mut failures = FailureChain::new(simulation_config.wal_failure_model.clone());
+    let clock = clock::current();
     while num_files_generated < simulation_config.num_wals_to_generate {
-        // decide on generated action:
-        let action = if rng.gen_bool(simulation_config.wal_failure_ratio) {
-            WalAction::Fail { count: rng.gen_range(simulation_config.wal_failure_attempt_min..simulation_config.wal_failure_attempt_max) }
+        // decide on generated action, a failure spike of the scenario takes over the failure model.
+        let fails = match scenario.failure_ratio() {
+            Some(failure_ratio) => failure_rng.gen_bool(failure_ratio),
+            None => failures.fails(&mut failure_rng),
+        };
+        let action = if fails {
+            let count = failure_rng.gen_range(simulation_config.wal_failure_attempt_min..=simulation_config.wal_failure_attempt_max);
+            simulation_config.wal_failure_kinds.action(count, &mut outcome_rng)
+        } else if outcome_rng.gen_bool(simulation_config.wal_slow_ratio) {
+            WalAction::Slow { factor: simulation_config.wal_slow_factor }
         } else {
             WalAction::Success
         };
 
-        let work_duration = rng.gen_range(
-            simulation_config.wal_process_duration_min..simulation_config.wal_process_duration_max);
+        let work_duration = simulation_config.wal_process_duration_distribution.sample(&mut duration_rng);
         let m = WalFile::generate_wal_file(num_files_generated, action, work_duration);
-        m.flush_to_file().expect("Failed to write a WAL file.");
-        thread::sleep(Duration::from_nanos(simulation_config.wal_generation_delay));
+        clock.sleep(scenario.disk_latency());
+        let size = write_segment_data(&simulation_config, &m, simulation_config.wal_segment_size, num_files_generated, &mut payload_rng);
+        retry_write("a WAL file", || m.flush_to_file());
+        recorder.record(&m.segment_name(), EventKind::Generated { size });
+        let delay = match scenario.generation_delay() {
+            Some(delay) => delay,
+            None => simulation_config.wal_generation_interval_distribution.sample(&mut arrival_rng),
+        };
+        clock.sleep(delay);
         num_files_generated += 1;
     }
 }
 
-pub fn service_startup(simulation_config: &SimulationConfig) -> JoinHandle<()> {
+/// Writes the segments of the trace as they become due. Their outcomes and
+/// sizes come from the trace, only the processing durations are drawn.
+fn trace_replay_internal(simulation_config: SimulationConfig, replay: Replay, recorder: Arc<Recorder>, scenario: Arc<Scenario>) {
+    let mut duration_rng = simulation_config.rng(RngStream::Durations);
+    let mut payload_rng = simulation_config.rng(RngStream::Payloads);
+    let clock = clock::current();
+    let started = clock.now();
+    let offsets = arrivals::replay_offsets(&replay.entries, replay.time_scale);
+    for (n, (entry, offset)) in replay.entries.iter().zip(offsets).enumerate() {
+        clock.sleep((started + offset).saturating_duration_since(clock.now()));
+
+        let work_duration = simulation_config.wal_process_duration_distribution.sample(&mut duration_rng);
+        let m = WalFile::simulated(&entry.segment, entry.action(), work_duration);
+        clock.sleep(scenario.disk_latency());
+        let size = write_segment_data(&simulation_config, &m, entry.size, n as u64, &mut payload_rng);
+        retry_write("a WAL file", || m.flush_to_file());
+        recorder.record(&entry.segment, EventKind::Generated { size });
+    }
+}
+
+/// Starts the generator, which replays the given arrival trace if any.
+pub fn service_startup(
+    simulation_config: &SimulationConfig,
+    replay: Option<Replay>,
+    recorder: Arc<Recorder>,
+    scenario: Arc<Scenario>,
+) -> JoinHandle<()> {
     let x = simulation_config.clone();
-    let join_handle = thread::spawn(move || {
-        file_generator_internal(x);
+    let join_handle = utilities::spawn(move || match replay {
+        Some(replay) => trace_replay_internal(x, replay, recorder, scenario),
+        None => file_generator_internal(x, recorder, scenario),
     });
 
     join_handle
@@ -1,3 +1,4 @@
+pub mod admin;
 pub mod generator;
 pub mod processor;
 pub mod consumer;
\ No newline at end of file
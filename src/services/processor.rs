@@ -1,13 +1,65 @@
 use std::ffi;
 use std::collections::{HashMap, HashSet};
+use std::io;
 use std::marker::PhantomData;
-use std::thread::{self, JoinHandle};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
-use crate::simulation::lib::SimulationConfig;
+use crate::clock;
+use crate::config::{Config, ProcessorConfig, RetryPolicy};
+use crate::destination::{self, Destination};
+use crate::filesystem;
+use crate::simulation::arrivals::{self, Outcome, TraceEntry, TraceWriter};
+use crate::simulation::report::{EventKind, Recorder};
+use crate::simulation::scenario::{Scenario, ScenarioDestination};
+use crate::spool;
 use crate::utilities::{self, FileEntry};
-use crate::wal::{WalAction, WalFile};
+use crate::wal::{ArchivedSegment, ErrorClass, FailureReason, WalAction, WalFile};
+
+/// The amount of time the processor waits before looking for new ready files
+/// when there was nothing to process.
+const DAEMON_IDLE_DELAY: Duration = Duration::from_millis(100);
+
+/// The state of a running processor, shared with the admin service which
+/// reports on it and pauses/resumes it.
+#[derive(Default)]
+pub struct ProcessorState {
+    /// Set while the processor thread is alive, cleared even if it panics.
+    pub running: AtomicBool,
+
+    /// When set, the processor does not pick up new ready files.
+    pub paused: AtomicBool,
+
+    /// The number of processing iterations completed.
+    pub iterations: AtomicU64,
+
+    /// The number of segments archived successfully.
+    pub archived: AtomicU64,
+
+    /// The number of failed archive attempts.
+    pub failures: AtomicU64,
+
+    /// The number of failed archive attempts of each error class, indexed
+    /// like `ErrorClass::ALL`.
+    pub failures_by_class: [AtomicU64; 3],
+}
+
+/// Clears `ProcessorState::running` when the processor thread exits, including
+/// when it unwinds due to a panic.
+struct RunningGuard(Arc<ProcessorState>);
+
+impl Drop for RunningGuard {
+    fn drop(&mut self) {
+        self.0.running.store(false, Ordering::SeqCst);
+    }
+}
 
 /// This metadata is maintained by the main proccessor, and not thread safe.
+#[allow(dead_code)]
 struct Metadata {
     /// the first file that processing error has occured,
     /// there is no need to process files that are earlier than this file.
@@ -28,14 +80,16 @@ struct Metadata {
 }
 
 /// Generated by the closure that is given to the thread pool. 
-enum WalResult {
-    /// Generated When the processing has failed.
-    Fail,
+pub(crate) enum WalResult {
+    /// Generated When the processing has failed, along with the reason of
+    /// the failure and the delay before the next attempt, if there is one.
+    Fail { wal_name: String, reason: FailureReason, retry_in: Option<Duration> },
 
     /// The string signifies the WAL file name.
     Success(String)
 }
 
+#[allow(dead_code)]
 impl Metadata {
     fn new(capacity: usize) -> Self {
         Metadata {
@@ -53,7 +107,7 @@ impl Metadata {
 fn generate_processed_wal_files() -> HashSet<String> {
     let mut processed_wals:  HashSet<String> = std::collections::HashSet::new();
 
-    utilities::walk_directory(utilities::SOURCE_DIR, |status_file| {
+    utilities::walk_directory(&utilities::layout().source_dir, |status_file| {
         status_file.ends_with(".done")
     })
         .expect("Failed to acquire status files")
@@ -65,61 +119,568 @@ fn generate_processed_wal_files() -> HashSet<String> {
     processed_wals
 }
 
-fn wal_processor_internal(sim_config: SimulationConfig) {
+/// Whether the processor's .done marker of the WAL file exists. The consumer
+/// removes it once the WAL file is acknowledged, or to have it attempted again.
+fn has_marker(wal_name: &str) -> bool {
+    filesystem::current().exists(Path::new(&format!("{}/{}.done", utilities::layout().source_dir, wal_name)))
+}
+
+/// Stores the segment's data in the destination, and returns its size and
+/// digest so that the archive can be verified later on.
+fn archive_payload(w: &WalFile, payload_path: &Path, destination: &dyn Destination) -> io::Result<ArchivedSegment> {
+    let (size, sha256) = utilities::sha256_digest(filesystem::current().open(payload_path)?)?;
+    destination.put(&w.segment_name(), payload_path)?;
+    Ok(ArchivedSegment { size, sha256 })
+}
+
+/// How the copy of a corrupt or partial upload differs from the segment.
+#[derive(Clone, Copy)]
+enum Damage {
+    /// The first byte is flipped.
+    Corrupt,
+
+    /// Only the first half is there.
+    Truncated,
+}
+
+/// Leaves a damaged copy of the segment's data in the destination, as a
+/// corrupt or partial upload does, for verify to catch unless an attempt
+/// replaces it.
+fn upload_damaged(w: &WalFile, payload_path: &Path, destination: &dyn Destination, damage: Damage) -> io::Result<()> {
+    let fs = filesystem::current();
+    let mut data = fs.read(payload_path)?;
+    match damage {
+        Damage::Corrupt => if let Some(byte) = data.first_mut() {
+            *byte ^= 0xFF;
+        },
+        Damage::Truncated => data.truncate(data.len() / 2),
+    }
+
+    let damaged_path = payload_path.with_extension("damaged");
+    fs.write(&damaged_path, &data)?;
+    let uploaded = destination.put(&w.segment_name(), &damaged_path);
+    fs.remove(&damaged_path)?;
+    uploaded
+}
+
+/// Converts the start of an attempt into a timestamp of the status file.
+fn started_at_millis(started: Instant) -> u64 {
+    clock::now_millis().saturating_sub(clock::current().elapsed(started).as_millis() as u64)
+}
+
+/// Records a failed attempt, which started at `started`, in the WAL file and
+/// in the error spool, along with when the WAL file is due to be attempted
+/// again according to the retry policy.
+fn record_failure(w: &mut WalFile, ready_file: &FileEntry, started: Instant, mut reason: FailureReason, policy: &RetryPolicy) -> WalResult {
+    w.history.failures += 1;
+    reason.attempt = w.history.failures;
+    reason.elapsed = Duration::from_micros(clock::current().elapsed(started).as_micros() as u64);
+    let retry_in = retry_delay(policy, &reason);
+
+    w.history.record_attempt(started_at_millis(started));
+    w.history.next_attempt_at = retry_in.map(|delay| clock::now_millis() + delay.as_millis() as u64);
+    w.history.last_error = Some(reason.clone());
+    w.flush_to_file().expect("failed to flush after recording the failure");
+
+    spool::record_error(&ready_file.file_name, &reason.to_string())
+        .expect("Failed to record the failure in the error spool");
+    WalResult::Fail { wal_name: ready_file.file_name.clone(), reason, retry_in }
+}
+
+/// Records a failed attempt of a simulated action, which fails one time less
+/// from then on.
+fn record_simulated_failure(w: &mut WalFile, ready_file: &FileEntry, started: Instant, reason: FailureReason, policy: &RetryPolicy) -> WalResult {
+    w.decrement_failure_count().expect("Failed to decrement the failure count");
+    record_failure(w, ready_file, started, reason, policy)
+}
+
+/// Takes a single attempt at archiving the given ready file. On success the
+/// segment's data (if any) is stored in the destination and the .done marker
+/// is generated, otherwise the failure is recorded in the WAL file and in the
+/// error spool. This is shared between the processor service and the
+/// archive-push command.
+///
+/// The simulated actions fail in their own ways: a timeout takes the whole
+/// `attempt_timeout`, a corrupt or partial upload leaves a damaged copy in
+/// a destination which overwrites it later on and is retried right away, a
+/// permanent error is never retried and a panic unwinds to the caller, see
+/// `attempt_wal_file`.
+pub(crate) fn process_wal_file(ready_file: &FileEntry, destination: &dyn Destination, config: &ProcessorConfig) -> WalResult {
+    let attempt_timeout = config.attempt_timeout;
+    let policy = &config.retry;
+    let clock = clock::current();
+    let started = clock.now();
+    let mut w = WalFile::read(&ready_file.full_path);
+    let duration = w.duration;
+    clock.sleep(match w.action {
+        WalAction::Slow { factor } => duration.mul_f64(factor),
+        WalAction::Timeout { .. } => attempt_timeout,
+        WalAction::Partial { .. } => duration / 2,
+        _ => duration,
+    });
+
+    match w.action {
+        WalAction::Success | WalAction::Slow { .. } => {
+            if let Some(payload_path) = w.payload_path() {
+                match archive_payload(&w, &payload_path, destination) {
+                    Ok(archived) => w.archived = Some(archived),
+                    Err(e) => {
+                        // the next attempt is just as slow.
+                        println!("Failed to archive {:?}: {}", ready_file.file_name, e);
+                        let reason = FailureReason::new(ErrorClass::of(&e), e.to_string());
+                        return record_failure(&mut w, ready_file, started, reason, policy);
+                    }
+                }
+            }
+
+            // a slow upload still goes through, which the consumer learns from the status file.
+            let history = w.history.clone();
+            w.action = WalAction::Success;
+            w.history.record_attempt(started_at_millis(started));
+            w.history.next_attempt_at = None;
+            if let Err(e) = w.flush_to_file().and_then(|_| w.generate_done_file()) {
+                // the next attempt archives the segment again, and marks it as done.
+                println!("Failed to mark {:?} as done: {}", ready_file.file_name, e);
+                w.history = history;
+                let reason = FailureReason::new(ErrorClass::of(&e), format!("failed to mark the segment as done: {}", e));
+                return record_failure(&mut w, ready_file, started, reason, policy);
+            }
+
+            if let Err(e) = spool::clear_error(&ready_file.file_name) {
+                println!("Failed to clear the error spool of {:?}: {}", ready_file.file_name, e);
+            }
+            WalResult::Success(ready_file.file_name.clone())
+        },
+        WalAction::Fail { count } => {
+            let reason = FailureReason::new(ErrorClass::Retryable, format!("simulated failure, {} attempt(s) left", count));
+            record_simulated_failure(&mut w, ready_file, started, reason, policy)
+        },
+        WalAction::Timeout { .. } => {
+            let reason = FailureReason::new(ErrorClass::Retryable, format!("timed out after {:?}", attempt_timeout));
+            record_simulated_failure(&mut w, ready_file, started, reason, policy)
+        },
+        WalAction::Corrupt { .. } | WalAction::Partial { .. } => {
+            let (message, damage) = match w.action {
+                WalAction::Corrupt { .. } => ("the checksum of the uploaded copy does not match", Damage::Corrupt),
+                _ => ("the segment was only partially written", Damage::Truncated),
+            };
+            // a destination which keeps what it stored would keep the damaged copy for good.
+            if let Some(payload_path) = w.payload_path().filter(|_| destination.overwrites()) {
+                if let Err(e) = upload_damaged(&w, &payload_path, destination, damage) {
+                    println!("Failed to upload a damaged copy of {:?}: {}", ready_file.file_name, e);
+                }
+            }
+            let reason = FailureReason::new(ErrorClass::Retryable, message);
+            record_simulated_failure(&mut w, ready_file, started, reason.retry_after(Duration::ZERO), policy)
+        },
+        WalAction::PermanentError => {
+            let reason = FailureReason::new(ErrorClass::Permanent, "the destination rejected the segment permanently");
+            record_failure(&mut w, ready_file, started, reason, policy)
+        },
+        WalAction::Panic => panic!("simulated panic while archiving {:?}", ready_file.file_name),
+    }
+}
+
+/// Takes a single attempt like `process_wal_file`, failing it when it
+/// panics rather than unwinding to the caller.
+pub(crate) fn attempt_wal_file(ready_file: &FileEntry, destination: &dyn Destination, config: &ProcessorConfig) -> WalResult {
+    let started = clock::current().now();
+    panic::catch_unwind(AssertUnwindSafe(|| process_wal_file(ready_file, destination, config)))
+        .or_else(|_| panic::catch_unwind(AssertUnwindSafe(|| {
+            let mut w = WalFile::read(&ready_file.full_path);
+            let reason = FailureReason::new(ErrorClass::Unknown, "the attempt panicked");
+            record_simulated_failure(&mut w, ready_file, started, reason, &config.retry)
+        })))
+        .unwrap_or_else(|_| unrecorded_failure(ready_file, &config.retry))
+}
+
+/// Fails an attempt whose failure could not be recorded either, e.g. as the
+/// status file could not be read. The WAL file is attempted again after the
+/// initial backoff.
+fn unrecorded_failure(ready_file: &FileEntry, policy: &RetryPolicy) -> WalResult {
+    let reason = FailureReason::new(ErrorClass::Unknown, "the failure of the attempt could not be recorded");
+    WalResult::Fail { wal_name: ready_file.file_name.clone(), retry_in: retry_delay(policy, &reason), reason }
+}
+
+/// Returns the delay before the next attempt of a WAL file which failed for
+/// the given reason, or none once it is not worth attempting anymore.
+fn retry_delay(policy: &RetryPolicy, reason: &FailureReason) -> Option<Duration> {
+    let exhausted = policy.max_attempts > 0 && reason.attempt >= policy.max_attempts;
+    match reason.class {
+        ErrorClass::Permanent => None,
+        _ if exhausted => None,
+        ErrorClass::Retryable => Some(reason.retry_after.unwrap_or_else(|| policy.backoff(reason.attempt))),
+
+        // not knowing what went wrong, the backoff is never shortened.
+        ErrorClass::Unknown => Some(policy.backoff(reason.attempt)),
+    }
+}
+
+/// Reads from the status files when the failed WAL files are due to be
+/// attempted again, so that their backoffs survive restarts of the processor.
+fn persisted_backoffs() -> HashMap<String, Instant> {
+    let now = clock::current().now();
+    let now_millis = clock::now_millis();
+    utilities::get_ready_files()
+        .expect("The API to list ready files did not terminate correctly")
+        .into_iter()
+        .filter_map(|ready_file| {
+            let next_attempt_at = WalFile::try_read(&ready_file.full_path).ok()?.history.next_attempt_at?;
+            let delay = Duration::from_millis(next_attempt_at.checked_sub(now_millis)?);
+            Some((ready_file.file_name, now + delay))
+        })
+        .collect()
+}
+
+/// Moves the status file of a WAL file which exhausted its attempts into the
+/// quarantine directory, so that it is not attempted anymore.
+fn quarantine(wal_name: &str) -> io::Result<()> {
+    let layout = utilities::layout();
+    let fs = filesystem::current();
+    fs.create_dir_all(Path::new(&layout.quarantine_dir))?;
+    fs.rename(
+        Path::new(&format!("{}/{}.ready", layout.status_dir, wal_name)),
+        Path::new(&format!("{}/{}.ready", layout.quarantine_dir, wal_name)))
+}
+
+/// Appends a segment the processor is done with to the arrival trace, along
+/// with the reason it is quarantined for, if it is.
+fn record_arrival(trace_writer: &mut TraceWriter, wal_name: &str, first_seen_at: Option<f64>, quarantined: Option<&FailureReason>) -> io::Result<()> {
+    // the consumer may have acknowledged the segment already.
+    let status_dir = &utilities::layout().status_dir;
+    let status = WalFile::try_read(&format!("{}/{}.ready", status_dir, wal_name))
+        .or_else(|_| WalFile::try_read(&format!("{}/{}.done", status_dir, wal_name)));
+    let (failures, size) = match status {
+        Ok(w) => (w.history.failures, w.archived.map_or(0, |a| a.size)),
+        Err(_) => (0, 0),
+    };
+    let failures = failures.min(u8::MAX as u32) as u8;
+
+    trace_writer.append(&TraceEntry {
+        timestamp: first_seen_at.unwrap_or_else(arrivals::now_timestamp),
+        segment: wal_name.to_string(),
+        size,
+        outcome: match quarantined {
+            None => Outcome::Archived { failures },
+            Some(reason) if reason.class == ErrorClass::Permanent => Outcome::Permanent,
+            Some(_) => Outcome::Quarantined { failures },
+        },
+    })
+}
+
+/// Processes the ready files in iterations. The processor terminates once
+/// `upstream_done` is set, as no more files become ready, and there are no
+/// ready files left.
+fn wal_processor_internal(
+    config: Config,
+    destination: Arc<dyn Destination>,
+    state: Arc<ProcessorState>,
+    recorder: Arc<Recorder>,
+    scenario: Arc<Scenario>,
+    upstream_done: Arc<AtomicBool>,
+) {
+    let _running_guard = RunningGuard(state.clone());
+    let clock = clock::current();
+
+    let processor_config = Arc::new(config.processor.clone());
     let mut iteration_count = 0;
     let mut processed_wals = generate_processed_wal_files();
-    let thread_pool: utilities::ThreadPool<WalResult> = utilities::ThreadPool::new(5);
+
+    // the failed WAL files are not attempted again until their backoff elapses.
+    let mut next_attempt_at = persisted_backoffs();
+
+    // when the segments became ready, as far as the processor can tell, for the arrival trace.
+    let mut trace_writer = config.processor.trace_file.as_deref()
+        .map(TraceWriter::open)
+        .transpose()
+        .expect("Failed to open the arrival trace");
+    let mut first_seen_at: HashMap<String, f64> = HashMap::new();
+    let thread_pool: utilities::ThreadPool<WalResult> = utilities::ThreadPool::new(config.processor.threads);
+    let mut restarts = 0;
     loop {
-        let ready_files = utilities::get_ready_files()
+        // a restart of the scenario loses whatever the processor kept in memory,
+        // only what the status files tell is left.
+        let (restarts_due, downtime) = scenario.restarts_due(restarts);
+        if restarts_due > restarts {
+            println!("Restarting the processor, down for {:?}", downtime);
+            clock.sleep(downtime);
+            restarts = restarts_due;
+            processed_wals = generate_processed_wal_files();
+            next_attempt_at = persisted_backoffs();
+        }
+
+        if state.paused.load(Ordering::SeqCst) {
+            clock.sleep(DAEMON_IDLE_DELAY);
+            continue;
+        }
+
+        // a WAL file whose marker went away is either acknowledged, and not
+        // ready anymore, or requeued by the consumer.
+        processed_wals.retain(|wal_name| has_marker(wal_name));
+        let done = upstream_done.load(Ordering::SeqCst);
+        let pending_files = utilities::get_ready_files()
             .expect("The API to list ready files did not terminate correctly")
             .into_iter()
             .filter(|w| { !processed_wals.contains(&w.file_name) })
             .collect::<Vec<FileEntry>>();
-        if ready_files.len() == 0 {
-            println!("Cleared the WAL files with num iterations: [{}]", iteration_count);
-            break;
+        if pending_files.is_empty() {
+            if done {
+                println!("Cleared the WAL files with num iterations: [{}]", iteration_count);
+                recorder.record_workers(thread_pool.busy_times(), thread_pool.elapsed());
+                break;
+            }
+
+            clock.sleep(DAEMON_IDLE_DELAY);
+            continue;
+        }
+
+        if trace_writer.is_some() {
+            for w in pending_files.iter() {
+                first_seen_at.entry(w.file_name.clone()).or_insert_with(arrivals::now_timestamp);
+            }
+        }
+
+        let now = clock.now();
+        let ready_files = pending_files.into_iter()
+            .filter(|w| next_attempt_at.get(&w.file_name).is_none_or(|at| *at <= now))
+            .collect::<Vec<FileEntry>>();
+        if ready_files.is_empty() {
+            let earliest = next_attempt_at.values().min().copied().unwrap_or(now);
+            clock.sleep(earliest.saturating_duration_since(now).min(DAEMON_IDLE_DELAY));
+            continue;
         }
 
+        recorder.record_iteration(ready_files.len());
         for ready_file  in ready_files.iter() {
-            let file_name = ready_file.file_name.clone();
             let ready_file = ready_file.clone();
+            let destination = destination.clone();
+            let recorder = recorder.clone();
+            let scenario = scenario.clone();
+            let processor_config = processor_config.clone();
+            let clock = clock.clone();
+            recorder.record(&ready_file.file_name, EventKind::Queued);
             thread_pool.execute(move || {
-                let mut w = WalFile::read(&ready_file.full_path);
-                thread::sleep(std::time::Duration::from_millis(w.duration));
-                match w.action {
-                    WalAction::Success => {
-                        w.generate_done_file().expect("Failed to mark the file as done.");
-                        return WalResult::Success(file_name)
-                    },
-                    WalAction::Fail { count: _ } => {
-                        w.decrement_failure_count().expect("Failed to decrement the failure count");
-                        w.flush_to_file().expect("failed to flush after decrementing the failure count");
-                        return WalResult::Fail;
-                    }
+                let worker = utilities::current_worker_id();
+                let started = recorder.now();
+                clock.sleep(scenario.disk_latency());
+                // a panic fails the attempt, the worker carries on with the next job.
+                let result = attempt_wal_file(&ready_file, destination.as_ref(), &processor_config);
+                let failure = match &result {
+                    WalResult::Fail { reason, .. } => Some(reason.class),
+                    WalResult::Success(_) => None,
+                };
+                recorder.record(&ready_file.file_name, EventKind::Attempted { worker, started, failure });
+                if failure.is_none() {
+                    recorder.record(&ready_file.file_name, EventKind::MarkerWritten { worker });
                 }
+                result
             });
         }
 
         let processing_results = thread_pool.collect_results(ready_files.len());
         for result in processing_results {
             match result {
-                WalResult::Success(wal_name) =>  {
+                WalResult::Success(wal_name) => {
+                    if let Some(trace_writer) = trace_writer.as_mut() {
+                        record_arrival(trace_writer, &wal_name, first_seen_at.remove(&wal_name), None)
+                            .expect("Failed to record the arrival trace");
+                    }
+                    state.archived.fetch_add(1, Ordering::SeqCst);
+                    next_attempt_at.remove(&wal_name);
                     processed_wals.insert(wal_name);
                 },
-                _ => {}
+                WalResult::Fail { wal_name, reason, retry_in } => {
+                    state.failures.fetch_add(1, Ordering::SeqCst);
+                    let class = ErrorClass::ALL.iter().position(|c| *c == reason.class).expect("a known error class");
+                    state.failures_by_class[class].fetch_add(1, Ordering::SeqCst);
+                    if let Some(delay) = retry_in {
+                        next_attempt_at.insert(wal_name, clock.now() + delay);
+                    } else {
+                        println!("Quarantining {:?}, {}", wal_name, reason);
+                        if let Some(trace_writer) = trace_writer.as_mut() {
+                            record_arrival(trace_writer, &wal_name, first_seen_at.remove(&wal_name), Some(&reason))
+                                .expect("Failed to record the arrival trace");
+                        }
+                        match quarantine(&wal_name) {
+                            Ok(()) => {
+                                next_attempt_at.remove(&wal_name);
+                            },
+                            Err(e) => println!("Failed to quarantine {:?}, attempting it again: {}", wal_name, e),
+                        }
+                    }
+                }
             }
         }
 
         iteration_count += 1;
-        thread::sleep(std::time::Duration::from_nanos(sim_config.wal_processing_delay));
+        state.iterations.fetch_add(1, Ordering::SeqCst);
+        clock.sleep(config.simulation.wal_processing_delay);
     }
 }
 
-pub fn service_startup(sim_config: &SimulationConfig) -> JoinHandle<()> {
-    let s = sim_config.clone();
-    let join_handle = thread::spawn(move || {
-        wal_processor_internal(s);
+/// Starts the processor for a simulation run, alongside the generator. It
+/// terminates once `generated` is set and there are no ready files left. The
+/// destination goes down during the outages of the scenario.
+pub fn service_startup(config: &Config, recorder: Arc<Recorder>, scenario: Arc<Scenario>, generated: Arc<AtomicBool>) -> JoinHandle<()> {
+    let c = config.clone();
+    let destination = Arc::new(ScenarioDestination::new(destination::from_config(config), scenario.clone()));
+    let state = Arc::new(ProcessorState::default());
+    state.running.store(true, Ordering::SeqCst);
+    let join_handle = utilities::spawn(move || {
+        wal_processor_internal(c, destination, state, recorder, scenario, generated);
     });
 
     join_handle
-}
\ No newline at end of file
+}
+
+/// Starts the processor as a background daemon which keeps shipping segments
+/// as they become ready, ahead of the archive-push calls asking for them.
+/// The given state is updated as the daemon makes progress.
+pub fn daemon_startup(config: &Config, state: Arc<ProcessorState>) -> JoinHandle<()> {
+    let c = config.clone();
+    let destination = destination::from_config(config);
+    state.running.store(true, Ordering::SeqCst);
+    utilities::spawn(move || {
+        // nothing tells the daemon that no more segments become ready.
+        let upstream_done = Arc::new(AtomicBool::new(false));
+        wal_processor_internal(c, destination, state, Arc::new(Recorder::disabled()), Arc::new(Scenario::default()), upstream_done);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use crate::clock::{Clock, ManualClock};
+    use crate::config::{ConfigArgs, LoadedConfig};
+    use crate::simulation::scenario::Phase;
+    use crate::destination::LocalDirectory;
+    use crate::filesystem::{FileSystem, MemoryFileSystem};
+
+    #[test]
+    fn retry_delay_by_class() {
+        let policy = RetryPolicy { max_attempts: 3, initial_backoff: Duration::from_millis(100), max_backoff: Duration::from_secs(1) };
+        let reason = |class, attempt| FailureReason { attempt, ..FailureReason::new(class, "failed") };
+
+        assert_eq!(Some(Duration::from_millis(200)), retry_delay(&policy, &reason(ErrorClass::Retryable, 2)));
+        assert_eq!(Some(Duration::ZERO), retry_delay(&policy, &reason(ErrorClass::Retryable, 2).retry_after(Duration::ZERO)));
+        assert_eq!(Some(Duration::from_millis(200)), retry_delay(&policy, &reason(ErrorClass::Unknown, 2).retry_after(Duration::ZERO)));
+        assert_eq!(None, retry_delay(&policy, &reason(ErrorClass::Permanent, 1)));
+        assert_eq!(None, retry_delay(&policy, &reason(ErrorClass::Retryable, 3)));
+    }
+
+    #[test]
+    fn backoff_follows_the_clock() {
+        let fs = Arc::new(MemoryFileSystem::default());
+        fs.create_dir_all(Path::new(&utilities::layout().status_dir)).unwrap();
+        let manual = Arc::new(ManualClock::new(1_700_000_000));
+        filesystem::with(fs, || clock::with(manual.clone(), || backoff_follows_the_clock_in(&manual)));
+    }
+
+    fn backoff_follows_the_clock_in(manual: &ManualClock) {
+        let retry = RetryPolicy { max_attempts: 0, initial_backoff: Duration::from_secs(1), max_backoff: Duration::from_secs(60) };
+        let config = ProcessorConfig { retry, ..ProcessorConfig::default() };
+        let destination = LocalDirectory::new("wal-destination");
+        let w = WalFile::generate_wal_file(1, WalAction::Fail { count: 2 }, Duration::ZERO);
+        w.flush_to_file().unwrap();
+        let ready_file = FileEntry::from_path(w.file_name.clone());
+        let attempt = || match process_wal_file(&ready_file, &destination, &config) {
+            WalResult::Fail { retry_in, .. } => retry_in,
+            WalResult::Success(_) => panic!("the attempt was meant to fail"),
+        };
+
+        assert_eq!(Some(Duration::from_secs(1)), attempt());
+        assert_eq!(Some(1_700_000_001_000), WalFile::read(&w.file_name).history.next_attempt_at);
+        assert_eq!(Some(&(manual.now() + Duration::from_secs(1))), persisted_backoffs().get("000000010000000000000001"));
+
+        // the backoff survives a restart, counting down with the clock.
+        manual.advance(Duration::from_millis(600));
+        assert_eq!(Some(&(manual.now() + Duration::from_millis(400))), persisted_backoffs().get("000000010000000000000001"));
+
+        manual.advance(Duration::from_millis(400));
+        assert_eq!(Some(Duration::from_secs(2)), attempt());
+        let history = WalFile::read(&w.file_name).history;
+        assert_eq!((Some(1_700_000_000_000), Some(1_700_000_001_000)), (history.first_attempt_at, history.last_attempt_at));
+        assert_eq!(Some(1_700_000_003_000), history.next_attempt_at);
+    }
+
+    #[test]
+    fn failed_attempts() {
+        let fs = Arc::new(MemoryFileSystem::default());
+        let layout = utilities::layout();
+        for dir in [&layout.status_dir, &layout.source_dir] {
+            fs.create_dir_all(Path::new(dir)).unwrap();
+        }
+        filesystem::with(fs, failed_attempts_in);
+    }
+
+    fn failed_attempts_in() {
+        let config = ProcessorConfig::default();
+        let destination = LocalDirectory::new("wal-destination");
+        let payload_path = Path::new(&utilities::layout().source_dir).join("000000010000000000000001");
+        filesystem::current().write(&payload_path, b"segment data").unwrap();
+        let attempt = |action, destination: &dyn Destination| {
+            let w = WalFile::generate_wal_file(1, action, Duration::ZERO);
+            w.flush_to_file().unwrap();
+            let result = attempt_wal_file(&FileEntry::from_path(w.file_name.clone()), destination, &config);
+            (result, WalFile::read(&w.file_name))
+        };
+        let archived = || {
+            let mut data = Vec::new();
+            destination.get("000000010000000000000001").unwrap().read_to_end(&mut data).unwrap();
+            data
+        };
+
+        // a slow upload which fails is just as slow the next time.
+        let outage = Phase::DestinationOutage { from: Duration::ZERO, until: Duration::from_secs(60) };
+        let down = ScenarioDestination::new(Arc::new(LocalDirectory::new("wal-destination")), Arc::new(Scenario::new(vec![outage])));
+        let (result, w) = attempt(WalAction::Slow { factor: 2.0 }, &down);
+        assert!(matches!(result, WalResult::Fail { .. }));
+        assert_eq!(WalAction::Slow { factor: 2.0 }, w.action);
+
+        let (result, _) = attempt(WalAction::Corrupt { count: 1 }, &destination);
+        assert!(matches!(result, WalResult::Fail { .. }));
+        assert_eq!(b"\x8cegment data".to_vec(), archived());
+
+        attempt(WalAction::Partial { count: 1 }, &destination);
+        assert_eq!(b"segmen".to_vec(), archived());
+
+        let (result, w) = attempt(WalAction::Panic, &destination);
+        assert!(matches!(result, WalResult::Fail { ref reason, .. } if reason.message == "the attempt panicked"));
+        assert_eq!(1, w.history.failures);
+    }
+
+    #[test]
+    fn service_follows_the_clock() {
+        let fs = Arc::new(MemoryFileSystem::default());
+        let layout = utilities::layout();
+        for dir in [&layout.status_dir, &layout.source_dir] {
+            fs.create_dir_all(Path::new(dir)).unwrap();
+        }
+        let manual = Arc::new(ManualClock::new(1_700_000_000));
+        filesystem::with(fs, || clock::with(manual.clone(), || service_follows_the_clock_in(&manual)));
+    }
+
+    fn service_follows_the_clock_in(manual: &ManualClock) {
+        let mut config = LoadedConfig::load(&ConfigArgs::default(), std::iter::empty()).unwrap().config;
+        config.processor.retry = RetryPolicy { max_attempts: 0, initial_backoff: Duration::from_secs(30), max_backoff: Duration::from_secs(60) };
+        let w = WalFile::generate_wal_file(1, WalAction::Fail { count: 1 }, Duration::ZERO);
+        w.flush_to_file().unwrap();
+        filesystem::current().write(&Path::new(&utilities::layout().source_dir).join("000000010000000000000001"), b"segment data").unwrap();
+
+        let recorder = Arc::new(Recorder::new());
+        let handle = service_startup(&config, recorder.clone(), Arc::new(Scenario::default()), Arc::new(AtomicBool::new(true)));
+        while !handle.is_finished() {
+            manual.advance(Duration::from_millis(100));
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        handle.join().unwrap();
+
+        // the attempt after the backoff is only taken once the clock moved past it.
+        let w = WalFile::read(&w.file_name);
+        assert_eq!(WalAction::Success, w.action);
+        assert_eq!((2, 1), (w.history.attempts, w.history.failures));
+        assert!(w.history.last_attempt_at.unwrap() - w.history.first_attempt_at.unwrap() >= 30_000);
+        assert!(recorder.report().elapsed_secs >= 30.0);
+    }
+}
@@ -0,0 +1,297 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::clock;
+use crate::wal::{self, WalAction};
+
+const HEADER: &str = "timestamp,segment,size,outcome";
+
+/// How archiving a segment of an arrival trace went.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Outcome {
+    /// Archived after the given number of failed attempts, "success" or
+    /// "fail:N" in a trace.
+    Archived { failures: u8 },
+
+    /// Rejected by the destination for good, "permanent" in a trace.
+    Permanent,
+
+    /// Given up on after the given number of failed attempts,
+    /// "quarantined:N" in a trace.
+    Quarantined { failures: u8 },
+}
+
+impl Outcome {
+    fn parse(outcome: &str) -> Result<Self, String> {
+        let count = |count: &str| count.parse().map_err(|_| format!("invalid number of failures {:?}", count));
+        if let Some(failures) = outcome.strip_prefix("fail:") {
+            return Ok(Outcome::Archived { failures: count(failures)? });
+        }
+        if let Some(failures) = outcome.strip_prefix("quarantined:") {
+            return Ok(Outcome::Quarantined { failures: count(failures)? });
+        }
+
+        match outcome {
+            "success" => Ok(Outcome::Archived { failures: 0 }),
+            "permanent" => Ok(Outcome::Permanent),
+            _ => Err(format!("invalid outcome {:?}, expected success, fail:<n>, permanent or quarantined:<n>", outcome)),
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Archived { failures: 0 } => write!(f, "success"),
+            Outcome::Archived { failures } => write!(f, "fail:{}", failures),
+            Outcome::Permanent => write!(f, "permanent"),
+            Outcome::Quarantined { failures } => write!(f, "quarantined:{}", failures),
+        }
+    }
+}
+
+/// A segment of an arrival trace: when it became ready, its size in bytes and
+/// how archiving it went.
+#[derive(Debug, PartialEq)]
+pub struct TraceEntry {
+    /// Seconds since the UNIX epoch, or since any other fixed point in time.
+    pub timestamp: f64,
+    pub segment: String,
+    pub size: u64,
+    pub outcome: Outcome,
+}
+
+impl TraceEntry {
+    /// Parses a `timestamp,segment,size,outcome` line. The segment must be
+    /// named like a WAL segment, as the name makes up paths.
+    fn parse(line: &str) -> Result<Self, String> {
+        let fields = line.split(',').map(str::trim).collect::<Vec<&str>>();
+        let [timestamp, segment, size, outcome] = fields[..] else {
+            return Err(format!("expected {}, got {:?}", HEADER, line));
+        };
+        if wal::parse_segment_name(segment).is_none() {
+            return Err(format!("invalid segment name {:?}, expected 24 hexadecimal digits", segment));
+        }
+
+        Ok(TraceEntry {
+            timestamp: timestamp.parse().map_err(|_| format!("invalid timestamp {:?}", timestamp))?,
+            segment: segment.to_string(),
+            size: size.parse().map_err(|_| format!("invalid size {:?}", size))?,
+            outcome: Outcome::parse(outcome)?,
+        })
+    }
+
+    fn to_line(&self) -> String {
+        format!("{:.6},{},{},{}", self.timestamp, self.segment, self.size, self.outcome)
+    }
+
+    /// The action the generator gives the replayed segment. A quarantined
+    /// segment fails as many times as an action allows, so that the retry
+    /// policy gives up on it again unless it allows more attempts than that.
+    pub fn action(&self) -> WalAction {
+        match self.outcome {
+            Outcome::Archived { failures: 0 } => WalAction::Success,
+            Outcome::Archived { failures } => WalAction::Fail { count: failures },
+            Outcome::Permanent => WalAction::PermanentError,
+            Outcome::Quarantined { .. } => WalAction::Fail { count: u8::MAX },
+        }
+    }
+}
+
+/// Reads an arrival trace, ordered by timestamp. The header line is optional.
+pub fn read_trace(path: &str) -> Result<Vec<TraceEntry>, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("failed to read the trace {:?}: {}", path, e))?;
+    let mut entries = Vec::new();
+    for (n, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() || (n == 0 && line.trim() == HEADER) {
+            continue;
+        }
+        entries.push(TraceEntry::parse(line).map_err(|message| format!("line {} of {:?}: {}", n + 1, path, message))?);
+    }
+
+    entries.sort_by(|a, b| a.timestamp.total_cmp(&b.timestamp));
+    Ok(entries)
+}
+
+/// Returns the seconds since the UNIX epoch of a log line starting with a
+/// `YYYY-MM-DD HH:MM:SS[.fff]` timestamp, along with the rest of the line.
+/// The time zone which follows is left out, as only the time between the
+/// lines matters to a replay.
+fn parse_log_timestamp(line: &str) -> Option<(f64, &str)> {
+    let (date, rest) = line.split_once(' ')?;
+    let (time, rest) = rest.split_once(' ').unwrap_or((rest, ""));
+    let number = |field: Option<&str>| field.filter(|f| f.bytes().all(|b| b.is_ascii_digit()))?.parse::<i64>().ok();
+
+    let mut fields = date.split('-');
+    let (year, month, day) = (number(fields.next())?, number(fields.next())?, number(fields.next())?);
+    let (time, fraction) = time.split_once('.').unwrap_or((time, "0"));
+    let mut fields = time.split(':');
+    let (hours, minutes, seconds) = (number(fields.next())?, number(fields.next())?, number(fields.next())?);
+    let fraction = format!("0.{}", fraction).parse::<f64>().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    // the days since the epoch of the proleptic Gregorian calendar.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+
+    let secs = days * 86400 + hours * 3600 + minutes * 60 + seconds;
+    Some((secs as f64 + fraction, rest))
+}
+
+/// Returns the first WAL segment name found in the text.
+fn find_segment_name(text: &str) -> Option<&str> {
+    text.split(|c: char| !c.is_ascii_hexdigit()).find(|word| wal::parse_segment_name(word).is_some())
+}
+
+/// Reads the arrivals from a PostgreSQL server log, written with a
+/// `log_line_prefix` starting with `%m` or `%t`, and `log_min_messages` set
+/// to debug1 at least so that every archived segment is logged. A segment
+/// arrives when it first shows up in the log, every failed archive command
+/// counts as a failure of it, and it is quarantined when it is never archived
+/// within the log. The log does not tell the sizes of the segments, which
+/// are all `segment_size` bytes.
+pub fn read_archive_log(path: &str, segment_size: u64) -> Result<Vec<TraceEntry>, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("failed to read the archive log {:?}: {}", path, e))?;
+
+    // the first time, the failures and whether it got archived, by segment.
+    let mut segments: HashMap<&str, (f64, u32, bool)> = HashMap::new();
+    for (timestamp, message) in contents.lines().filter_map(parse_log_timestamp) {
+        let (segment, archived) = if let Some((_, archived)) = message.split_once("archived write-ahead log file ") {
+            (find_segment_name(archived), true)
+        } else if let Some((_, command)) = message.split_once("The failed archive command was: ") {
+            (find_segment_name(command), false)
+        } else {
+            continue;
+        };
+
+        let Some(segment) = segment else {
+            continue;
+        };
+        let (_, failures, done) = segments.entry(segment).or_insert((timestamp, 0, false));
+        if archived {
+            *done = true;
+        } else if !*done {
+            *failures += 1;
+        }
+    }
+
+    let mut entries = segments.into_iter()
+        .map(|(segment, (timestamp, failures, archived))| {
+            let failures = failures.min(u8::MAX as u32) as u8;
+            TraceEntry {
+                timestamp,
+                segment: segment.to_string(),
+                size: segment_size,
+                outcome: if archived { Outcome::Archived { failures } } else { Outcome::Quarantined { failures } },
+            }
+        })
+        .collect::<Vec<TraceEntry>>();
+    entries.sort_by(|a, b| a.timestamp.total_cmp(&b.timestamp).then_with(|| a.segment.cmp(&b.segment)));
+    Ok(entries)
+}
+
+/// When each segment of the trace is due, relative to the start of the
+/// replay. A time scale of 2 replays the trace twice as fast.
+pub fn replay_offsets(entries: &[TraceEntry], time_scale: f64) -> Vec<Duration> {
+    let first = entries.first().map_or(0.0, |e| e.timestamp);
+    entries.iter()
+        .map(|e| Duration::try_from_secs_f64((e.timestamp - first) / time_scale).unwrap_or(Duration::MAX))
+        .collect()
+}
+
+/// An arrival trace to replay instead of generating segments.
+pub struct Replay {
+    pub entries: Vec<TraceEntry>,
+
+    /// How many times faster than recorded the trace is replayed.
+    pub time_scale: f64,
+}
+
+/// Appends the segments archived by the processor to an arrival trace, so
+/// that the run can be replayed later on.
+pub struct TraceWriter {
+    file: File,
+}
+
+impl TraceWriter {
+    pub fn open(path: &str) -> io::Result<Self> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        if file.metadata()?.len() == 0 {
+            writeln!(file, "{}", HEADER)?;
+        }
+        Ok(TraceWriter { file })
+    }
+
+    pub fn append(&mut self, entry: &TraceEntry) -> io::Result<()> {
+        writeln!(self.file, "{}", entry.to_line())
+    }
+}
+
+/// The current time as a trace timestamp.
+pub fn now_timestamp() -> f64 {
+    clock::current().system_time().duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn write_and_read_trace() {
+        let dir = TempDir::new("arrivals");
+        let path = dir.join("arrivals.csv");
+        let path = path.to_str().unwrap();
+
+        let mut writer = TraceWriter::open(path).unwrap();
+        let entry = |timestamp, segment: &str, outcome| TraceEntry { timestamp, segment: segment.to_string(), size: 16, outcome };
+        writer.append(&entry(102.5, "000000010000000000000002", Outcome::Archived { failures: 3 })).unwrap();
+        writer.append(&entry(100.0, "000000010000000000000001", Outcome::Archived { failures: 0 })).unwrap();
+        writer.append(&entry(103.0, "000000010000000000000003", Outcome::Permanent)).unwrap();
+        writer.append(&entry(104.0, "000000010000000000000004", Outcome::Quarantined { failures: 5 })).unwrap();
+        drop(writer);
+
+        let entries = read_trace(path).unwrap();
+        assert_eq!("000000010000000000000001", entries[0].segment);
+        assert_eq!(WalAction::Fail { count: 3 }, entries[1].action());
+        assert_eq!(WalAction::PermanentError, entries[2].action());
+        assert_eq!((Outcome::Quarantined { failures: 5 }, WalAction::Fail { count: u8::MAX }), (entries[3].outcome, entries[3].action()));
+        assert_eq!(vec![Duration::ZERO, Duration::from_millis(1250)], replay_offsets(&entries[..2], 2.0));
+
+        fs::write(path, "100,000000010000000000000001,16,retry\n").unwrap();
+        assert!(read_trace(path).unwrap_err().contains("line 1"));
+        fs::write(path, "100,../../etc/passwd,16,success\n").unwrap();
+        assert!(read_trace(path).unwrap_err().contains("invalid segment name"));
+    }
+
+    #[test]
+    fn read_postgres_archive_log() {
+        let dir = TempDir::new("archive-log");
+        let path = dir.join("postgresql.log");
+        fs::write(&path, r#"2024-05-01 12:00:00.250 UTC [41] DEBUG:  archived write-ahead log file "000000010000000000000001"
+2024-05-01 12:00:01 UTC [41] LOG:  archive command failed with exit code 1
+2024-05-01 12:00:01 UTC [41] DETAIL:  The failed archive command was: cp pg_wal/000000010000000000000002 /archive/000000010000000000000002
+2024-05-01 12:00:02 UTC [41] DETAIL:  The failed archive command was: cp pg_wal/000000010000000000000002 /archive/000000010000000000000002
+	continued line without a timestamp 000000010000000000000009
+2024-05-01 12:00:02.500 UTC [41] DEBUG:  archived write-ahead log file "000000010000000000000002"
+2024-05-01 12:00:03 UTC [41] DETAIL:  The failed archive command was: cp pg_wal/000000010000000000000003 /archive/000000010000000000000003
+2024-05-01 12:00:04 UTC [41] LOG:  checkpoint starting: time
+"#).unwrap();
+
+        let entries = read_archive_log(path.to_str().unwrap(), 16).unwrap();
+        assert_eq!(vec![
+            TraceEntry { timestamp: 1714564800.25, segment: "000000010000000000000001".to_string(), size: 16, outcome: Outcome::Archived { failures: 0 } },
+            TraceEntry { timestamp: 1714564801.0, segment: "000000010000000000000002".to_string(), size: 16, outcome: Outcome::Archived { failures: 2 } },
+            TraceEntry { timestamp: 1714564803.0, segment: "000000010000000000000003".to_string(), size: 16, outcome: Outcome::Quarantined { failures: 1 } },
+        ], entries);
+    }
+}
@@ -0,0 +1,262 @@
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::clock;
+use crate::destination::Destination;
+use crate::filesystem;
+use crate::simulation::distribution::DurationDistribution;
+use crate::simulation::lib::{self, RngStream};
+use crate::utilities;
+
+/// When the simulated destination is unavailable.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Outages {
+    /// The time between the end of an outage and the start of the next one.
+    pub every: DurationDistribution,
+
+    /// How long an outage lasts.
+    pub lasting: DurationDistribution,
+}
+
+/// How the simulated destination behaves, regardless of the segments sent to it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackendConfig {
+    /// The round trip time of every request.
+    pub latency: DurationDistribution,
+
+    /// The upload bandwidth in bytes per second, 0 is unlimited.
+    pub bandwidth: u64,
+
+    /// The probability of an upload failing with a dropped connection.
+    pub error_rate: f64,
+
+    /// Requests fail while the destination is out, none by default.
+    pub outages: Option<Outages>,
+}
+
+impl Default for BackendConfig {
+    /// A destination as fast and reliable as the one it wraps.
+    fn default() -> Self {
+        BackendConfig {
+            latency: DurationDistribution::Fixed { value: Duration::ZERO },
+            bandwidth: 0,
+            error_rate: 0.0,
+            outages: None,
+        }
+    }
+}
+
+impl BackendConfig {
+    pub fn validate(&mut self) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&self.error_rate) {
+            return Err(format!("error_rate must be between 0 and 1, got {}", self.error_rate));
+        }
+
+        self.latency.validate().map_err(|e| format!("latency: {}", e))?;
+        if let Some(outages) = self.outages.as_mut() {
+            outages.every.validate().map_err(|e| format!("outages.every: {}", e))?;
+            outages.lasting.validate().map_err(|e| format!("outages.lasting: {}", e))?;
+        }
+        Ok(())
+    }
+}
+
+/// The draws of the destination, shared by the workers uploading to it.
+struct BackendState {
+    seed: u64,
+
+    /// The draws of the outages, and of the requests made outside of the
+    /// processor's workers.
+    rng: ChaCha8Rng,
+
+    /// The draws of the requests of every worker, from a stream of its own.
+    workers: HashMap<u8, ChaCha8Rng>,
+
+    /// The start and the end of the current or the next outage.
+    outage: Option<(Instant, Instant)>,
+}
+
+impl BackendState {
+    /// The draws of the request made on the current thread.
+    fn request_rng(&mut self) -> &mut ChaCha8Rng {
+        match utilities::current_worker_id() {
+            Some(id) => self.workers.entry(id).or_insert_with(|| lib::stream_rng(self.seed, RngStream::Worker(id))),
+            None => &mut self.rng,
+        }
+    }
+
+    /// Whether the destination is out at `now`. Once an outage ends, the next
+    /// one is scheduled from then on.
+    fn is_out(&mut self, outages: &Outages, now: Instant) -> bool {
+        match self.outage {
+            Some((start, end)) if now < end => now >= start,
+            _ => {
+                let start = now + outages.every.sample(&mut self.rng);
+                self.outage = Some((start, start + outages.lasting.sample(&mut self.rng)));
+                false
+            }
+        }
+    }
+}
+
+/// A remote destination simulated on top of another one, which stores the
+/// segments. Whether an upload succeeds depends on the state of the
+/// destination at the time, not on the segment.
+pub struct SimulatedBackend<D> {
+    inner: D,
+    config: BackendConfig,
+    state: Mutex<BackendState>,
+}
+
+impl<D: Destination> SimulatedBackend<D> {
+    /// The first outage, if any, is scheduled from the creation of the backend.
+    /// The draws derive from the seed of the simulation.
+    pub fn new(inner: D, config: BackendConfig, seed: u64) -> Self {
+        let rng = lib::stream_rng(seed, RngStream::Backend);
+        let mut state = BackendState { seed, rng, workers: HashMap::new(), outage: None };
+        if let Some(outages) = config.outages.as_ref() {
+            state.is_out(outages, clock::current().now());
+        }
+        SimulatedBackend { inner, config, state: Mutex::new(state) }
+    }
+
+    /// Takes the round trip of a request, which fails while the destination is out.
+    fn round_trip(&self) -> io::Result<()> {
+        let (latency, out) = {
+            let mut state = self.state.lock().expect("the backend state is poisoned");
+            let latency = self.config.latency.sample(state.request_rng());
+            let out = match self.config.outages.as_ref() {
+                Some(outages) => state.is_out(outages, clock::current().now()),
+                None => false,
+            };
+            (latency, out)
+        };
+
+        clock::current().sleep(latency);
+        if out {
+            return Err(io::Error::new(io::ErrorKind::ConnectionRefused, "the destination is in an outage"));
+        }
+        Ok(())
+    }
+}
+
+impl<D: Destination> Destination for SimulatedBackend<D> {
+    /// The upload takes as long as the bandwidth allows, even when the
+    /// connection ends up dropped.
+    fn put(&self, segment_name: &str, source: &Path) -> io::Result<()> {
+        self.round_trip()?;
+        let dropped = self.state.lock().expect("the backend state is poisoned").request_rng().gen_bool(self.config.error_rate);
+        if self.config.bandwidth > 0 {
+            let size = filesystem::current().size(source)?;
+            clock::current().sleep(Duration::from_secs_f64(size as f64 / self.config.bandwidth as f64));
+        }
+
+        if dropped {
+            return Err(io::Error::new(io::ErrorKind::ConnectionReset, "the destination dropped the connection"));
+        }
+        self.inner.put(segment_name, source)
+    }
+
+    fn get(&self, segment_name: &str) -> io::Result<Box<dyn Read>> {
+        self.round_trip()?;
+        self.inner.get(segment_name)
+    }
+
+    fn list(&self) -> io::Result<Vec<String>> {
+        self.round_trip()?;
+        self.inner.list()
+    }
+
+    fn overwrites(&self) -> bool {
+        self.inner.overwrites()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Barrier};
+    use crate::destination::LocalDirectory;
+    use crate::filesystem::MemoryFileSystem;
+    use crate::wal::ErrorClass;
+
+    #[test]
+    fn destination_state_decides() {
+        filesystem::with(Arc::new(MemoryFileSystem::default()), destination_state_decides_in_memory);
+    }
+
+    fn destination_state_decides_in_memory() {
+        let root = Path::new("wal-backend");
+        let fs = filesystem::current();
+        fs.create_dir_all(root).unwrap();
+        fs.write(&root.join("source"), b"segment contents").unwrap();
+        let backend = |config| SimulatedBackend::new(LocalDirectory::new(root.join("archive")), config, 1);
+
+        let reliable = backend(BackendConfig { bandwidth: 1_000, ..BackendConfig::default() });
+        let started = Instant::now();
+        reliable.put("000000010000000000000001", &root.join("source")).unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(16));
+
+        let flaky = backend(BackendConfig { error_rate: 1.0, ..BackendConfig::default() });
+        let e = flaky.put("000000010000000000000001", &root.join("source")).unwrap_err();
+        assert_eq!(ErrorClass::Retryable, ErrorClass::of(&e));
+        assert_eq!(vec!["000000010000000000000001".to_string()], flaky.list().unwrap());
+
+        let hour = DurationDistribution::Fixed { value: Duration::from_secs(3600) };
+        let out = backend(BackendConfig {
+            outages: Some(Outages { every: DurationDistribution::Fixed { value: Duration::ZERO }, lasting: hour }),
+            ..BackendConfig::default()
+        });
+        assert_eq!(io::ErrorKind::ConnectionRefused, out.list().unwrap_err().kind());
+    }
+
+    #[test]
+    fn workers_draw_from_their_own_stream() {
+        filesystem::with(Arc::new(MemoryFileSystem::default()), || {
+            let uploads = flaky_uploads(7);
+            assert_eq!(uploads, flaky_uploads(7));
+            assert!(uploads.iter().flatten().any(|ok| *ok) && uploads.iter().flatten().any(|ok| !ok));
+        });
+    }
+
+    /// Takes rounds of one upload per worker of a pool, the workers drawing in
+    /// whichever order they get scheduled. Returns whether each upload of every
+    /// worker went through.
+    fn flaky_uploads(seed: u64) -> Vec<Vec<bool>> {
+        const WORKERS: u8 = 2;
+        const ROUNDS: usize = 32;
+
+        let root = Path::new("wal-backend");
+        filesystem::current().create_dir_all(root).unwrap();
+        filesystem::current().write(&root.join("source"), b"segment contents").unwrap();
+        let config = BackendConfig { error_rate: 0.5, ..BackendConfig::default() };
+        let backend = Arc::new(SimulatedBackend::new(LocalDirectory::new(root.join("archive")), config, seed));
+
+        let pool = utilities::ThreadPool::new(WORKERS);
+        let barrier = Arc::new(Barrier::new(WORKERS as usize));
+        for _ in 0..ROUNDS * WORKERS as usize {
+            let backend = backend.clone();
+            let barrier = barrier.clone();
+            pool.execute(move || {
+                // a worker waiting for the others cannot take the next upload.
+                barrier.wait();
+                let uploaded = backend.put("000000010000000000000001", &root.join("source")).is_ok();
+                (utilities::current_worker_id().unwrap(), uploaded)
+            });
+        }
+
+        let mut uploads = vec![Vec::new(); WORKERS as usize];
+        for (worker, uploaded) in pool.collect_results(ROUNDS * WORKERS as usize) {
+            uploads[worker as usize].push(uploaded);
+        }
+        uploads
+    }
+}
@@ -0,0 +1,335 @@
+use std::fs;
+use std::time::Duration;
+use rand::prelude::*;
+use rand_distr::{Exp, LogNormal, Pareto};
+use serde::{Deserialize, Serialize};
+
+use crate::simulation::duration;
+use crate::wal::WalAction;
+
+/// How a duration of the simulation, e.g. the processing time of a WAL file,
+/// is drawn.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum DurationDistribution {
+    /// Always the same duration.
+    Fixed {
+        #[serde(with = "duration")]
+        value: Duration,
+    },
+
+    /// Uniformly distributed between min and max, inclusive.
+    Uniform {
+        #[serde(with = "duration")]
+        min: Duration,
+        #[serde(with = "duration")]
+        max: Duration,
+    },
+
+    Exponential {
+        #[serde(with = "duration")]
+        mean: Duration,
+    },
+
+    /// The logarithm of the duration is normally distributed, `sigma` being
+    /// its standard deviation.
+    LogNormal {
+        #[serde(with = "duration")]
+        median: Duration,
+        sigma: f64,
+    },
+
+    /// Heavy tailed, never shorter than `scale`; the smaller the shape the
+    /// heavier the tail.
+    Pareto {
+        #[serde(with = "duration")]
+        scale: Duration,
+        shape: f64,
+    },
+
+    /// Resampled from the durations listed in a CSV file, one per line in the
+    /// first column, e.g. "12ms". A first line which is not a duration is
+    /// taken as the header.
+    Empirical {
+        path: String,
+        #[serde(skip)]
+        samples: Vec<Duration>,
+    },
+}
+
+fn from_secs(secs: f64) -> Duration {
+    Duration::try_from_secs_f64(secs).unwrap_or(Duration::MAX)
+}
+
+fn read_samples(path: &str) -> Result<Vec<Duration>, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("failed to read {:?}: {}", path, e))?;
+    let mut samples = Vec::new();
+    for (n, line) in contents.lines().enumerate() {
+        let field = line.split(',').next().unwrap_or("").trim();
+        if field.is_empty() {
+            continue;
+        }
+
+        match duration::parse_duration(field) {
+            Ok(sample) => samples.push(sample),
+            Err(_) if n == 0 => continue,
+            Err(message) => return Err(format!("line {} of {:?}: {}", n + 1, path, message)),
+        }
+    }
+
+    if samples.is_empty() {
+        return Err(format!("{:?} does not list any duration", path));
+    }
+    Ok(samples)
+}
+
+impl DurationDistribution {
+    /// Checks the parameters, and loads the samples of an empirical distribution.
+    pub fn validate(&mut self) -> Result<(), String> {
+        match self {
+            DurationDistribution::Uniform { min, max } if *min > *max => {
+                Err(format!("max ({}) must not be less than min ({})",
+                    duration::format_duration(max), duration::format_duration(min)))
+            },
+            DurationDistribution::Exponential { mean } if mean.is_zero() => {
+                Err("the mean must be greater than zero".to_string())
+            },
+            DurationDistribution::LogNormal { median, sigma } if median.is_zero() || !sigma.is_finite() || *sigma < 0.0 => {
+                Err("the median must be greater than zero and sigma must not be negative".to_string())
+            },
+            DurationDistribution::Pareto { scale, shape } if scale.is_zero() || !shape.is_finite() || *shape <= 0.0 => {
+                Err("the scale and the shape must be greater than zero".to_string())
+            },
+            DurationDistribution::Empirical { path, samples } => {
+                *samples = read_samples(path)?;
+                Ok(())
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// Draws a duration, the distribution must be validated beforehand.
+    pub fn sample(&self, rng: &mut impl Rng) -> Duration {
+        match self {
+            DurationDistribution::Fixed { value } => *value,
+            DurationDistribution::Uniform { min, max } => rng.gen_range(*min..=*max),
+            DurationDistribution::Exponential { mean } => {
+                from_secs(Exp::new(1.0 / mean.as_secs_f64()).expect("validated exponential").sample(rng))
+            },
+            DurationDistribution::LogNormal { median, sigma } => {
+                from_secs(LogNormal::new(median.as_secs_f64().ln(), *sigma).expect("validated log-normal").sample(rng))
+            },
+            DurationDistribution::Pareto { scale, shape } => {
+                from_secs(Pareto::new(scale.as_secs_f64(), *shape).expect("validated pareto").sample(rng))
+            },
+            DurationDistribution::Empirical { samples, .. } => {
+                *samples.choose(rng).expect("validated empirical distribution")
+            },
+        }
+    }
+}
+
+/// How the generator decides whether a WAL file fails.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum FailureModel {
+    /// Every WAL file fails independently with the given probability.
+    Bernoulli { ratio: f64 },
+
+    /// A two-state Markov chain, failures come in bursts while the chain is
+    /// in the bad state. The chain transitions before every WAL file.
+    GilbertElliott {
+        p_good_to_bad: f64,
+        p_bad_to_good: f64,
+        good_failure_ratio: f64,
+        bad_failure_ratio: f64,
+    },
+}
+
+impl FailureModel {
+    /// Lists the probabilities which are not between 0 and 1.
+    pub fn validate(&self) -> Result<(), String> {
+        let probabilities = match self {
+            FailureModel::Bernoulli { ratio } => vec![("ratio", *ratio)],
+            FailureModel::GilbertElliott { p_good_to_bad, p_bad_to_good, good_failure_ratio, bad_failure_ratio } => vec![
+                ("p_good_to_bad", *p_good_to_bad),
+                ("p_bad_to_good", *p_bad_to_good),
+                ("good_failure_ratio", *good_failure_ratio),
+                ("bad_failure_ratio", *bad_failure_ratio),
+            ],
+        };
+
+        let invalid = probabilities.iter()
+            .filter(|(_, p)| !(0.0..=1.0).contains(p))
+            .map(|(name, p)| format!("{} must be between 0 and 1, got {}", name, p))
+            .collect::<Vec<String>>();
+        if invalid.is_empty() { Ok(()) } else { Err(invalid.join(", ")) }
+    }
+}
+
+/// Builds the action of a failing WAL file from its failure count.
+type ActionFn = fn(u8) -> WalAction;
+
+/// The relative weights of the ways a failing WAL file fails.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct FailureKinds {
+    pub fail: f64,
+    pub timeout: f64,
+    pub corrupt: f64,
+    pub partial: f64,
+    pub permanent_error: f64,
+    pub panic: f64,
+}
+
+impl Default for FailureKinds {
+    /// Plain failures only.
+    fn default() -> Self {
+        FailureKinds { fail: 1.0, timeout: 0.0, corrupt: 0.0, partial: 0.0, permanent_error: 0.0, panic: 0.0 }
+    }
+}
+
+impl FailureKinds {
+    fn weights(&self) -> [(f64, ActionFn); 6] {
+        [
+            (self.fail, |count| WalAction::Fail { count }),
+            (self.timeout, |count| WalAction::Timeout { count }),
+            (self.corrupt, |count| WalAction::Corrupt { count }),
+            (self.partial, |count| WalAction::Partial { count }),
+            (self.permanent_error, |_| WalAction::PermanentError),
+            (self.panic, |_| WalAction::Panic),
+        ]
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let weights = self.weights();
+        if weights.iter().any(|(w, _)| !w.is_finite() || *w < 0.0) {
+            return Err("the weights must not be negative".to_string());
+        }
+        if weights.iter().all(|(w, _)| *w == 0.0) {
+            return Err("at least one weight must be greater than zero".to_string());
+        }
+        Ok(())
+    }
+
+    /// Draws the action of a failing WAL file, which fails `count` times
+    /// unless it fails only once or for good.
+    pub fn action(&self, count: u8, rng: &mut impl Rng) -> WalAction {
+        let weights = self.weights();
+        let mut r = rng.gen::<f64>() * weights.iter().map(|(w, _)| w).sum::<f64>();
+        for (weight, action) in weights {
+            if r < weight {
+                return action(count);
+            }
+            r -= weight;
+        }
+
+        // rounding left r at the total, the last kind with a weight takes it.
+        let (_, action) = weights.into_iter().rev().find(|(w, _)| *w > 0.0).expect("validated failure kinds");
+        action(count)
+    }
+}
+
+/// The state of a failure model over the WAL files of a run.
+pub struct FailureChain {
+    model: FailureModel,
+    bad: bool,
+}
+
+impl FailureChain {
+    /// Starts the chain in the good state.
+    pub fn new(model: FailureModel) -> Self {
+        FailureChain { model, bad: false }
+    }
+
+    /// Decides whether the next WAL file fails.
+    pub fn fails(&mut self, rng: &mut impl Rng) -> bool {
+        match self.model {
+            FailureModel::Bernoulli { ratio } => rng.gen_bool(ratio),
+            FailureModel::GilbertElliott { p_good_to_bad, p_bad_to_good, good_failure_ratio, bad_failure_ratio } => {
+                let transition = if self.bad { p_bad_to_good } else { p_good_to_bad };
+                if rng.gen_bool(transition) {
+                    self.bad = !self.bad;
+                }
+                rng.gen_bool(if self.bad { bad_failure_ratio } else { good_failure_ratio })
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand_chacha::ChaCha8Rng;
+    use crate::testing::TempDir;
+
+    fn mean(distribution: &DurationDistribution, n: u32) -> Duration {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        (0..n).map(|_| distribution.sample(&mut rng)).sum::<Duration>() / n
+    }
+
+    #[test]
+    fn duration_distributions() {
+        let exponential: DurationDistribution = serde_json::from_str(r#"{"kind": "exponential", "mean": "10ms"}"#).unwrap();
+        let m = mean(&exponential, 20_000);
+        assert!(m > Duration::from_micros(9_500) && m < Duration::from_micros(10_500), "{:?}", m);
+
+        let pareto = DurationDistribution::Pareto { scale: Duration::from_millis(2), shape: 3.0 };
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        assert!((0..1000).all(|_| pareto.sample(&mut rng) >= Duration::from_millis(2)));
+
+        let mut invalid = DurationDistribution::LogNormal { median: Duration::ZERO, sigma: 1.0 };
+        assert!(invalid.validate().is_err());
+        assert!(serde_json::from_str::<DurationDistribution>(r#"{"kind": "gamma"}"#).is_err());
+    }
+
+    #[test]
+    fn empirical_distribution() {
+        let dir = TempDir::new("empirical");
+        let path = dir.join("empirical.csv");
+        fs::write(&path, "duration,host\n5ms,a\n\n7ms,b\n").unwrap();
+
+        let mut empirical = DurationDistribution::Empirical { path: path.to_string_lossy().into_owned(), samples: Vec::new() };
+        empirical.validate().unwrap();
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        assert!((0..100).all(|_| [Duration::from_millis(5), Duration::from_millis(7)].contains(&empirical.sample(&mut rng))));
+
+        fs::write(&path, "5ms\nsoon\n").unwrap();
+        assert!(empirical.validate().is_err());
+    }
+
+    #[test]
+    fn gilbert_elliott_bursts() {
+        // the chain goes bad on the first WAL file and stays there.
+        let model = FailureModel::GilbertElliott {
+            p_good_to_bad: 1.0,
+            p_bad_to_good: 0.0,
+            good_failure_ratio: 0.0,
+            bad_failure_ratio: 1.0,
+        };
+        assert!(model.validate().is_ok());
+
+        let mut chain = FailureChain::new(model);
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        assert!((0..10).all(|_| chain.fails(&mut rng)));
+
+        let invalid = FailureModel::Bernoulli { ratio: 2.0 };
+        assert_eq!(Err("ratio must be between 0 and 1, got 2".to_string()), invalid.validate());
+    }
+
+    #[test]
+    fn failure_kinds() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        assert!((0..100).all(|_| FailureKinds::default().action(2, &mut rng) == WalAction::Fail { count: 2 }));
+
+        let kinds: FailureKinds = serde_json::from_str(r#"{"fail": 0, "timeout": 1, "panic": 1}"#).unwrap();
+        assert!(kinds.validate().is_ok());
+        let actions = (0..1000).map(|_| kinds.action(3, &mut rng)).collect::<Vec<_>>();
+        assert!(actions.iter().all(|a| matches!(a, WalAction::Timeout { count: 3 } | WalAction::Panic)));
+        let panics = actions.iter().filter(|a| **a == WalAction::Panic).count();
+        assert!((400..600).contains(&panics), "{}", panics);
+
+        let none = FailureKinds { fail: 0.0, ..FailureKinds::default() };
+        assert!(none.validate().is_err());
+    }
+}
@@ -0,0 +1,98 @@
+use std::time::Duration;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/// The units accepted in duration strings, along with their length in nanoseconds.
+const UNITS: [(&str, u64); 7] = [
+    ("h", 3_600_000_000_000),
+    ("m", 60_000_000_000),
+    ("s", 1_000_000_000),
+    ("ms", 1_000_000),
+    ("us", 1_000),
+    ("µs", 1_000),
+    ("ns", 1),
+];
+
+/// A duration as written in a configuration file. Durations are written as
+/// an integer followed by a unit (e.g. "10ms", "2s"), a plain number is
+/// taken as nanoseconds for compatibility with older files.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum DurationSpec {
+    Nanos(u64),
+    Text(String),
+}
+
+impl DurationSpec {
+    pub fn to_duration(&self) -> Result<Duration, String> {
+        match self {
+            DurationSpec::Nanos(nanos) => Ok(Duration::from_nanos(*nanos)),
+            DurationSpec::Text(text) => parse_duration(text),
+        }
+    }
+}
+
+/// Parses a human readable duration such as "10ms" or "1s".
+pub fn parse_duration(text: &str) -> Result<Duration, String> {
+    let text = text.trim();
+    let split_at = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+    let (number, unit) = text.split_at(split_at);
+
+    let number: u64 = number.parse()
+        .map_err(|_| format!("invalid duration {:?}, expected a number followed by a unit such as \"10ms\"", text))?;
+    let unit_nanos = UNITS.iter()
+        .find(|(name, _)| *name == unit.trim())
+        .map(|(_, nanos)| *nanos)
+        .ok_or_else(|| format!("invalid duration unit {:?} in {:?}, expected one of h, m, s, ms, us, ns", unit, text))?;
+
+    number.checked_mul(unit_nanos)
+        .map(Duration::from_nanos)
+        .ok_or_else(|| format!("duration {:?} is too large", text))
+}
+
+/// Formats the duration with the largest unit that represents it exactly.
+pub fn format_duration(duration: &Duration) -> String {
+    let nanos = duration.as_nanos();
+    if nanos == 0 {
+        return "0s".to_string();
+    }
+
+    let (unit, unit_nanos) = UNITS.iter()
+        .filter(|(name, _)| *name != "µs")
+        .find(|(_, unit_nanos)| nanos.is_multiple_of(*unit_nanos as u128))
+        .expect("every duration is a multiple of a nanosecond");
+    format!("{}{}", nanos / (*unit_nanos as u128), unit)
+}
+
+/// Serializes a duration as a human readable string, for `serialize_with`.
+pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    format_duration(duration).serialize(serializer)
+}
+
+/// Deserializes a duration from a `DurationSpec`, for `deserialize_with`.
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    DurationSpec::deserialize(deserializer)?
+        .to_duration()
+        .map_err(de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_format() {
+        assert_eq!(Ok(Duration::from_millis(10)), parse_duration("10ms"));
+        assert_eq!(Ok(Duration::from_micros(10)), parse_duration("10us"));
+        assert_eq!(Ok(Duration::from_micros(10)), parse_duration("10µs"));
+        assert_eq!(Ok(Duration::from_secs(90)), parse_duration("90 s"));
+        assert_eq!(Ok(Duration::from_secs(7200)), parse_duration("2h"));
+        assert!(parse_duration("10").is_err());
+        assert!(parse_duration("ms").is_err());
+        assert!(parse_duration("10xs").is_err());
+
+        assert_eq!("10ms", format_duration(&Duration::from_millis(10)));
+        assert_eq!("1500us", format_duration(&Duration::from_micros(1500)));
+        assert_eq!("2m", format_duration(&Duration::from_secs(120)));
+        assert_eq!("0s", format_duration(&Duration::ZERO));
+    }
+}
@@ -0,0 +1,239 @@
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::clock;
+use crate::filesystem::{FileSystem, MemoryFileSystem, RealFileSystem};
+use crate::simulation::duration;
+use crate::simulation::lib::{RngStream, SimulationConfig};
+use crate::utilities;
+
+/// The errno values of the injected errors, the same on Linux and macOS.
+const ENOSPC: i32 = 28;
+const EACCES: i32 = 13;
+const EIO: i32 = 5;
+
+/// The file system the services run against during a simulation.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FileSystemKind {
+    #[default]
+    Real,
+
+    /// Held in memory, starting out with empty source and status directories.
+    Memory,
+}
+
+/// The probabilities of the faults injected into every file operation.
+/// Listing and creating directories never fails.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Faults {
+    /// Writes fail as the disk is full.
+    pub enospc: f64,
+
+    /// Operations fail as permission is denied.
+    pub eacces: f64,
+
+    /// Operations fail with an I/O error.
+    pub eio: f64,
+
+    /// Operations take `slow_delay` longer.
+    pub slow: f64,
+
+    #[serde(with = "duration")]
+    pub slow_delay: Duration,
+}
+
+impl Default for Faults {
+    fn default() -> Self {
+        Faults { enospc: 0.0, eacces: 0.0, eio: 0.0, slow: 0.0, slow_delay: Duration::from_millis(10) }
+    }
+}
+
+impl Faults {
+    fn is_none(&self) -> bool {
+        self.enospc == 0.0 && self.eacces == 0.0 && self.eio == 0.0 && self.slow == 0.0
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileSystemConfig {
+    pub kind: FileSystemKind,
+    pub faults: Faults,
+}
+
+impl FileSystemConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        let f = &self.faults;
+        let probabilities = [("enospc", f.enospc), ("eacces", f.eacces), ("eio", f.eio), ("slow", f.slow)];
+        if let Some((name, p)) = probabilities.iter().find(|(_, p)| !(0.0..=1.0).contains(p)) {
+            return Err(format!("faults.{} must be between 0 and 1, got {}", name, p));
+        }
+        if f.enospc + f.eacces + f.eio >= 1.0 {
+            return Err("the errors must leave a chance for the operations to succeed".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Whether an operation writes, only those can run out of space.
+#[derive(Clone, Copy, PartialEq)]
+enum Operation {
+    Read,
+    Write,
+}
+
+/// Injects faults into the operations of another file system.
+pub struct FaultyFileSystem<F> {
+    inner: F,
+    faults: Faults,
+    rng: Mutex<ChaCha8Rng>,
+}
+
+impl<F: FileSystem> FaultyFileSystem<F> {
+    pub fn new(inner: F, faults: Faults, rng: ChaCha8Rng) -> Self {
+        FaultyFileSystem { inner, faults, rng: Mutex::new(rng) }
+    }
+
+    /// Draws the fault of an operation, if any. A slow operation still goes
+    /// through.
+    fn inject(&self, operation: Operation) -> io::Result<()> {
+        let (r, slow) = {
+            let mut rng = self.rng.lock().expect("the fault injection state is poisoned");
+            (rng.gen::<f64>(), rng.gen_bool(self.faults.slow))
+        };
+        if slow {
+            clock::current().sleep(self.faults.slow_delay);
+        }
+
+        let enospc = if operation == Operation::Write { self.faults.enospc } else { 0.0 };
+        let errno = if r < enospc {
+            ENOSPC
+        } else if r < enospc + self.faults.eacces {
+            EACCES
+        } else if r < enospc + self.faults.eacces + self.faults.eio {
+            EIO
+        } else {
+            return Ok(());
+        };
+        Err(io::Error::from_raw_os_error(errno))
+    }
+}
+
+impl<F: FileSystem> FileSystem for FaultyFileSystem<F> {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>> {
+        self.inject(Operation::Read)?;
+        self.inner.open(path)
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.inject(Operation::Read)?;
+        self.inner.read(path)
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        self.inject(Operation::Write)?;
+        self.inner.write(path, contents)
+    }
+
+    fn append(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        self.inject(Operation::Write)?;
+        self.inner.append(path, contents)
+    }
+
+    fn copy(&self, from: &Path, to: &Path) -> io::Result<u64> {
+        self.inject(Operation::Write)?;
+        self.inner.copy(from, to)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.inject(Operation::Read)?;
+        self.inner.rename(from, to)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        self.inject(Operation::Read)?;
+        self.inner.remove(path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.inject(Operation::Read)?;
+        self.inner.remove_dir_all(path)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        self.inner.read_dir(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.inner.create_dir_all(path)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.inner.exists(path)
+    }
+
+    fn size(&self, path: &Path) -> io::Result<u64> {
+        self.inner.size(path)
+    }
+
+    fn modified(&self, path: &Path) -> io::Result<SystemTime> {
+        self.inner.modified(path)
+    }
+
+    fn fsync(&self, path: &Path) -> io::Result<()> {
+        self.inject(Operation::Write)?;
+        self.inner.fsync(path)
+    }
+}
+
+fn with_faults(file_system: impl FileSystem + 'static, simulation_config: &SimulationConfig) -> Arc<dyn FileSystem> {
+    let faults = &simulation_config.wal_filesystem.faults;
+    if faults.is_none() {
+        Arc::new(file_system)
+    } else {
+        Arc::new(FaultyFileSystem::new(file_system, faults.clone(), simulation_config.rng(RngStream::FaultInjection)))
+    }
+}
+
+/// Builds the file system the services of a simulation run against.
+pub fn from_config(simulation_config: &SimulationConfig) -> io::Result<Arc<dyn FileSystem>> {
+    match simulation_config.wal_filesystem.kind {
+        FileSystemKind::Real => Ok(with_faults(RealFileSystem, simulation_config)),
+        FileSystemKind::Memory => {
+            let memory = MemoryFileSystem::default();
+            memory.create_dir_all(Path::new(&utilities::layout().status_dir))?;
+            memory.create_dir_all(Path::new(&utilities::layout().source_dir))?;
+            Ok(with_faults(memory, simulation_config))
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn injected_faults() {
+        let faults = Faults { enospc: 0.5, eio: 0.49, ..Faults::default() };
+        let fs = FaultyFileSystem::new(MemoryFileSystem::default(), faults, ChaCha8Rng::seed_from_u64(1));
+        let errors = (0..200)
+            .filter_map(|_| fs.write(Path::new("segment"), b"contents").err())
+            .filter_map(|e| e.raw_os_error())
+            .collect::<Vec<_>>();
+        assert!(errors.contains(&ENOSPC) && errors.contains(&EIO) && errors.len() < 200);
+        assert_eq!(io::ErrorKind::StorageFull, io::Error::from_raw_os_error(ENOSPC).kind());
+
+        // reads never run out of space.
+        assert!((0..200).filter_map(|_| fs.read(Path::new("segment")).err()).all(|e| e.raw_os_error() == Some(EIO)));
+
+        let invalid = FileSystemConfig { faults: Faults { eacces: 0.5, eio: 0.5, ..Faults::default() }, ..FileSystemConfig::default() };
+        assert!(invalid.validate().is_err());
+    }
+}
@@ -1,18 +1,23 @@
-use std::io::Read;
+use std::fmt;
+use std::time::Duration;
 use serde::{Serialize, Deserialize};
 use serde_json;
 use rand::prelude::*;
 use rand_chacha::ChaCha8Rng;
 
-use crate::utilities;
+use crate::simulation::backend::BackendConfig;
+use crate::simulation::distribution::{DurationDistribution, FailureKinds, FailureModel};
+use crate::simulation::duration::{self, DurationSpec};
+use crate::simulation::faults::FileSystemConfig;
+use crate::simulation::payload::{self, PayloadKind};
 
 /// Represents the simulation configurations that will
 /// be read from the simulation_conf.json file.
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Clone, Debug)]
 pub struct SimulationConfig {
     /// A fixed seed that will be fed to the randomizer to obtain the same randomization always.
-    seed: u64,
-    
+    pub(crate) seed: u64,
+
     /// Specifies how often the WAL generator create WAL files which will cause
     /// failures.
     pub(crate) wal_failure_ratio: f64,
@@ -21,7 +26,7 @@ pub struct SimulationConfig {
     /// Only applies to WalAction::Fail
     pub(crate) wal_failure_attempt_min: u8,
 
-    /// The max number attempts that will result with failure.
+    /// The max number attempts that will result with failure, inclusive.
     /// Only applies to WalAction::Fail
     pub(crate) wal_failure_attempt_max: u8,
 
@@ -29,47 +34,426 @@ pub struct SimulationConfig {
     pub(crate) num_wals_to_generate: u64,
 
     /// Specifies the amount of delay to be put between WAL file generation.
-    /// Unit is nanoseconds. 
-    pub(crate) wal_generation_delay: u64,
-    
+    #[serde(serialize_with = "duration::serialize")]
+    pub(crate) wal_generation_delay: Duration,
+
     /// Specifies the amount of delay the WAL consumer will apply before marking
     /// WAL files as done.
-    pub(crate) wal_consumer_delay: u64,
+    #[serde(serialize_with = "duration::serialize")]
+    pub(crate) wal_consumer_delay: Duration,
 
     /// Specifies the amount of delay to be put between WAL file processing.
-    /// Unit is nanoseconds.
-    pub(crate) wal_processing_delay: u64,
-    
+    #[serde(serialize_with = "duration::serialize")]
+    pub(crate) wal_processing_delay: Duration,
+
     /// Specifies the minimum amount of time that will be spend on processing a particular
-    /// WAL file. The unit is nanoseconds.
-    pub(crate) wal_process_duration_min: u64,
-    
+    /// WAL file.
+    #[serde(serialize_with = "duration::serialize")]
+    pub(crate) wal_process_duration_min: Duration,
+
     /// Specifies the maximum amount of time that will be spend on processing a particular
-    /// WAL file. The unit is nanoseconds.
-    pub(crate)  wal_process_duration_max: u64,
+    /// WAL file, inclusive.
+    #[serde(serialize_with = "duration::serialize")]
+    pub(crate)  wal_process_duration_max: Duration,
+
+    /// How the processing time of a WAL file is drawn, uniformly between
+    /// wal_process_duration_min and wal_process_duration_max unless given.
+    pub(crate) wal_process_duration_distribution: DurationDistribution,
+
+    /// How the delay between two generated WAL files is drawn, always
+    /// wal_generation_delay unless given.
+    pub(crate) wal_generation_interval_distribution: DurationDistribution,
+
+    /// How the generator decides whether a WAL file fails, independently at
+    /// wal_failure_ratio unless given.
+    pub(crate) wal_failure_model: FailureModel,
+
+    /// The size of the segment data files created by the generator, in bytes.
+    pub(crate) wal_segment_size: u64,
+
+    /// What the segment data files are filled with.
+    pub(crate) wal_payload: PayloadKind,
+
+    /// How the failing WAL files fail, e.g. by timing out or panicking.
+    pub(crate) wal_failure_kinds: FailureKinds,
+
+    /// Specifies how often the succeeding WAL files are slow to upload.
+    pub(crate) wal_slow_ratio: f64,
+
+    /// How many times longer than usual the upload of a slow WAL file takes.
+    pub(crate) wal_slow_factor: f64,
+
+    /// How the simulated destination behaves, when the destination is of the
+    /// "simulated" kind.
+    pub(crate) wal_backend: BackendConfig,
+
+    /// The file system the services run against, and the faults injected
+    /// into it.
+    pub(crate) wal_filesystem: FileSystemConfig,
+}
+
+/// The components of the simulation drawing random numbers. Each of them draws
+/// from its own stream of the master seed, so that a change to the draws of
+/// one component keeps the sequences of the others intact.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RngStream {
+    /// Whether the generated WAL files fail, and how many times.
+    Failures,
+
+    /// The processing durations of the generated WAL files.
+    Durations,
+
+    /// The delays between two generated WAL files.
+    Arrivals,
+
+    /// The contents of the segment data files.
+    Payloads,
+
+    /// How the generated WAL files fail, and which ones are slow.
+    Outcomes,
+
+    /// The draws made on behalf of the processor's ThreadPool worker N, which
+    /// do not depend on how the workers interleave.
+    Worker(u8),
+
+    /// The simulated destination.
+    Backend,
+
+    /// The faults injected into the services.
+    FaultInjection,
+}
+
+impl RngStream {
+    fn id(self) -> u64 {
+        match self {
+            RngStream::Failures => 1,
+            RngStream::Durations => 2,
+            RngStream::Arrivals => 3,
+            RngStream::Backend => 4,
+            RngStream::FaultInjection => 5,
+            RngStream::Payloads => 6,
+            RngStream::Outcomes => 7,
+            RngStream::Worker(n) => 0x100 + n as u64,
+        }
+    }
+}
+
+/// The simulation configuration as written in the file, before validation.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawSimulationConfig {
+    seed: u64,
+    wal_failure_ratio: f64,
+    wal_failure_attempt_min: u8,
+    wal_failure_attempt_max: u8,
+    num_wals_to_generate: u64,
+    wal_generation_delay: DurationSpec,
+    wal_consumer_delay: DurationSpec,
+    wal_processing_delay: DurationSpec,
+    wal_process_duration_min: DurationSpec,
+    wal_process_duration_max: DurationSpec,
+    #[serde(default)]
+    wal_process_duration_distribution: Option<DurationDistribution>,
+    #[serde(default)]
+    wal_generation_interval_distribution: Option<DurationDistribution>,
+    #[serde(default)]
+    wal_failure_model: Option<FailureModel>,
+    #[serde(default = "default_segment_size")]
+    wal_segment_size: u64,
+    #[serde(default)]
+    wal_payload: PayloadKind,
+    #[serde(default)]
+    wal_failure_kinds: FailureKinds,
+    #[serde(default)]
+    wal_slow_ratio: f64,
+    #[serde(default = "default_slow_factor")]
+    wal_slow_factor: f64,
+    #[serde(default)]
+    wal_backend: BackendConfig,
+    #[serde(default)]
+    wal_filesystem: FileSystemConfig,
+}
+
+fn default_segment_size() -> u64 {
+    payload::DEFAULT_SEGMENT_SIZE
+}
+
+fn default_slow_factor() -> f64 {
+    4.0
+}
+
+/// A problem with a single field of the configuration.
+#[derive(Debug, PartialEq)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub message: String,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    /// A field is missing, unknown or has the wrong type.
+    Syntax(serde_json::Error),
+
+    /// The values of one or more fields are not valid.
+    Invalid(Vec<FieldError>),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Syntax(e) => write!(f, "failed to parse the simulation config: {}", e),
+            ConfigError::Invalid(errors) => {
+                write!(f, "the simulation config is not valid:")?;
+                for error in errors {
+                    write!(f, "\n  {}: {}", error.field, error.message)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl RawSimulationConfig {
+    /// Validates every field, collecting all the problems rather than stopping at the first one.
+    fn validate(self) -> Result<SimulationConfig, Vec<FieldError>> {
+        let mut errors = Vec::new();
+        let mut duration = |field: &'static str, spec: &DurationSpec| {
+            spec.to_duration().unwrap_or_else(|message| {
+                errors.push(FieldError { field, message });
+                Duration::ZERO
+            })
+        };
+
+        let wal_generation_delay = duration("wal_generation_delay", &self.wal_generation_delay);
+        let wal_consumer_delay = duration("wal_consumer_delay", &self.wal_consumer_delay);
+        let wal_processing_delay = duration("wal_processing_delay", &self.wal_processing_delay);
+        let wal_process_duration_min = duration("wal_process_duration_min", &self.wal_process_duration_min);
+        let wal_process_duration_max = duration("wal_process_duration_max", &self.wal_process_duration_max);
+
+        if !(0.0..=1.0).contains(&self.wal_failure_ratio) {
+            errors.push(FieldError {
+                field: "wal_failure_ratio",
+                message: format!("must be between 0 and 1, got {}", self.wal_failure_ratio),
+            });
+        }
+
+        if self.wal_failure_attempt_min == 0 {
+            errors.push(FieldError {
+                field: "wal_failure_attempt_min",
+                message: "must be at least 1, a failing WAL file fails at least once".to_string(),
+            });
+        }
+
+        if self.wal_failure_attempt_min > self.wal_failure_attempt_max {
+            errors.push(FieldError {
+                field: "wal_failure_attempt_max",
+                message: format!("must not be less than wal_failure_attempt_min ({}), got {}",
+                    self.wal_failure_attempt_min, self.wal_failure_attempt_max),
+            });
+        }
+
+        if wal_process_duration_min > wal_process_duration_max {
+            errors.push(FieldError {
+                field: "wal_process_duration_max",
+                message: format!("must not be less than wal_process_duration_min ({}), got {}",
+                    duration::format_duration(&wal_process_duration_min),
+                    duration::format_duration(&wal_process_duration_max)),
+            });
+        }
+
+        // the distributions derived from the fields above need no further validation.
+        let wal_process_duration_distribution = match self.wal_process_duration_distribution {
+            Some(mut distribution) => {
+                if let Err(message) = distribution.validate() {
+                    errors.push(FieldError { field: "wal_process_duration_distribution", message });
+                }
+                distribution
+            },
+            None => DurationDistribution::Uniform { min: wal_process_duration_min, max: wal_process_duration_max },
+        };
+
+        let wal_generation_interval_distribution = match self.wal_generation_interval_distribution {
+            Some(mut distribution) => {
+                if let Err(message) = distribution.validate() {
+                    errors.push(FieldError { field: "wal_generation_interval_distribution", message });
+                }
+                distribution
+            },
+            None => DurationDistribution::Fixed { value: wal_generation_delay },
+        };
+
+        let wal_failure_model = match self.wal_failure_model {
+            Some(model) => {
+                if let Err(message) = model.validate() {
+                    errors.push(FieldError { field: "wal_failure_model", message });
+                }
+                model
+            },
+            None => FailureModel::Bernoulli { ratio: self.wal_failure_ratio },
+        };
+
+        if self.wal_segment_size == 0 && self.wal_payload != PayloadKind::None {
+            errors.push(FieldError {
+                field: "wal_segment_size",
+                message: "must be greater than zero, unless wal_payload is \"none\"".to_string(),
+            });
+        }
+
+        if let Err(message) = self.wal_failure_kinds.validate() {
+            errors.push(FieldError { field: "wal_failure_kinds", message });
+        }
+
+        if !(0.0..=1.0).contains(&self.wal_slow_ratio) {
+            errors.push(FieldError {
+                field: "wal_slow_ratio",
+                message: format!("must be between 0 and 1, got {}", self.wal_slow_ratio),
+            });
+        }
+
+        if !self.wal_slow_factor.is_finite() || self.wal_slow_factor < 1.0 {
+            errors.push(FieldError {
+                field: "wal_slow_factor",
+                message: format!("must be at least 1, got {}", self.wal_slow_factor),
+            });
+        }
+
+        let mut wal_backend = self.wal_backend;
+        if let Err(message) = wal_backend.validate() {
+            errors.push(FieldError { field: "wal_backend", message });
+        }
+
+        if let Err(message) = self.wal_filesystem.validate() {
+            errors.push(FieldError { field: "wal_filesystem", message });
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        Ok(SimulationConfig {
+            seed: self.seed,
+            wal_failure_ratio: self.wal_failure_ratio,
+            wal_failure_attempt_min: self.wal_failure_attempt_min,
+            wal_failure_attempt_max: self.wal_failure_attempt_max,
+            num_wals_to_generate: self.num_wals_to_generate,
+            wal_generation_delay,
+            wal_consumer_delay,
+            wal_processing_delay,
+            wal_process_duration_min,
+            wal_process_duration_max,
+            wal_process_duration_distribution,
+            wal_generation_interval_distribution,
+            wal_failure_model,
+            wal_segment_size: self.wal_segment_size,
+            wal_payload: self.wal_payload,
+            wal_failure_kinds: self.wal_failure_kinds,
+            wal_slow_ratio: self.wal_slow_ratio,
+            wal_slow_factor: self.wal_slow_factor,
+            wal_backend,
+            wal_filesystem: self.wal_filesystem,
+        })
+    }
+}
 
-    #[serde(skip_serializing, skip_deserializing)]
-    pub(crate) rng: Option<ChaCha8Rng>,
+/// Returns the random number generator of the given component for the seed.
+pub fn stream_rng(seed: u64, stream: RngStream) -> ChaCha8Rng {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    rng.set_stream(stream.id());
+    rng
 }
 
 impl SimulationConfig {
-    pub fn get_simulation_config() -> Self {
-        let mut buffer = vec![0; 1024];
-        let mut f = std::fs::OpenOptions::new()
-            .read(true)
-            .create(false)
-            .open(format!("{}/simulation_conf.json", utilities::SIMULATION_DIR))
-            .expect("The simulation configuration file does not exist.");
-        f.read_to_end(&mut buffer).expect("failed to read the file.");
-
-        let buffer = String::from_utf8(buffer).unwrap();
-        let buffer = buffer.replace("\0", "");
-        println!("{:?}", buffer);
-        let mut conf: SimulationConfig = serde_json::from_str(&buffer)
-            .expect("failed to deserialize the simulation config");
-
-        // setup the RNG
-        conf.rng = Some(ChaCha8Rng::seed_from_u64(conf.seed));
-        conf
+    /// Returns the random number generator of the given component, which is
+    /// the same for every run with the same seed.
+    pub fn rng(&self, stream: RngStream) -> ChaCha8Rng {
+        stream_rng(self.seed, stream)
     }
-}
\ No newline at end of file
+
+    /// Validates the configuration given as a JSON tree, e.g. the simulation
+    /// section of the layered configuration.
+    pub fn from_value(value: serde_json::Value) -> Result<Self, ConfigError> {
+        let raw: RawSimulationConfig = serde_json::from_value(value).map_err(ConfigError::Syntax)?;
+        raw.validate().map_err(ConfigError::Invalid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VALID: &str = r#"{
+        "seed": 1,
+        "wal_failure_ratio": 0.2,
+        "wal_failure_attempt_min": 2,
+        "wal_failure_attempt_max": 2,
+        "num_wals_to_generate": 10,
+        "wal_generation_delay": "10us",
+        "wal_consumer_delay": 10000,
+        "wal_processing_delay": "1ms",
+        "wal_process_duration_min": "0s",
+        "wal_process_duration_max": "5ms"
+    }"#;
+
+    #[test]
+    fn valid_config() {
+        let conf = SimulationConfig::from_value(serde_json::from_str(VALID).unwrap()).unwrap();
+        assert_eq!(Duration::from_micros(10), conf.wal_generation_delay);
+        assert_eq!(Duration::from_micros(10), conf.wal_consumer_delay);
+        assert_eq!(Duration::from_millis(5), conf.wal_process_duration_max);
+
+        assert_eq!(DurationDistribution::Fixed { value: Duration::from_micros(10) }, conf.wal_generation_interval_distribution);
+        assert_eq!(FailureModel::Bernoulli { ratio: 0.2 }, conf.wal_failure_model);
+        assert_eq!((payload::DEFAULT_SEGMENT_SIZE, PayloadKind::Random), (conf.wal_segment_size, conf.wal_payload));
+
+        let serialized = serde_json::to_value(&conf).unwrap();
+        assert_eq!("10us", serialized["wal_consumer_delay"]);
+        assert_eq!("uniform", serialized["wal_process_duration_distribution"]["kind"]);
+    }
+
+    #[test]
+    fn independent_streams() {
+        let conf = SimulationConfig::from_value(serde_json::from_str(VALID).unwrap()).unwrap();
+        let draws = |stream| conf.rng(stream).sample_iter(rand::distributions::Standard).take(4).collect::<Vec<u64>>();
+
+        assert_eq!(draws(RngStream::Failures), draws(RngStream::Failures));
+        assert_ne!(draws(RngStream::Failures), draws(RngStream::Durations));
+        assert_ne!(draws(RngStream::Backend), draws(RngStream::FaultInjection));
+        assert_ne!(draws(RngStream::Worker(0)), draws(RngStream::Worker(1)));
+
+        let reseeded = SimulationConfig::from_value(serde_json::from_str(&VALID.replace("\"seed\": 1", "\"seed\": 2")).unwrap()).unwrap();
+        assert_ne!(draws(RngStream::Failures), reseeded.rng(RngStream::Failures).sample_iter(rand::distributions::Standard).take(4).collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn field_errors() {
+        let invalid = VALID
+            .replace("\"wal_failure_ratio\": 0.2", "\"wal_failure_ratio\": 1.5")
+            .replace("\"wal_failure_attempt_max\": 2", "\"wal_failure_attempt_max\": 1")
+            .replace("\"1ms\"", "\"1 fortnight\"");
+
+        match SimulationConfig::from_value(serde_json::from_str(&invalid).unwrap()) {
+            Err(ConfigError::Invalid(errors)) => {
+                let fields = errors.iter().map(|e| e.field).collect::<Vec<_>>();
+                assert_eq!(vec!["wal_processing_delay", "wal_failure_ratio", "wal_failure_attempt_max"], fields);
+            },
+            other => panic!("unexpected outcome: {:?}", other),
+        }
+
+        let invalid_model = VALID.replace("\"seed\": 1,", r#""seed": 1, "wal_failure_model": {"kind": "bernoulli", "ratio": -1},"#);
+        match SimulationConfig::from_value(serde_json::from_str(&invalid_model).unwrap()) {
+            Err(ConfigError::Invalid(errors)) => assert_eq!("wal_failure_model", errors[0].field),
+            other => panic!("unexpected outcome: {:?}", other),
+        }
+
+        let slow = VALID.replace("\"seed\": 1,", r#""seed": 1, "wal_slow_ratio": 0.5, "wal_slow_factor": 0.5, "wal_failure_kinds": {"fail": -1},"#);
+        match SimulationConfig::from_value(serde_json::from_str(&slow).unwrap()) {
+            Err(ConfigError::Invalid(errors)) => {
+                assert_eq!(vec!["wal_failure_kinds", "wal_slow_factor"], errors.iter().map(|e| e.field).collect::<Vec<_>>());
+            },
+            other => panic!("unexpected outcome: {:?}", other),
+        }
+
+        let unknown = VALID.replace("\"seed\"", "\"sed\"");
+        assert!(matches!(SimulationConfig::from_value(serde_json::from_str(&unknown).unwrap()), Err(ConfigError::Syntax(_))));
+    }
+}
@@ -1 +1,11 @@
-pub mod lib;
\ No newline at end of file
+pub mod arrivals;
+pub mod backend;
+pub mod distribution;
+pub mod duration;
+pub mod faults;
+pub mod lib;
+pub mod payload;
+pub mod report;
+pub mod s3_server;
+pub mod scenario;
+pub mod trace;
@@ -0,0 +1,108 @@
+use std::io;
+use std::path::Path;
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::filesystem;
+
+/// The size of a WAL segment PostgreSQL writes by default.
+pub const DEFAULT_SEGMENT_SIZE: u64 = 16 * 1024 * 1024;
+
+/// The size of a WAL page.
+const PAGE_SIZE: usize = 8192;
+
+/// The magic number at the start of every page header.
+const PAGE_MAGIC: u16 = 0xD10D;
+
+const PAGE_HEADER_SIZE: usize = 24;
+
+/// What the generator fills the segment data files with.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PayloadKind {
+    /// Only the status files are written, the segments carry no data.
+    None,
+
+    /// Pseudo-random bytes, which do not compress.
+    #[default]
+    Random,
+
+    /// Pages laid out like PostgreSQL's WAL: a header followed by records of
+    /// varying length, and zeroes in the unused end of the page.
+    Pages,
+}
+
+/// Fills a page of the segment starting at `address`, the position of the
+/// page in the WAL stream.
+fn fill_page(page: &mut [u8], timeline: u32, address: u64, rng: &mut impl Rng) {
+    page.fill(0);
+    page[0..2].copy_from_slice(&PAGE_MAGIC.to_le_bytes());
+    page[4..8].copy_from_slice(&timeline.to_le_bytes());
+    page[8..16].copy_from_slice(&address.to_le_bytes());
+
+    // records fill most of the page, each being its length, a transaction id
+    // and a payload repeating a few bytes like table rows do.
+    let used = rng.gen_range(page.len() * 3 / 4..=page.len());
+    let mut offset = PAGE_HEADER_SIZE;
+    while offset + 8 < used {
+        let length = rng.gen_range(32..=256).min(used - offset);
+        page[offset..offset + 4].copy_from_slice(&(length as u32).to_le_bytes());
+        page[offset + 4..offset + 8].copy_from_slice(&rng.gen::<u32>().to_le_bytes());
+
+        let pattern: [u8; 4] = rng.gen();
+        for (i, b) in page[offset + 8..offset + length].iter_mut().enumerate() {
+            *b = pattern[i % pattern.len()];
+        }
+        offset += length;
+    }
+}
+
+/// Writes `size` bytes of segment data to `path`. The content only depends
+/// on the state of the given RNG, so runs with the same seed write the same
+/// segments.
+pub fn write_payload(path: &Path, size: u64, kind: PayloadKind, segment_number: u64, rng: &mut impl Rng) -> io::Result<()> {
+    let mut contents = Vec::with_capacity(size as usize);
+    let mut buffer = vec![0; PAGE_SIZE];
+    let mut written = 0;
+    while written < size {
+        let n = (size - written).min(PAGE_SIZE as u64) as usize;
+        match kind {
+            PayloadKind::Pages => fill_page(&mut buffer, 1, segment_number * size + written, rng),
+            _ => rng.fill_bytes(&mut buffer[..n]),
+        }
+        contents.extend_from_slice(&buffer[..n]);
+        written += n as u64;
+    }
+
+    let fs = filesystem::current();
+    fs.write(path, &contents)?;
+    fs.fsync(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand_chacha::ChaCha8Rng;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn deterministic_payloads() {
+        let root = TempDir::new("payload");
+
+        let write = |name: &str, kind| {
+            let path = root.join(name);
+            write_payload(&path, 3 * PAGE_SIZE as u64 + 100, kind, 7, &mut ChaCha8Rng::seed_from_u64(1)).unwrap();
+            std::fs::read(path).unwrap()
+        };
+
+        let random = write("random", PayloadKind::Random);
+        assert_eq!(3 * PAGE_SIZE + 100, random.len());
+        assert_eq!(random, write("random-again", PayloadKind::Random));
+
+        let pages = write("pages", PayloadKind::Pages);
+        assert_eq!(random.len(), pages.len());
+        assert_eq!(PAGE_MAGIC.to_le_bytes(), pages[PAGE_SIZE..PAGE_SIZE + 2]);
+        let address = u64::from_le_bytes(pages[PAGE_SIZE + 8..PAGE_SIZE + 16].try_into().unwrap());
+        assert_eq!(7 * pages.len() as u64 + PAGE_SIZE as u64, address);
+    }
+}
@@ -0,0 +1,438 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use serde::Serialize;
+
+use crate::clock;
+use crate::wal::ErrorClass;
+
+/// What happened to a segment during a simulation run.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EventKind {
+    /// The generator wrote the segment's ready file, along with `size` bytes
+    /// of segment data.
+    Generated { size: u64 },
+
+    /// The processor handed an attempt at the segment to the ThreadPool.
+    Queued,
+
+    /// The processor took an attempt at archiving the segment, `started` is
+    /// relative to the start of the run like the event's own timestamp. A
+    /// failed attempt carries the class of its error.
+    Attempted { worker: Option<u8>, started: Duration, failure: Option<ErrorClass> },
+
+    /// The processor wrote the segment's .done marker.
+    MarkerWritten { worker: Option<u8> },
+
+    /// The consumer marked the segment as done.
+    Acknowledged,
+}
+
+#[derive(Debug, Clone)]
+pub struct Event {
+    /// The time since the start of the run.
+    pub at: Duration,
+    pub segment: String,
+    pub kind: EventKind,
+}
+
+/// Records what the services do during a simulation run, to be summarised in
+/// a `RunReport` once the run is over. A disabled recorder drops everything,
+/// so that long running services do not accumulate events.
+pub struct Recorder {
+    enabled: bool,
+    started: Instant,
+    events: Mutex<Vec<Event>>,
+
+    /// The backlog seen by every processor iteration.
+    backlogs: Mutex<Vec<usize>>,
+
+    /// The busy time of every processor worker, and the lifetime of the pool.
+    workers: Mutex<Option<(Vec<Duration>, Duration)>>,
+}
+
+impl Recorder {
+    pub fn new() -> Self {
+        Recorder {
+            enabled: true,
+            started: clock::current().now(),
+            events: Mutex::new(Vec::new()),
+            backlogs: Mutex::new(Vec::new()),
+            workers: Mutex::new(None),
+        }
+    }
+
+    pub fn disabled() -> Self {
+        Recorder { enabled: false, ..Recorder::new() }
+    }
+
+    /// Returns the time since the start of the run.
+    pub fn now(&self) -> Duration {
+        clock::current().elapsed(self.started)
+    }
+
+    pub fn record(&self, segment: &str, kind: EventKind) {
+        if !self.enabled {
+            return;
+        }
+
+        let event = Event { at: self.now(), segment: segment.to_string(), kind };
+        self.events.lock().unwrap().push(event);
+    }
+
+    /// Records the number of ready files a processor iteration started with.
+    pub fn record_iteration(&self, backlog: usize) {
+        if self.enabled {
+            self.backlogs.lock().unwrap().push(backlog);
+        }
+    }
+
+    /// Records how long each processor worker was busy, out of `elapsed`.
+    pub fn record_workers(&self, busy: Vec<Duration>, elapsed: Duration) {
+        if self.enabled {
+            *self.workers.lock().unwrap() = Some((busy, elapsed));
+        }
+    }
+
+    pub fn events(&self) -> Vec<Event> {
+        self.events.lock().unwrap().clone()
+    }
+
+    pub fn report(&self) -> RunReport {
+        RunReport::new(
+            self.now(),
+            &self.events(),
+            &self.backlogs.lock().unwrap(),
+            self.workers.lock().unwrap().clone())
+    }
+}
+
+/// The rate at which a service got through the segments.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct StageThroughput {
+    pub stage: &'static str,
+    pub segments: usize,
+
+    /// The time from the first to the last event of the stage.
+    pub window_secs: f64,
+    pub per_sec: f64,
+
+    /// The amount of segment data the stage got through.
+    pub bytes: u64,
+    pub bytes_per_sec: f64,
+}
+
+/// A bucket of the latency histogram, counting the samples above the previous
+/// bucket's bound up to `le_ms`.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct HistogramBucket {
+    pub le_ms: f64,
+    pub count: usize,
+}
+
+/// The latencies from generating a segment to the consumer marking it as done.
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct LatencySummary {
+    pub samples: usize,
+    pub mean_ms: f64,
+    pub p50_ms: f64,
+    pub p90_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+    pub histogram: Vec<HistogramBucket>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct WorkerUtilisation {
+    pub worker: usize,
+    pub busy_secs: f64,
+
+    /// The share of the pool's lifetime the worker spent running jobs.
+    pub utilisation: f64,
+}
+
+/// The summary of a simulation run.
+#[derive(Serialize, Debug)]
+pub struct RunReport {
+    pub elapsed_secs: f64,
+    pub generated: usize,
+    pub attempts: usize,
+    pub failed_attempts: usize,
+
+    /// The number of failed attempts by the class of their error.
+    pub failures_by_class: BTreeMap<ErrorClass, usize>,
+    pub acknowledged: usize,
+    pub throughput: Vec<StageThroughput>,
+    pub latency: LatencySummary,
+
+    /// The number of segments by the number of attempts it took to archive them.
+    pub attempts_per_segment: BTreeMap<usize, usize>,
+    pub processor_iterations: usize,
+
+    /// The largest number of ready files a processor iteration started with.
+    pub peak_backlog: usize,
+    pub workers: Vec<WorkerUtilisation>,
+}
+
+fn millis(d: Duration) -> f64 {
+    d.as_secs_f64() * 1000.0
+}
+
+/// Returns the nearest-rank percentile of the sorted samples.
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Buckets the samples by powers of two milliseconds, up to the largest sample.
+fn histogram(sorted: &[Duration]) -> Vec<HistogramBucket> {
+    let mut buckets = Vec::new();
+    let mut samples = sorted.iter().peekable();
+    let mut bound = 1.0;
+    while samples.peek().is_some() {
+        let mut count = 0;
+        while samples.next_if(|d| millis(**d) <= bound).is_some() {
+            count += 1;
+        }
+        buckets.push(HistogramBucket { le_ms: bound, count });
+        bound *= 2.0;
+    }
+
+    buckets
+}
+
+impl LatencySummary {
+    fn new(mut samples: Vec<Duration>) -> Self {
+        if samples.is_empty() {
+            return LatencySummary::default();
+        }
+
+        samples.sort();
+        let total: Duration = samples.iter().sum();
+        LatencySummary {
+            samples: samples.len(),
+            mean_ms: millis(total) / samples.len() as f64,
+            p50_ms: millis(percentile(&samples, 50.0)),
+            p90_ms: millis(percentile(&samples, 90.0)),
+            p99_ms: millis(percentile(&samples, 99.0)),
+            max_ms: millis(samples[samples.len() - 1]),
+            histogram: histogram(&samples),
+        }
+    }
+}
+
+impl StageThroughput {
+    /// `spans` holds the start and end of every event of the stage, along
+    /// with the size of its segment.
+    fn new(stage: &'static str, spans: impl Iterator<Item = (Duration, Duration, u64)>) -> Self {
+        let mut segments = 0;
+        let mut bytes = 0;
+        let mut first = Duration::MAX;
+        let mut last = Duration::ZERO;
+        for (start, end, size) in spans {
+            segments += 1;
+            bytes += size;
+            first = first.min(start);
+            last = last.max(end);
+        }
+
+        let window_secs = last.saturating_sub(first).as_secs_f64();
+        let rate = |n: f64| if window_secs > 0.0 { n / window_secs } else { 0.0 };
+        StageThroughput {
+            stage,
+            segments,
+            window_secs,
+            per_sec: rate(segments as f64),
+            bytes,
+            bytes_per_sec: rate(bytes as f64),
+        }
+    }
+}
+
+impl RunReport {
+    pub fn new(
+        elapsed: Duration,
+        events: &[Event],
+        backlogs: &[usize],
+        workers: Option<(Vec<Duration>, Duration)>,
+    ) -> Self {
+        let mut generated_at = HashMap::new();
+        let mut sizes: HashMap<&str, u64> = HashMap::new();
+        let mut attempts: HashMap<&str, usize> = HashMap::new();
+        let mut latencies = Vec::new();
+        let mut failures_by_class = BTreeMap::new();
+        let (mut attempted, mut failed_attempts, mut acknowledged) = (0, 0, 0);
+        for event in events {
+            match event.kind {
+                EventKind::Generated { size } => {
+                    generated_at.insert(event.segment.as_str(), event.at);
+                    sizes.insert(event.segment.as_str(), size);
+                },
+                EventKind::Attempted { failure, .. } => {
+                    attempted += 1;
+                    *attempts.entry(&event.segment).or_default() += 1;
+                    if let Some(class) = failure {
+                        failed_attempts += 1;
+                        *failures_by_class.entry(class).or_default() += 1;
+                    }
+                },
+                EventKind::Acknowledged => {
+                    acknowledged += 1;
+                    // segments left over from earlier runs have no generation time.
+                    if let Some(at) = generated_at.get(event.segment.as_str()) {
+                        latencies.push(event.at.saturating_sub(*at));
+                    }
+                },
+                EventKind::Queued | EventKind::MarkerWritten { .. } => {},
+            }
+        }
+
+        let stage = |name, span: fn(&Event) -> Option<(Duration, Duration)>| {
+            StageThroughput::new(name, events.iter().filter_map(|e| {
+                span(e).map(|(start, end)| (start, end, sizes.get(e.segment.as_str()).copied().unwrap_or(0)))
+            }))
+        };
+        let throughput = vec![
+            stage("generator", |e| matches!(e.kind, EventKind::Generated { .. }).then_some((e.at, e.at))),
+            stage("processor", |e| match e.kind {
+                EventKind::Attempted { started, failure: None, .. } => Some((started, e.at)),
+                _ => None,
+            }),
+            stage("consumer", |e| matches!(e.kind, EventKind::Acknowledged).then_some((e.at, e.at))),
+        ];
+
+        let mut attempts_per_segment = BTreeMap::new();
+        for count in attempts.values() {
+            *attempts_per_segment.entry(*count).or_default() += 1;
+        }
+
+        let workers = workers
+            .map(|(busy, pool_elapsed)| busy.into_iter()
+                .enumerate()
+                .map(|(worker, busy)| WorkerUtilisation {
+                    worker,
+                    busy_secs: busy.as_secs_f64(),
+                    utilisation: if pool_elapsed.is_zero() { 0.0 } else { busy.as_secs_f64() / pool_elapsed.as_secs_f64() },
+                })
+                .collect())
+            .unwrap_or_default();
+
+        RunReport {
+            elapsed_secs: elapsed.as_secs_f64(),
+            generated: generated_at.len(),
+            attempts: attempted,
+            failed_attempts,
+            failures_by_class,
+            acknowledged,
+            throughput,
+            latency: LatencySummary::new(latencies),
+            attempts_per_segment,
+            processor_iterations: backlogs.len(),
+            peak_backlog: backlogs.iter().copied().max().unwrap_or(0),
+            workers,
+        }
+    }
+}
+
+impl fmt::Display for RunReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Simulation run: {:.3}s", self.elapsed_secs)?;
+        writeln!(f, "  generated {}, attempts {} ({} failed), acknowledged {}",
+            self.generated, self.attempts, self.failed_attempts, self.acknowledged)?;
+        if !self.failures_by_class.is_empty() {
+            let classes = self.failures_by_class.iter()
+                .map(|(class, count)| format!("{} {}", class, count))
+                .collect::<Vec<String>>();
+            writeln!(f, "  failures by class: {}", classes.join(", "))?;
+        }
+
+        writeln!(f, "Throughput:")?;
+        for stage in self.throughput.iter() {
+            writeln!(f, "  {:<10} {:>6} segments in {:.3}s, {:.1}/s, {:.1} MiB/s",
+                stage.stage, stage.segments, stage.window_secs, stage.per_sec, stage.bytes_per_sec / (1024.0 * 1024.0))?;
+        }
+
+        let latency = &self.latency;
+        writeln!(f, "End-to-end latency ({} samples):", latency.samples)?;
+        writeln!(f, "  mean {:.3}ms, p50 {:.3}ms, p90 {:.3}ms, p99 {:.3}ms, max {:.3}ms",
+            latency.mean_ms, latency.p50_ms, latency.p90_ms, latency.p99_ms, latency.max_ms)?;
+        let widest = latency.histogram.iter().map(|b| b.count).max().unwrap_or(0).max(1);
+        for bucket in latency.histogram.iter() {
+            writeln!(f, "  <= {:>8}ms {:>6} {}",
+                bucket.le_ms, bucket.count, "#".repeat(bucket.count * 40 / widest))?;
+        }
+
+        writeln!(f, "Attempts per segment:")?;
+        for (attempts, segments) in self.attempts_per_segment.iter() {
+            writeln!(f, "  {:>3} attempt(s): {}", attempts, segments)?;
+        }
+
+        writeln!(f, "Processor: {} iterations, peak backlog {}", self.processor_iterations, self.peak_backlog)?;
+        for worker in self.workers.iter() {
+            writeln!(f, "  worker {:>2}: busy {:.3}s, {:.1}% utilised",
+                worker.worker, worker.busy_secs, worker.utilisation * 100.0)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(at_ms: u64, segment: &str, kind: EventKind) -> Event {
+        Event { at: Duration::from_millis(at_ms), segment: segment.to_string(), kind }
+    }
+
+    #[test]
+    fn percentiles_and_histogram() {
+        let samples = (1..=100).map(Duration::from_millis).collect::<Vec<_>>();
+        assert_eq!(Duration::from_millis(50), percentile(&samples, 50.0));
+        assert_eq!(Duration::from_millis(99), percentile(&samples, 99.0));
+        assert_eq!(Duration::from_millis(100), percentile(&samples, 100.0));
+
+        let buckets = histogram(&samples);
+        assert_eq!(vec![1, 1, 2, 4, 8, 16, 32, 36], buckets.iter().map(|b| b.count).collect::<Vec<_>>());
+        assert_eq!(128.0, buckets.last().unwrap().le_ms);
+    }
+
+    #[test]
+    fn report_from_events() {
+        let attempt = |started_ms, failure| EventKind::Attempted {
+            worker: Some(0),
+            started: Duration::from_millis(started_ms),
+            failure,
+        };
+        let events = vec![
+            event(0, "a", EventKind::Generated { size: 100 }),
+            event(10, "b", EventKind::Generated { size: 300 }),
+            event(25, "a", attempt(20, None)),
+            event(30, "b", attempt(20, Some(ErrorClass::Retryable))),
+            event(40, "b", attempt(35, None)),
+            event(60, "a", EventKind::Acknowledged),
+            event(70, "b", EventKind::Acknowledged),
+        ];
+
+        let report = RunReport::new(
+            Duration::from_millis(80), &events, &[2, 1],
+            Some((vec![Duration::from_millis(20)], Duration::from_millis(80))));
+        assert_eq!((2, 3, 1, 2), (report.generated, report.attempts, report.failed_attempts, report.acknowledged));
+        assert_eq!(BTreeMap::from([(ErrorClass::Retryable, 1)]), report.failures_by_class);
+        assert_eq!(60.0, report.latency.p50_ms);
+        assert_eq!(60.0, report.latency.max_ms);
+        assert_eq!(BTreeMap::from([(1, 1), (2, 1)]), report.attempts_per_segment);
+        assert_eq!((2, 2), (report.processor_iterations, report.peak_backlog));
+        assert_eq!(0.25, report.workers[0].utilisation);
+
+        let processor = &report.throughput[1];
+        assert_eq!((2, 400), (processor.segments, processor.bytes));
+        assert!((processor.window_secs - 0.02).abs() < 1e-9);
+        assert!((processor.bytes_per_sec - 20_000.0).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,341 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use sha2::{Digest, Sha256};
+
+use crate::s3::{xml_elements, xml_escape};
+use crate::utilities;
+
+/// The number of objects a listing returns at most, unless asked for fewer.
+const MAX_KEYS: usize = 1000;
+
+/// An object, along with the metadata headers it was stored with.
+struct Object {
+    contents: Vec<u8>,
+    metadata: Vec<(String, String)>,
+}
+
+/// A multipart upload in progress.
+struct Upload {
+    bucket: String,
+    key: String,
+    metadata: Vec<(String, String)>,
+    parts: BTreeMap<u32, Vec<u8>>,
+}
+
+/// The buckets of the stand-in, which are created as they are written to.
+#[derive(Default)]
+struct Store {
+    /// The objects by bucket and key.
+    objects: BTreeMap<(String, String), Object>,
+    uploads: HashMap<String, Upload>,
+    next_upload_id: u64,
+}
+
+/// A request, addressing the bucket by path.
+struct Request {
+    method: String,
+    bucket: String,
+    key: String,
+    query: BTreeMap<String, String>,
+
+    /// The `x-amz-meta-` headers, by their lowercase names.
+    metadata: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn empty(status: u16) -> Self {
+        Response { status, headers: Vec::new(), body: Vec::new() }
+    }
+
+    fn xml(status: u16, body: String) -> Self {
+        Response {
+            status,
+            headers: vec![("Content-Type".to_string(), "application/xml".to_string())],
+            body: format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n{}", body).into_bytes(),
+        }
+    }
+
+    fn error(status: u16, code: &str, message: &str) -> Self {
+        Response::xml(status, format!("<Error><Code>{}</Code><Message>{}</Message></Error>", code, xml_escape(message)))
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            204 => "No Content",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            _ => "Not Implemented",
+        }
+    }
+}
+
+fn etag(contents: &[u8]) -> String {
+    format!("\"{}\"", &utilities::hex(&Sha256::digest(contents))[..32])
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes.get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            },
+            None => {
+                decoded.push(if bytes[i] == b'+' { b' ' } else { bytes[i] });
+                i += 1;
+            },
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Lists the objects of the bucket in the order of their keys, a page at a
+/// time. The continuation token is the last key of the previous page.
+fn list_objects(store: &Store, request: &Request) -> Response {
+    let prefix = request.query.get("prefix").cloned().unwrap_or_default();
+    let max_keys = request.query.get("max-keys")
+        .and_then(|m| m.parse::<usize>().ok())
+        .map_or(MAX_KEYS, |m| m.min(MAX_KEYS));
+    let after = request.query.get("continuation-token").cloned().unwrap_or_default();
+
+    let mut matching = store.objects.iter()
+        .filter(|((bucket, key), _)| *bucket == request.bucket && key.starts_with(&prefix) && *key > after)
+        .map(|((_, key), object)| (key, object.contents.len()));
+    let page = matching.by_ref().take(max_keys).collect::<Vec<_>>();
+    let truncated = matching.next().is_some();
+
+    let mut body = format!(
+        "<ListBucketResult xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\"><Name>{}</Name><Prefix>{}</Prefix><KeyCount>{}</KeyCount><MaxKeys>{}</MaxKeys><IsTruncated>{}</IsTruncated>",
+        xml_escape(&request.bucket), xml_escape(&prefix), page.len(), max_keys, truncated);
+    if let (true, Some((last, _))) = (truncated, page.last()) {
+        body.push_str(&format!("<NextContinuationToken>{}</NextContinuationToken>", xml_escape(last)));
+    }
+    for (key, size) in page.iter() {
+        body.push_str(&format!("<Contents><Key>{}</Key><Size>{}</Size></Contents>", xml_escape(key), size));
+    }
+    body.push_str("</ListBucketResult>");
+    Response::xml(200, body)
+}
+
+/// Assembles the object out of the parts listed in the request.
+fn complete_upload(store: &mut Store, upload_id: &str, request: &Request) -> Response {
+    let Some(upload) = store.uploads.get(upload_id) else {
+        return Response::error(404, "NoSuchUpload", "the upload does not exist");
+    };
+
+    let body = String::from_utf8_lossy(&request.body);
+    let mut contents = Vec::new();
+    for part_number in xml_elements(&body, "PartNumber") {
+        match part_number.parse().ok().and_then(|n: u32| upload.parts.get(&n)) {
+            Some(part) => contents.extend_from_slice(part),
+            None => return Response::error(400, "InvalidPart", &format!("part {} was not uploaded", part_number)),
+        }
+    }
+
+    let upload = store.uploads.remove(upload_id).expect("the upload exists");
+    let etag = etag(&contents);
+    store.objects.insert((upload.bucket.clone(), upload.key.clone()), Object { contents, metadata: upload.metadata });
+    Response::xml(200, format!(
+        "<CompleteMultipartUploadResult><Bucket>{}</Bucket><Key>{}</Key><ETag>{}</ETag></CompleteMultipartUploadResult>",
+        xml_escape(&upload.bucket), xml_escape(&upload.key), xml_escape(&etag)))
+}
+
+/// Routes a request to the operation of the S3 API it stands for.
+fn handle(store: &Mutex<Store>, request: &Request) -> Response {
+    let mut store = store.lock().expect("the object store is poisoned");
+    let object = (request.bucket.clone(), request.key.clone());
+    let upload_id = request.query.get("uploadId");
+
+    match (request.method.as_str(), request.key.is_empty(), upload_id) {
+        (_, _, _) if request.bucket.is_empty() => Response::error(501, "NotImplemented", "buckets are not listed"),
+        ("GET", true, _) if request.query.get("list-type").is_some_and(|t| t == "2") => list_objects(&store, request),
+        (_, true, _) => Response::error(501, "NotImplemented", "only ListObjectsV2 is supported on buckets"),
+
+        ("PUT", false, Some(upload_id)) => {
+            let part_number = request.query.get("partNumber").and_then(|n| n.parse::<u32>().ok());
+            match (store.uploads.get_mut(upload_id), part_number) {
+                (None, _) => Response::error(404, "NoSuchUpload", "the upload does not exist"),
+                (Some(_), None) => Response::error(400, "InvalidArgument", "the part number is missing"),
+                (Some(upload), Some(part_number)) => {
+                    upload.parts.insert(part_number, request.body.clone());
+                    Response { headers: vec![("ETag".to_string(), etag(&request.body))], ..Response::empty(200) }
+                },
+            }
+        },
+        ("PUT", false, None) => {
+            store.objects.insert(object, Object { contents: request.body.clone(), metadata: request.metadata.clone() });
+            Response { headers: vec![("ETag".to_string(), etag(&request.body))], ..Response::empty(200) }
+        },
+        ("POST", false, None) if request.query.contains_key("uploads") => {
+            store.next_upload_id += 1;
+            let upload_id = format!("upload-{}", store.next_upload_id);
+            let upload = Upload { bucket: object.0, key: object.1, metadata: request.metadata.clone(), parts: BTreeMap::new() };
+            store.uploads.insert(upload_id.clone(), upload);
+            Response::xml(200, format!(
+                "<InitiateMultipartUploadResult><Bucket>{}</Bucket><Key>{}</Key><UploadId>{}</UploadId></InitiateMultipartUploadResult>",
+                xml_escape(&request.bucket), xml_escape(&request.key), upload_id))
+        },
+        ("POST", false, Some(upload_id)) => complete_upload(&mut store, upload_id, request),
+        ("DELETE", false, Some(upload_id)) => match store.uploads.remove(upload_id) {
+            Some(_) => Response::empty(204),
+            None => Response::error(404, "NoSuchUpload", "the upload does not exist"),
+        },
+        ("DELETE", false, None) => {
+            store.objects.remove(&object);
+            Response::empty(204)
+        },
+        ("GET" | "HEAD", false, None) => match store.objects.get(&object) {
+            Some(Object { contents, metadata }) => Response {
+                status: 200,
+                headers: [("ETag".to_string(), etag(contents)), ("Content-Type".to_string(), "application/octet-stream".to_string())]
+                    .into_iter()
+                    .chain(metadata.iter().cloned())
+                    .collect(),
+                body: contents.clone(),
+            },
+            None => Response::error(404, "NoSuchKey", "the object does not exist"),
+        },
+        _ => Response::error(405, "MethodNotAllowed", "the method is not allowed on this resource"),
+    }
+}
+
+fn read_request(stream: &TcpStream) -> io::Result<Request> {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    let mut content_length = 0;
+    let mut metadata = Vec::new();
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        if let Some((name, value)) = header.split_once(':') {
+            let name = name.trim().to_ascii_lowercase();
+            if name == "content-length" {
+                content_length = value.trim().parse()
+                    .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("malformed header {:?}", header)))?;
+            } else if name.starts_with("x-amz-meta-") {
+                metadata.push((name, value.trim().to_string()));
+            }
+        }
+        header.clear();
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let target = parts.next().unwrap_or_default();
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let (bucket, key) = path.trim_start_matches('/').split_once('/').unwrap_or((path.trim_start_matches('/'), ""));
+    let query = query.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(name), percent_decode(value))
+        })
+        .collect();
+
+    Ok(Request { method, bucket: percent_decode(bucket), key: percent_decode(key), query, metadata, body })
+}
+
+fn serve_connection(stream: TcpStream, store: &Mutex<Store>) -> io::Result<()> {
+    let request = read_request(&stream)?;
+    let response = handle(store, &request);
+
+    let mut head = format!("HTTP/1.1 {} {}\r\n", response.status, response.reason());
+    for (name, value) in response.headers.iter() {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str(&format!("Content-Length: {}\r\nConnection: close\r\n\r\n", response.body.len()));
+
+    // a HEAD response tells the length of the object without its contents.
+    let mut stream = &stream;
+    stream.write_all(head.as_bytes())?;
+    if request.method != "HEAD" {
+        stream.write_all(&response.body)?;
+    }
+    stream.flush()
+}
+
+fn s3_server_internal(listener: TcpListener, store: Arc<Mutex<Store>>) {
+    for stream in listener.incoming() {
+        let store = store.clone();
+        let result = stream.map(|stream| thread::spawn(move || {
+            if let Err(e) = serve_connection(stream, &store) {
+                println!("Failed to serve an S3 request: {}", e);
+            }
+        }));
+        if let Err(e) = result {
+            println!("Failed to accept an S3 connection: {}", e);
+        }
+    }
+}
+
+/// Starts a stand-in for an S3-compatible object store, which keeps the
+/// objects in memory so that the S3 destination can be exercised without
+/// network access. The signatures of the requests and the sizes of the parts
+/// are not checked. Returns the address it listens on.
+pub fn service_startup(address: &str) -> io::Result<(SocketAddr, JoinHandle<()>)> {
+    let listener = TcpListener::bind(address)?;
+    let address = listener.local_addr()?;
+    let store = Arc::new(Mutex::new(Store::default()));
+    Ok((address, thread::spawn(move || {
+        s3_server_internal(listener, store);
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: &str, key: &str, query: &[(&str, &str)], body: &[u8]) -> Request {
+        Request {
+            method: method.to_string(),
+            bucket: "wal".to_string(),
+            key: key.to_string(),
+            query: query.iter().map(|(n, v)| (n.to_string(), v.to_string())).collect(),
+            metadata: Vec::new(),
+            body: body.to_vec(),
+        }
+    }
+
+    #[test]
+    fn multipart_upload() {
+        let store = Mutex::new(Store::default());
+        let initiated = handle(&store, &request("POST", "segment", &[("uploads", "")], b""));
+        let upload_id = xml_elements(std::str::from_utf8(&initiated.body).unwrap(), "UploadId")[0].to_string();
+
+        for (part_number, part) in [("2", "world"), ("1", "hello ")] {
+            let query = [("uploadId", upload_id.as_str()), ("partNumber", part_number)];
+            assert_eq!(200, handle(&store, &request("PUT", "segment", &query, part.as_bytes())).status);
+        }
+        let missing_part = b"<CompleteMultipartUpload><Part><PartNumber>3</PartNumber></Part></CompleteMultipartUpload>";
+        assert_eq!(400, handle(&store, &request("POST", "segment", &[("uploadId", &upload_id)], missing_part)).status);
+
+        let parts = b"<CompleteMultipartUpload><Part><PartNumber>1</PartNumber></Part><Part><PartNumber>2</PartNumber></Part></CompleteMultipartUpload>";
+        assert_eq!(200, handle(&store, &request("POST", "segment", &[("uploadId", &upload_id)], parts)).status);
+        assert_eq!(b"hello world".to_vec(), handle(&store, &request("GET", "segment", &[], b"")).body);
+        assert_eq!(404, handle(&store, &request("DELETE", "segment", &[("uploadId", &upload_id)], b"")).status);
+        assert_eq!("a b/c", percent_decode("a%20b%2Fc"));
+    }
+}
@@ -0,0 +1,312 @@
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use serde::Deserialize;
+
+use crate::clock;
+use crate::destination::Destination;
+use crate::simulation::duration;
+
+/// A period of a scenario, times are relative to the start of the run.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Phase {
+    /// Every request to the destination fails as it is unreachable.
+    DestinationOutage {
+        #[serde(deserialize_with = "duration::deserialize")]
+        from: Duration,
+        #[serde(deserialize_with = "duration::deserialize")]
+        until: Duration,
+    },
+
+    /// The generator creates failing WAL files at the given ratio.
+    FailureSpike {
+        #[serde(deserialize_with = "duration::deserialize")]
+        from: Duration,
+        #[serde(deserialize_with = "duration::deserialize")]
+        until: Duration,
+        failure_ratio: f64,
+    },
+
+    /// The generator creates WAL files with the given delay between them.
+    GenerationBurst {
+        #[serde(deserialize_with = "duration::deserialize")]
+        from: Duration,
+        #[serde(deserialize_with = "duration::deserialize")]
+        until: Duration,
+        #[serde(deserialize_with = "duration::deserialize")]
+        generation_delay: Duration,
+    },
+
+    /// Every write of the generator and every archive attempt takes the given
+    /// latency on top of its usual duration.
+    SlowDisk {
+        #[serde(deserialize_with = "duration::deserialize")]
+        from: Duration,
+        #[serde(deserialize_with = "duration::deserialize")]
+        until: Duration,
+        #[serde(deserialize_with = "duration::deserialize")]
+        latency: Duration,
+    },
+
+    /// The processor loses its in-memory state and stays down for `downtime`.
+    ProcessorRestart {
+        #[serde(deserialize_with = "duration::deserialize")]
+        at: Duration,
+        #[serde(deserialize_with = "duration::deserialize")]
+        downtime: Duration,
+    },
+}
+
+impl Phase {
+    /// Returns whether the phase is in effect at `t`; a restart is never in
+    /// effect as it only happens once.
+    fn active_at(&self, t: Duration) -> bool {
+        match self {
+            Phase::DestinationOutage { from, until }
+            | Phase::FailureSpike { from, until, .. }
+            | Phase::GenerationBurst { from, until, .. }
+            | Phase::SlowDisk { from, until, .. } => (*from..*until).contains(&t),
+            Phase::ProcessorRestart { .. } => false,
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        match self {
+            Phase::DestinationOutage { from, until }
+            | Phase::FailureSpike { from, until, .. }
+            | Phase::GenerationBurst { from, until, .. }
+            | Phase::SlowDisk { from, until, .. } if from >= until => {
+                Err(format!("must end after it starts, got from {} until {}",
+                    duration::format_duration(from), duration::format_duration(until)))
+            },
+            Phase::FailureSpike { failure_ratio, .. } if !(0.0..=1.0).contains(failure_ratio) => {
+                Err(format!("failure_ratio must be between 0 and 1, got {}", failure_ratio))
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScenarioFile {
+    phases: Vec<Phase>,
+}
+
+/// A script of phases the generator and the processor follow during a
+/// simulation run, to rehearse incidents. The default scenario has no phases.
+#[derive(Debug)]
+pub struct Scenario {
+    phases: Vec<Phase>,
+    started: Instant,
+}
+
+impl Default for Scenario {
+    fn default() -> Self {
+        Scenario::new(Vec::new())
+    }
+}
+
+impl Scenario {
+    /// Starts the scenario's clock.
+    pub fn new(phases: Vec<Phase>) -> Self {
+        Scenario { phases, started: clock::current().now() }
+    }
+
+    /// Reads the phases from a TOML or JSON file, e.g.
+    ///
+    /// ```toml
+    /// [[phases]]
+    /// kind = "destination_outage"
+    /// from = "1s"
+    /// until = "3s"
+    /// ```
+    pub fn load(path: &str) -> Result<Vec<Phase>, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("failed to read the scenario {:?}: {}", path, e))?;
+        let file: ScenarioFile = if path.ends_with(".toml") {
+            toml::from_str(&contents).map_err(|e| format!("failed to parse the scenario {:?}: {}", path, e))?
+        } else {
+            serde_json::from_str(&contents).map_err(|e| format!("failed to parse the scenario {:?}: {}", path, e))?
+        };
+
+        for (n, phase) in file.phases.iter().enumerate() {
+            phase.validate().map_err(|message| format!("phase {} of the scenario {:?} {}", n + 1, path, message))?;
+        }
+        Ok(file.phases)
+    }
+
+    /// Returns the time since the scenario started.
+    pub fn elapsed(&self) -> Duration {
+        clock::current().elapsed(self.started)
+    }
+
+    fn active(&self) -> impl Iterator<Item = &Phase> {
+        let t = self.elapsed();
+        self.phases.iter().filter(move |p| p.active_at(t))
+    }
+
+    pub fn destination_down(&self) -> bool {
+        self.active().any(|p| matches!(p, Phase::DestinationOutage { .. }))
+    }
+
+    /// The failure ratio of an ongoing spike, the highest one if they overlap.
+    pub fn failure_ratio(&self) -> Option<f64> {
+        self.active()
+            .filter_map(|p| match p {
+                Phase::FailureSpike { failure_ratio, .. } => Some(*failure_ratio),
+                _ => None,
+            })
+            .reduce(f64::max)
+    }
+
+    /// The generation delay of an ongoing burst, the shortest one if they overlap.
+    pub fn generation_delay(&self) -> Option<Duration> {
+        self.active()
+            .filter_map(|p| match p {
+                Phase::GenerationBurst { generation_delay, .. } => Some(*generation_delay),
+                _ => None,
+            })
+            .min()
+    }
+
+    /// The latency added to every disk access, zero unless the disk is slow.
+    pub fn disk_latency(&self) -> Duration {
+        self.active()
+            .map(|p| match p {
+                Phase::SlowDisk { latency, .. } => *latency,
+                _ => Duration::ZERO,
+            })
+            .sum()
+    }
+
+    /// Returns the downtime of the restarts due since the first `done`
+    /// restarts, along with the number of restarts due so far.
+    pub fn restarts_due(&self, done: usize) -> (usize, Duration) {
+        let t = self.elapsed();
+        let mut restarts = self.phases.iter()
+            .filter_map(|p| match p {
+                Phase::ProcessorRestart { at, downtime } if *at <= t => Some((*at, *downtime)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        restarts.sort();
+
+        let downtime = restarts.iter().skip(done).map(|(_, downtime)| *downtime).sum();
+        (restarts.len(), downtime)
+    }
+}
+
+/// Takes the destination down during the outages of the scenario, on top of
+/// whatever the destination does on its own.
+pub struct ScenarioDestination {
+    inner: Arc<dyn Destination>,
+    scenario: Arc<Scenario>,
+}
+
+impl ScenarioDestination {
+    pub fn new(inner: Arc<dyn Destination>, scenario: Arc<Scenario>) -> Self {
+        ScenarioDestination { inner, scenario }
+    }
+
+    fn reachable(&self) -> io::Result<()> {
+        if self.scenario.destination_down() {
+            return Err(io::Error::new(io::ErrorKind::ConnectionRefused, "the destination is unavailable"));
+        }
+        Ok(())
+    }
+}
+
+impl Destination for ScenarioDestination {
+    fn put(&self, segment_name: &str, source: &Path) -> io::Result<()> {
+        self.reachable()?;
+        self.inner.put(segment_name, source)
+    }
+
+    fn get(&self, segment_name: &str) -> io::Result<Box<dyn Read>> {
+        self.reachable()?;
+        self.inner.get(segment_name)
+    }
+
+    fn list(&self) -> io::Result<Vec<String>> {
+        self.reachable()?;
+        self.inner.list()
+    }
+
+    fn overwrites(&self) -> bool {
+        self.inner.overwrites()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::destination::LocalDirectory;
+    use crate::filesystem::{self, FileSystem, MemoryFileSystem};
+
+    #[test]
+    fn parse_phases() {
+        let phases: ScenarioFile = toml::from_str(r#"
+            [[phases]]
+            kind = "destination_outage"
+            from = "1s"
+            until = "3s"
+
+            [[phases]]
+            kind = "failure_spike"
+            from = "0s"
+            until = "2s"
+            failure_ratio = 0.9
+
+            [[phases]]
+            kind = "processor_restart"
+            at = "500ms"
+            downtime = "100ms"
+        "#).unwrap();
+        assert_eq!(Phase::DestinationOutage { from: Duration::from_secs(1), until: Duration::from_secs(3) }, phases.phases[0]);
+        assert!(phases.phases.iter().all(|p| p.validate().is_ok()));
+
+        assert!(phases.phases[0].active_at(Duration::from_secs(2)));
+        assert!(!phases.phases[0].active_at(Duration::from_secs(3)));
+        assert!(!phases.phases[2].active_at(Duration::from_millis(500)));
+
+        let inverted = Phase::SlowDisk { from: Duration::from_secs(2), until: Duration::from_secs(1), latency: Duration::ZERO };
+        assert!(inverted.validate().is_err());
+        assert!(toml::from_str::<ScenarioFile>("[[phases]]\nkind = \"meteor_strike\"\n").is_err());
+    }
+
+    #[test]
+    fn scenario_at_start() {
+        let scenario = Scenario::new(vec![
+            Phase::FailureSpike { from: Duration::ZERO, until: Duration::from_secs(60), failure_ratio: 0.5 },
+            Phase::FailureSpike { from: Duration::ZERO, until: Duration::from_secs(60), failure_ratio: 0.8 },
+            Phase::SlowDisk { from: Duration::ZERO, until: Duration::from_secs(60), latency: Duration::from_millis(3) },
+            Phase::ProcessorRestart { at: Duration::ZERO, downtime: Duration::from_millis(10) },
+            Phase::ProcessorRestart { at: Duration::from_secs(60), downtime: Duration::from_millis(10) },
+        ]);
+
+        assert_eq!(Some(0.8), scenario.failure_ratio());
+        assert_eq!(None, scenario.generation_delay());
+        assert!(!scenario.destination_down());
+        assert_eq!(Duration::from_millis(3), scenario.disk_latency());
+        assert_eq!((1, Duration::from_millis(10)), scenario.restarts_due(0));
+        assert_eq!((1, Duration::ZERO), scenario.restarts_due(1));
+    }
+
+    #[test]
+    fn outages_go_through_the_destination() {
+        let fs = Arc::new(MemoryFileSystem::default());
+        let outage = Phase::DestinationOutage { from: Duration::ZERO, until: Duration::from_secs(60) };
+        let (down, up) = filesystem::with(fs.clone(), || {
+            fs.write(Path::new("segment"), b"data").unwrap();
+            let destination = |phases| ScenarioDestination::new(Arc::new(LocalDirectory::new("archive")), Arc::new(Scenario::new(phases)));
+            (destination(vec![outage]).put("segment", Path::new("segment")), destination(Vec::new()).put("segment", Path::new("segment")))
+        });
+
+        assert_eq!(io::ErrorKind::ConnectionRefused, down.unwrap_err().kind());
+        assert!(up.is_ok());
+    }
+}
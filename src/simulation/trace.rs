@@ -0,0 +1,161 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::simulation::report::{Event, EventKind};
+
+const PID: u32 = 1;
+const GENERATOR_TID: u32 = 1;
+const PROCESSOR_TID: u32 = 2;
+const CONSUMER_TID: u32 = 3;
+
+/// The thread id of the ThreadPool worker N is `WORKER_TID_BASE + N`.
+const WORKER_TID_BASE: u32 = 10;
+
+/// An entry of the Chrome trace event format, as understood by Perfetto and
+/// chrome://tracing.
+#[derive(Serialize, Debug)]
+struct TraceEvent {
+    name: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cat: Option<&'static str>,
+
+    /// The phase: "X" for a complete event, "i" for an instant one and "M"
+    /// for metadata.
+    ph: &'static str,
+
+    /// Microseconds since the start of the run.
+    ts: f64,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dur: Option<f64>,
+
+    /// The scope of an instant event.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    s: Option<&'static str>,
+
+    pid: u32,
+    tid: u32,
+    args: Value,
+}
+
+fn micros(d: Duration) -> f64 {
+    d.as_secs_f64() * 1_000_000.0
+}
+
+fn worker_tid(worker: Option<u8>) -> u32 {
+    worker.map_or(PROCESSOR_TID, |w| WORKER_TID_BASE + w as u32)
+}
+
+fn instant(name: &str, cat: &'static str, at: Duration, tid: u32, segment: &str) -> TraceEvent {
+    TraceEvent {
+        name: name.to_string(),
+        cat: Some(cat),
+        ph: "i",
+        ts: micros(at),
+        dur: None,
+        s: Some("t"),
+        pid: PID,
+        tid,
+        args: json!({ "segment": segment }),
+    }
+}
+
+fn metadata(name: &str, tid: u32, args: Value) -> TraceEvent {
+    TraceEvent {
+        name: name.to_string(),
+        cat: None,
+        ph: "M",
+        ts: 0.0,
+        dur: None,
+        s: None,
+        pid: PID,
+        tid,
+        args,
+    }
+}
+
+fn trace_event(event: &Event) -> TraceEvent {
+    let segment = event.segment.as_str();
+    match event.kind {
+        EventKind::Generated { .. } => instant("generated", "generator", event.at, GENERATOR_TID, segment),
+        EventKind::Queued => instant("queued", "processor", event.at, PROCESSOR_TID, segment),
+        EventKind::Attempted { worker, started, failure } => TraceEvent {
+            name: if failure.is_none() { "attempt" } else { "failed attempt" }.to_string(),
+            cat: Some("processor"),
+            ph: "X",
+            ts: micros(started),
+            dur: Some(micros(event.at.saturating_sub(started))),
+            s: None,
+            pid: PID,
+            tid: worker_tid(worker),
+            args: json!({ "segment": segment, "success": failure.is_none(), "error_class": failure }),
+        },
+        EventKind::MarkerWritten { worker } => instant("marker written", "processor", event.at, worker_tid(worker), segment),
+        EventKind::Acknowledged => instant("acknowledged", "consumer", event.at, CONSUMER_TID, segment),
+    }
+}
+
+/// Converts the events of a simulation run into the Chrome trace event JSON
+/// format, with a thread per service and per ThreadPool worker.
+pub fn chrome_trace(events: &[Event]) -> Value {
+    let trace_events = events.iter().map(trace_event).collect::<Vec<_>>();
+
+    let mut threads = BTreeMap::from([
+        (GENERATOR_TID, "generator".to_string()),
+        (PROCESSOR_TID, "processor".to_string()),
+        (CONSUMER_TID, "consumer".to_string()),
+    ]);
+    for e in trace_events.iter().filter(|e| e.tid >= WORKER_TID_BASE) {
+        threads.entry(e.tid).or_insert_with(|| format!("worker {}", e.tid - WORKER_TID_BASE));
+    }
+
+    let mut all = vec![metadata("process_name", 0, json!({ "name": "simulation" }))];
+    for (tid, name) in threads {
+        all.push(metadata("thread_name", tid, json!({ "name": name })));
+        all.push(metadata("thread_sort_index", tid, json!({ "sort_index": tid })));
+    }
+    all.extend(trace_events);
+
+    json!({
+        "traceEvents": all,
+        "displayTimeUnit": "ms",
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wal::ErrorClass;
+
+    #[test]
+    fn trace_events_per_worker() {
+        let event = |at_ms, kind| Event {
+            at: Duration::from_millis(at_ms),
+            segment: "000000010000000000000001".to_string(),
+            kind,
+        };
+        let events = vec![
+            event(1, EventKind::Generated { size: 0 }),
+            event(2, EventKind::Queued),
+            event(7, EventKind::Attempted { worker: Some(3), started: Duration::from_millis(3), failure: Some(ErrorClass::Unknown) }),
+            event(9, EventKind::Acknowledged),
+        ];
+
+        let trace = chrome_trace(&events);
+        let trace_events = trace["traceEvents"].as_array().unwrap();
+        assert!(trace_events.iter().any(|e| e["ph"] == "M" && e["tid"] == 13 && e["args"]["name"] == "worker 3"));
+
+        let attempt = trace_events.iter().find(|e| e["ph"] == "X").unwrap();
+        assert_eq!("failed attempt", attempt["name"]);
+        assert_eq!(13, attempt["tid"]);
+        assert_eq!(3000.0, attempt["ts"]);
+        assert_eq!(4000.0, attempt["dur"]);
+        assert_eq!("unknown", attempt["args"]["error_class"]);
+
+        let acknowledged = trace_events.last().unwrap();
+        assert_eq!(("i", 3), (acknowledged["ph"].as_str().unwrap(), acknowledged["tid"].as_u64().unwrap()));
+    }
+}
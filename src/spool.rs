@@ -0,0 +1,59 @@
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use crate::filesystem;
+use crate::utilities;
+use crate::wal::WalFile;
+
+/// Returns whether the segment is acknowledged as archived. The processor
+/// leaves a .done marker in the source directory, which the consumer later
+/// turns into a .done status file, so either of them acts as the acknowledgement.
+/// Both hold the status of the segment, which has to record it as archived;
+/// an empty or unreadable marker does not count.
+pub fn is_acknowledged(segment_name: &str) -> bool {
+    let layout = utilities::layout();
+    [&layout.source_dir, &layout.status_dir].iter()
+        .filter_map(|dir| filesystem::current().read(Path::new(&format!("{}/{}.done", dir, segment_name))).ok())
+        .filter_map(|contents| WalFile::parse(&contents).ok())
+        .any(|w| w.is_archived())
+}
+
+fn error_file(segment_name: &str) -> PathBuf {
+    Path::new(&utilities::layout().error_spool_dir).join(format!("{}.error", segment_name))
+}
+
+/// Records the reason of the latest failed archive attempt for the segment,
+/// so that it can be reported back on the next archive-push call.
+pub fn record_error(segment_name: &str, message: &str) -> io::Result<()> {
+    let fs = filesystem::current();
+    fs.create_dir_all(Path::new(&utilities::layout().error_spool_dir))?;
+    fs.write(&error_file(segment_name), message.as_bytes())
+}
+
+/// Removes the recorded failure for the segment, if there is any.
+pub fn clear_error(segment_name: &str) -> io::Result<()> {
+    match filesystem::current().remove(&error_file(segment_name)) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Returns the recorded failure for the segment, if there is any, leaving
+/// it in the spool.
+pub fn read_error(segment_name: &str) -> io::Result<Option<String>> {
+    match filesystem::current().read(&error_file(segment_name)) {
+        Ok(message) => Ok(Some(String::from_utf8_lossy(&message).into_owned())),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Returns and removes the recorded failure for the segment. The error is
+/// reported once, after which the segment is attempted again.
+pub fn take_error(segment_name: &str) -> io::Result<Option<String>> {
+    let message = read_error(segment_name)?;
+    if message.is_some() {
+        clear_error(segment_name)?;
+    }
+    Ok(message)
+}
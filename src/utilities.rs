@@ -1,19 +1,46 @@
-use std::{fs, io};
-use std::fs::DirEntry;
+use std::io::{self, Read};
+use std::path::Path;
+use sha2::{Digest, Sha256};
+use std::cell::Cell;
 use std::thread::{self, JoinHandle};
 use std::sync::mpsc::{self, Sender, Receiver};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::clock;
+use crate::config::Layout;
+use crate::filesystem;
 
 pub(crate) const SOURCE_DIR: &'static str = "file-source";
 pub(crate) const STATUS_DIR: &'static str = "file-source/file-status";
 pub(crate) const SIMULATION_DIR: &'static str = "src/simulation";
+pub(crate) const ARCHIVE_DIR: &'static str = "file-source/archive";
+pub(crate) const ERROR_SPOOL_DIR: &'static str = "file-source/error-spool";
+pub(crate) const QUARANTINE_DIR: &'static str = "file-source/quarantine";
+pub(crate) const RECONCILIATION_LOG: &'static str = "file-source/reconciliation.log";
+
+/// The layout in effect, set once at startup from the configuration.
+static LAYOUT: OnceLock<Layout> = OnceLock::new();
+
+/// Returns the layout in effect, the default one unless `set_layout` was called.
+pub fn layout() -> &'static Layout {
+    LAYOUT.get_or_init(Layout::default)
+}
+
+/// Sets the layout used by every service, it can only be set before first use.
+pub fn set_layout(layout: Layout) {
+    if LAYOUT.set(layout).is_err() {
+        println!("The layout is already in use, ignoring the configured one.");
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct FileEntry {
     /// file's name without the extension string.
     pub file_name: String,
 
-    /// The part after the first "."
+    /// The part of the file's name after its first "."
     pub file_extension: String,
 
     /// The full path of the file.
@@ -21,13 +48,15 @@ pub struct FileEntry {
 }
 
 impl FileEntry {
-    pub fn new(entry: &DirEntry) -> Self {
-        let full_path = entry.path().into_os_string().into_string().unwrap();
-        let (_, extension) = full_path.split_once(".").unwrap();
-        let file_name: &str = full_path.split("/").last().unwrap();
-        let file_name = file_name.split(".").take(1).collect::<String>();
+    /// Constructs the entry from a path, without requiring the file to be
+    /// discovered by walking a directory.
+    pub fn from_path(full_path: String) -> Self {
+        let file_name = Path::new(&full_path).file_name().and_then(|f| f.to_str()).unwrap_or_default();
+        // only the extension of the status file is cut off, the names of history
+        // and backup files have dots of their own.
+        let (file_name, extension) = file_name.rsplit_once('.').unwrap_or((file_name, ""));
         FileEntry {
-            file_name,
+            file_name: String::from(file_name),
             file_extension: String::from(extension),
             full_path
         }
@@ -35,37 +64,76 @@ impl FileEntry {
 }
 
 pub fn get_ready_files() -> Result<Vec<FileEntry>, std::io::Error> {
-    let files = walk_directory(STATUS_DIR, &|x: &str| x.ends_with(".ready"))?;
+    let files = walk_directory(&layout().status_dir, &|x: &str| x.ends_with(".ready"))?;
 
     Ok(files)
 }
 
 pub fn get_done_files() -> Result<Vec<FileEntry>, std::io::Error>  {
-    let files = walk_directory(SOURCE_DIR, &|x: &str| x.ends_with(".done"))?;
+    let files = walk_directory(&layout().source_dir, &|x: &str| x.ends_with(".done"))?;
 
     Ok(files)
 }
 
 pub fn walk_directory(path: &str, fn_filter: impl Fn(&str) -> bool) -> Result<Vec<FileEntry>, std::io::Error> {
-    let entries = fs::read_dir(&path)?;
+    let entries = filesystem::current().read_dir(Path::new(path))?;
 
     let mut files: Vec<FileEntry> = Vec::new();
     for entry in entries {
-        let entry = entry?;
-        let metadata = entry.metadata()?;
-        if metadata.is_file() {
-            let file_name = entry.file_name();
-            if fn_filter(file_name.to_str().unwrap()) {
-                files.push(FileEntry::new(&entry));
-            }
+        let file_name = entry.file_name().unwrap().to_str().unwrap();
+        if fn_filter(file_name) {
+            files.push(FileEntry::from_path(entry.into_os_string().into_string().unwrap()));
         }
     }
 
     Ok(files)
 }
 
+/// Reads the given reader to its end, and returns the number of bytes read
+/// along with their hex encoded SHA-256 digest.
+pub fn sha256_digest(mut reader: impl Read) -> io::Result<(u64, String)> {
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    let mut size = 0;
+    loop {
+        let n = reader.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+        size += n as u64;
+    }
+
+    Ok((size, hex(&hasher.finalize())))
+}
+
+/// Formats the bytes as lowercase hexadecimal.
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Starts a service thread, which runs against the same file system and clock
+/// as the calling thread, including ones put in effect with `filesystem::with`
+/// and `clock::with`.
+pub fn spawn<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> JoinHandle<T> {
+    let file_system = filesystem::current();
+    let clock = clock::current();
+    thread::spawn(move || filesystem::with(file_system, || clock::with(clock, f)))
+}
+
 type Job<T> = Box<dyn FnOnce() -> T + Send + 'static>;
 
+thread_local! {
+    /// The id of the pool worker running on the current thread.
+    static WORKER_ID: Cell<Option<u8>> = const { Cell::new(None) };
+}
+
+/// Returns the id of the ThreadPool worker the caller runs on, if any.
+pub fn current_worker_id() -> Option<u8> {
+    WORKER_ID.with(|id| id.get())
+}
+
+#[allow(dead_code)]
 struct Worker {
     id: u8,
     thread: JoinHandle<()>,
@@ -74,18 +142,25 @@ struct Worker {
 impl Worker {
     fn new<T>(id: u8,
            sender: Sender<T>,
-           pool_receiver: Arc<Mutex<Receiver<Job<T>>>>) -> Worker
+           pool_receiver: Arc<Mutex<Receiver<Job<T>>>>,
+           busy_nanos: Arc<AtomicU64>) -> Worker
            where T: Send + 'static {
-        let thread = thread::spawn(move || loop {
-            let job = pool_receiver.lock().unwrap().recv();
-            if job.is_err() {
-                println!("Failed to acquire a job, terminating.");
-                return;
+        let thread = spawn(move || {
+            WORKER_ID.with(|worker_id| worker_id.set(Some(id)));
+            loop {
+                let job = pool_receiver.lock().unwrap().recv();
+                if job.is_err() {
+                    println!("Failed to acquire a job, terminating.");
+                    return;
+                }
+
+                let job = job.unwrap();
+                let clock = clock::current();
+                let started = clock.now();
+                let res: T = job();
+                busy_nanos.fetch_add(clock.elapsed(started).as_nanos() as u64, Ordering::Relaxed);
+                sender.send(res).expect("Failed to send a result back to the pool");
             }
-
-            let job = job.unwrap();
-            let res: T = job();
-            sender.send(res).expect("Failed to send a result back to the pool");
         });
         Worker { id, thread }
     }
@@ -93,11 +168,17 @@ impl Worker {
 
 pub struct ThreadPool<T: Send + 'static> {
     /// specifies the number of threads.
+    #[allow(dead_code)]
     workers: Vec<Worker>,
 
     result_receiver: Receiver<T>,
 
     job_sender: Sender<Job<T>>,
+
+    /// The time each worker spent running jobs, in nanoseconds.
+    busy_nanos: Vec<Arc<AtomicU64>>,
+
+    created_at: Instant,
 }
 
 impl<T: Send + 'static> ThreadPool<T> {
@@ -107,16 +188,31 @@ impl<T: Send + 'static> ThreadPool<T> {
         let (sender_v, receiver_v) = mpsc::channel();
 
         let receiver = Arc::new(Mutex::new(receiver));
+        let busy_nanos = (0..n).map(|_| Arc::new(AtomicU64::new(0))).collect::<Vec<_>>();
         for id in 0..n {
-            workers.push(Worker::new(id, sender_v.clone(), receiver.clone()));
+            workers.push(Worker::new(id, sender_v.clone(), receiver.clone(), busy_nanos[id as usize].clone()));
         }
         ThreadPool {
             workers,
             result_receiver: receiver_v,
             job_sender: sender,
+            busy_nanos,
+            created_at: clock::current().now(),
         }
     }
 
+    /// Returns the time each worker spent running jobs, indexed by worker id.
+    pub fn busy_times(&self) -> Vec<Duration> {
+        self.busy_nanos.iter()
+            .map(|nanos| Duration::from_nanos(nanos.load(Ordering::Relaxed)))
+            .collect()
+    }
+
+    /// Returns the time elapsed since the pool got created.
+    pub fn elapsed(&self) -> Duration {
+        clock::current().elapsed(self.created_at)
+    }
+
     pub fn execute<F>(&self, f: F)
     where 
         F: FnOnce() -> T + Send + 'static
@@ -140,6 +236,7 @@ impl<T: Send + 'static> ThreadPool<T> {
 
     /// TODO: Need to make a graceful shutdown implemented.
     /// ALso, need cancellation tokens.
+    #[allow(dead_code)]
     pub fn shutdown(&self) {
         
     }
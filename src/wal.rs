@@ -1,10 +1,69 @@
-use std::{fs::OpenOptions, io::{self, Read, Write}};
-use serde_json;
+use std::io;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use serde::{Serialize, Deserialize};
 
+use crate::filesystem;
+use crate::format::{self, Format};
+use crate::schema::{self, CURRENT_VERSION};
+use crate::simulation::duration;
 use crate::utilities;
 
-#[derive(Serialize, Deserialize)]
+/// The number of 16 MiB segments in a logical log file, so the last eight
+/// digits of a segment name never go past FF.
+pub const SEGMENTS_PER_LOG: u64 = 0x100;
+
+/// Splits a segment name into its timeline and its position in the sequence
+/// of segments. The name is the timeline, the logical log file and the segment
+/// within it, eight hexadecimal digits each.
+pub fn parse_segment_name(name: &str) -> Option<(u32, u64)> {
+    if name.len() != 24 || !name.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let timeline = u32::from_str_radix(&name[..8], 16).ok()?;
+    let log = u64::from_str_radix(&name[8..16], 16).ok()?;
+    let segment = u64::from_str_radix(&name[16..], 16).ok()?;
+    if segment >= SEGMENTS_PER_LOG {
+        return None;
+    }
+
+    Some((timeline, log * SEGMENTS_PER_LOG + segment))
+}
+
+/// Formats the name of the segment at the given position of the timeline,
+/// the reverse of `parse_segment_name`.
+pub fn segment_name(timeline: u32, position: u64) -> String {
+    format!("{:08X}{:08X}{:08X}", timeline, position / SEGMENTS_PER_LOG, position % SEGMENTS_PER_LOG)
+}
+
+fn is_hex(text: &str, len: usize) -> bool {
+    text.len() == len && text.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Tells whether the name is one of the files PostgreSQL archives: a segment,
+/// the partial segment left behind by a promotion, a backup history file or a
+/// timeline history file. They are all archived under their own name.
+pub fn is_archivable_name(name: &str) -> bool {
+    if let Some(segment) = name.strip_suffix(".partial") {
+        return parse_segment_name(segment).is_some();
+    }
+    if let Some(backup) = name.strip_suffix(".backup") {
+        return backup.split_once('.')
+            .is_some_and(|(segment, offset)| parse_segment_name(segment).is_some() && is_hex(offset, 8));
+    }
+    if let Some(timeline) = name.strip_suffix(".history") {
+        return is_hex(timeline, 8);
+    }
+    parse_segment_name(name).is_some()
+}
+
+fn is_zero(n: &u32) -> bool {
+    *n == 0
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub enum WalAction {
     /// Signifies the number of times that uploading this file will fail.
     /// When it is 0, it is expected to be succeeded.
@@ -12,17 +71,193 @@ pub enum WalAction {
 
     /// Upload is successful.
     Success,
+
+    /// The upload hangs until the processor gives up on it, `count` times.
+    Timeout { count: u8 },
+
+    /// The checksum of the uploaded copy does not match the segment, `count` times.
+    Corrupt { count: u8 },
+
+    /// Only part of the segment gets written to the destination, `count` times.
+    Partial { count: u8 },
+
+    /// The upload is rejected for good, e.g. access is denied, so retrying
+    /// is pointless.
+    PermanentError,
+
+    /// The upload succeeds, but takes `factor` times its usual duration.
+    Slow { factor: f64 },
+
+    /// The worker uploading the segment panics, once.
+    Panic,
+}
+
+/// Tells whether a failed attempt is worth retrying.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorClass {
+    /// A transient problem, e.g. a network blip or an unavailable destination.
+    Retryable,
+
+    /// Retrying cannot help, e.g. access to the destination is denied.
+    Permanent,
+
+    /// Nothing is known about the failure, e.g. the worker panicked.
+    Unknown,
+}
+
+impl ErrorClass {
+    pub const ALL: [ErrorClass; 3] = [ErrorClass::Retryable, ErrorClass::Permanent, ErrorClass::Unknown];
+
+    /// Classifies an I/O error of an archive attempt.
+    pub fn of(e: &io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::TimedOut
+            | io::ErrorKind::Interrupted
+            | io::ErrorKind::WouldBlock
+            | io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::NotConnected
+            | io::ErrorKind::BrokenPipe => ErrorClass::Retryable,
+            io::ErrorKind::NotFound
+            | io::ErrorKind::AlreadyExists
+            | io::ErrorKind::PermissionDenied
+            | io::ErrorKind::InvalidInput
+            | io::ErrorKind::InvalidData => ErrorClass::Permanent,
+            _ => ErrorClass::Unknown,
+        }
+    }
+}
+
+impl fmt::Display for ErrorClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorClass::Retryable => write!(f, "retryable"),
+            ErrorClass::Permanent => write!(f, "permanent"),
+            ErrorClass::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+/// Why an attempt at archiving a segment failed.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FailureReason {
+    pub class: ErrorClass,
+    pub message: String,
+
+    /// The number of the failed attempt, starting from 1.
+    pub attempt: u32,
+
+    /// How long the attempt took.
+    #[serde(with = "duration")]
+    pub elapsed: Duration,
+
+    /// When the failure tells when to try again, overriding the backoff of
+    /// the retry policy. Only the processor's scheduling looks at it.
+    #[serde(skip)]
+    pub retry_after: Option<Duration>,
+}
+
+impl FailureReason {
+    /// The attempt number and the elapsed time are filled in once the failure
+    /// is recorded.
+    pub fn new(class: ErrorClass, message: impl Into<String>) -> Self {
+        FailureReason { class, message: message.into(), attempt: 0, elapsed: Duration::ZERO, retry_after: None }
+    }
+
+    pub fn retry_after(self, delay: Duration) -> Self {
+        FailureReason { retry_after: Some(delay), ..self }
+    }
+}
+
+impl fmt::Display for FailureReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "attempt {} failed after {} ({}): {}",
+            self.attempt, duration::format_duration(&self.elapsed), self.class, self.message)
+    }
+}
+
+/// When and how the attempts at archiving a segment went. It lives in the
+/// status file, so that it survives restarts of the processor.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct AttemptHistory {
+    /// The number of attempts taken so far, the successful one included.
+    pub attempts: u32,
+
+    /// The number of attempts that failed to archive the segment so far.
+    #[serde(skip_serializing_if = "is_zero")]
+    pub failures: u32,
+
+    /// When the first attempt started, in milliseconds since the UNIX epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_attempt_at: Option<u64>,
+
+    /// When the latest attempt started, in milliseconds since the UNIX epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_attempt_at: Option<u64>,
+
+    /// Why the latest failed attempt failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<FailureReason>,
+
+    /// When the segment may be attempted again, in milliseconds since the
+    /// UNIX epoch. Unset once it is archived or is not to be retried.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_attempt_at: Option<u64>,
+}
+
+impl AttemptHistory {
+    fn is_empty(&self) -> bool {
+        *self == AttemptHistory::default()
+    }
+
+    /// Records an attempt which started at `started_at`.
+    pub fn record_attempt(&mut self, started_at: u64) {
+        self.attempts += 1;
+        self.first_attempt_at.get_or_insert(started_at);
+        self.last_attempt_at = Some(started_at);
+    }
+}
+
+/// Describes the archived copy of a segment, so that the archive can be
+/// verified later on.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ArchivedSegment {
+    /// The size of the segment in bytes.
+    pub size: u64,
+
+    /// The hex encoded SHA-256 digest of the segment.
+    pub sha256: String,
 }
 
 /// Represents the WAL file format.
 #[derive(Serialize, Deserialize)]
 pub struct WalFile {
+    /// The version of the format, older files are migrated on read.
+    pub(crate) version: u32,
+
     /// The type of action to be performed by the processor.
     pub(crate) action: WalAction,
 
-    /// The duration where each action will take. The unit is
-    /// milliseconds.
-    pub(crate) duration: u64, 
+    /// The duration where each action will take.
+    #[serde(with = "duration")]
+    pub(crate) duration: Duration,
+
+    /// The location of the segment's data, when it lives outside of the
+    /// source directory (e.g. the `%p` argument given by archive-push).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) segment_path: Option<String>,
+
+    /// Recorded once the segment's data is stored in the destination.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) archived: Option<ArchivedSegment>,
+
+    /// The attempts taken so far, missing from the status files written
+    /// before any attempt and by older versions.
+    #[serde(default, skip_serializing_if = "AttemptHistory::is_empty")]
+    pub(crate) history: AttemptHistory,
 
     /// The file name to be stored to take action on it.
     #[serde(skip_serializing, skip_deserializing)]
@@ -32,131 +267,294 @@ pub struct WalFile {
 impl WalFile {
     /// Reads the provided WAL file and constructs the WAL file format.
     pub fn read(f_name: &str) -> Self {
-        // need to make this work with bytes...
-        let mut file_contents = String::new();
-
-        let mut f = std::fs::OpenOptions::new()
-            .read(true)
-            .open(f_name)
-            .expect("File does not exist");
-        let read_bytes = f.read_to_string(&mut file_contents).expect("Reading from file did not end up as expected");
-        println!("File size was: {}", read_bytes); 
-
-        // let mut buffer = vec![0; 1024];
-        // let (zeros, content) = buffer.split_at(1024-read_bytes);
-        // println!("content: {:?}", content);
-
-        // file_contents = String::from_utf8(buffer).expect("Failed to convert the read bytes into string");
-        let mut wal_file: WalFile = serde_json::from_str(&file_contents).expect("The WAL has incorrect formatting");
+        let file_contents = filesystem::current().read(Path::new(f_name)).expect("File does not exist");
+        println!("File size was: {}", file_contents.len());
+
+        let mut wal_file = WalFile::parse(&file_contents).expect("The WAL has incorrect formatting");
         wal_file.file_name = f_name.to_string();
         wal_file
     }
 
+    /// Reads the provided WAL file like `read`, but reports missing or badly
+    /// formatted files as errors instead of panicking.
+    pub fn try_read(f_name: &str) -> io::Result<Self> {
+        let file_contents = filesystem::current().read(Path::new(f_name))?;
+        let mut wal_file = WalFile::parse(&file_contents)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        wal_file.file_name = f_name.to_string();
+        Ok(wal_file)
+    }
+
+    /// Parses the contents of a status file of any version and format,
+    /// upgrading it to the current version.
+    pub fn parse(contents: &[u8]) -> Result<Self, String> {
+        WalFile::parse_versioned(contents).map(|(wal_file, _)| wal_file)
+    }
+
+    /// Parses the contents of a status file like `parse`, also returning the
+    /// version it was written with.
+    pub fn parse_versioned(contents: &[u8]) -> Result<(Self, u32), String> {
+        let (mut value, _) = format::decode(contents)?;
+        let version = schema::migrate(&mut value)?;
+        let wal_file = serde_json::from_value(value).map_err(|e| e.to_string())?;
+        Ok((wal_file, version))
+    }
+
     /// When WAL file is simulating a failure case, it would include
     /// the number of attempts it would fail. When the count reaches 0,
-    /// it would alter the action to become "success".
+    /// it would alter the action to become "success". A panic only happens
+    /// once, while a permanent error never goes away.
     /// If the action is already "Success", then this is a no-op.
     pub fn decrement_failure_count(&mut self) -> std::io::Result<()> {
-        match self.action {
-            WalAction::Fail { count } => {
-                if count <= 1 {
-                    self.action = WalAction::Success;
-                } else {
-                    self.action = WalAction::Fail { count: count - 1 };
-                }
-            },
-
-            _ => {},
-        }
+        self.action = match self.action {
+            WalAction::Fail { count } if count > 1 => WalAction::Fail { count: count - 1 },
+            WalAction::Timeout { count } if count > 1 => WalAction::Timeout { count: count - 1 },
+            WalAction::Corrupt { count } if count > 1 => WalAction::Corrupt { count: count - 1 },
+            WalAction::Partial { count } if count > 1 => WalAction::Partial { count: count - 1 },
+            WalAction::PermanentError => WalAction::PermanentError,
+            _ => WalAction::Success,
+        };
 
         Ok(())
     }
     
     /// Each character of a WAL file name is in 16 Base, thus can reach "f".
     /// given the number, construct the WAL file name and generate a WalFile object.
-    pub fn generate_wal_file(num: u64, action: WalAction, work_duration: u64) -> WalFile {
-        let hex_name = format!("{:X}", num);
+    pub fn generate_wal_file(num: u64, action: WalAction, work_duration: Duration) -> WalFile {
+        WalFile::simulated(&segment_name(1, num), action, work_duration)
+    }
+
+    /// Generates the WalFile of a simulated segment with the given name.
+    pub fn simulated(segment_name: &str, action: WalAction, work_duration: Duration) -> WalFile {
         WalFile {
-            action: action,
+            version: CURRENT_VERSION,
+            action,
             duration: work_duration,
-            file_name: format!("{}/00000001{:0>16}.ready", utilities::STATUS_DIR, hex_name)
+            segment_path: None,
+            archived: None,
+            history: AttemptHistory::default(),
+            file_name: format!("{}/{}.ready", utilities::layout().status_dir, segment_name)
         }
     }
 
+    /// Generates the WalFile for an externally provided segment, such as the
+    /// one PostgreSQL hands over to the archive_command.
+    pub fn for_segment(segment_name: &str, segment_path: &str) -> WalFile {
+        WalFile {
+            version: CURRENT_VERSION,
+            action: WalAction::Success,
+            duration: Duration::ZERO,
+            segment_path: Some(segment_path.to_string()),
+            archived: None,
+            history: AttemptHistory::default(),
+            file_name: format!("{}/{}.ready", utilities::layout().status_dir, segment_name)
+        }
+    }
+
+    /// The name of the segment without the directory and the extension of
+    /// the status file, e.g. `00000002.history` for `00000002.history.ready`.
+    pub fn segment_name(&self) -> String {
+        let file_name = self.file_name.split('/').next_back().unwrap();
+        file_name.rsplit_once('.').map_or(file_name, |(name, _)| name).to_string()
+    }
+
+    /// Returns the location of the segment's data if there is any to archive.
+    /// Segments generated by the simulation carry no data.
+    pub fn payload_path(&self) -> Option<PathBuf> {
+        if let Some(segment_path) = &self.segment_path {
+            return Some(PathBuf::from(segment_path));
+        }
+
+        let default_path = Path::new(&utilities::layout().source_dir).join(self.segment_name());
+        if filesystem::current().exists(&default_path) {
+            Some(default_path)
+        } else {
+            None
+        }
+    }
+
+    /// Writes the WAL file into a temporary file first and renames it, so that
+    /// a concurrent reader (e.g. the processor daemon) never observes a
+    /// partially written file. It is encoded in the format of the layout.
     pub fn flush_to_file(&self) -> io::Result<()> {
+        self.flush_to_file_as(utilities::layout().status_format)
+    }
+
+    /// Writes the WAL file like `flush_to_file`, in the given format.
+    pub fn flush_to_file_as(&self, format: Format) -> io::Result<()> {
        if self.file_name.is_empty() {
             return Err(io::Error::new(io::ErrorKind::NotFound, "file name is empty")); 
        } 
 
-       let mut f = OpenOptions::new()
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .open(&self.file_name)
-            .expect(&format!("could not open/create a WAL file with name: {:?}", self.file_name));
-       let buffer = serde_json::to_string(&self)
-            .expect("failed to deserialize the WalFile");
-       f.write_all(buffer.as_bytes())
+       let tmp_file_name = format!("{}.tmp", self.file_name);
+       let fs = filesystem::current();
+       fs.write(Path::new(&tmp_file_name), &format.encode(&self)?)?;
+       fs.rename(Path::new(&tmp_file_name), Path::new(&self.file_name))
     }
 
     /// Renames the .ready WAL file as .done
     pub fn mark_done(&self) -> io::Result<()> {
         let file_name = &self.file_name;
-        let file_name = file_name.split('.').take(1).collect::<String>();
+        let file_name = file_name.rsplit_once('.').map_or(file_name.as_str(), |(name, _)| name);
         let done_file_name = format!("{}.done", file_name);
         
-        std::fs::rename(&self.file_name, &done_file_name)
+        filesystem::current().rename(Path::new(&self.file_name), Path::new(&done_file_name))
+    }
+
+    /// Tells whether the status records the segment as archived: the attempt
+    /// succeeded, and the segment's data, if it has any, is in the destination.
+    pub fn is_archived(&self) -> bool {
+        self.action == WalAction::Success && (self.segment_path.is_none() || self.archived.is_some())
     }
 
-    /// Generates a new .done WAL file under file-source folder.
+    /// Generates a new .done WAL file under file-source folder, holding the
+    /// status of the archived segment. The marker is synced before it shows
+    /// up, as the consumer may remove it right away.
     pub fn generate_done_file(&self) -> io::Result<()> {
-        let file_name = &self.file_name;
-        let file_name = file_name.split('.').take(1).collect::<String>();
-        let file_name = file_name.split('/').last().unwrap();
-        let done_file_name = format!("{}/{}.done", utilities::SOURCE_DIR, file_name);
-        
-        OpenOptions::new()
-            .write(true)
-            .create(true)
-            .open(done_file_name).unwrap();
-        Ok(())
+        let source_dir = &utilities::layout().source_dir;
+        let done_file_name = format!("{}/{}.done", source_dir, self.segment_name());
+        let tmp_file_name = format!("{}.tmp", done_file_name);
+        let fs = filesystem::current();
+        fs.write(Path::new(&tmp_file_name), &utilities::layout().status_format.encode(&self)?)?;
+        fs.fsync(Path::new(&tmp_file_name))?;
+        fs.rename(Path::new(&tmp_file_name), Path::new(&done_file_name))?;
+        fs.fsync(Path::new(source_dir))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::filesystem::FileSystem;
 
     #[test]
     fn serialization_ignore_file_name() {
-        let x = WalFile { action: WalAction::Success, duration: 10, file_name: "test".to_string() };
+        let x = WalFile { version: CURRENT_VERSION, action: WalAction::Success, duration: Duration::from_millis(10), segment_path: None, archived: None, history: AttemptHistory::default(), file_name: "test".to_string() };
         let y: WalFile = serde_json::from_str(&serde_json::to_string(&x).unwrap()).unwrap();
         assert!(y.file_name.is_empty());
 
-        let x = WalFile { action: WalAction::Fail { count: 100 }, duration: 10, file_name: "test".to_string() };
+        let x = WalFile { version: CURRENT_VERSION, action: WalAction::Fail { count: 100 }, duration: Duration::from_millis(10), segment_path: None, archived: None, history: AttemptHistory::default(), file_name: "test".to_string() };
         let y: WalFile = serde_json::from_str(&serde_json::to_string(&x).unwrap()).unwrap();
         assert!(y.file_name.is_empty());
     }
 
     #[test]
     fn serialization_format() {
-        let x = WalFile { action: WalAction::Success, duration: 10, file_name: "test".to_string() };
-        assert_eq!("{\"action\":\"Success\",\"duration\":10}", serde_json::to_string(&x).unwrap());
+        let x = WalFile { version: CURRENT_VERSION, action: WalAction::Success, duration: Duration::from_millis(10), segment_path: None, archived: None, history: AttemptHistory::default(), file_name: "test".to_string() };
+        assert_eq!("{\"version\":5,\"action\":\"Success\",\"duration\":\"10ms\"}", serde_json::to_string(&x).unwrap());
         
-        let x = WalFile { action: WalAction::Fail { count: 10 }, duration: 100, file_name: "test".to_string() };
-        assert_eq!("{\"action\":{\"Fail\":{\"count\":10}},\"duration\":100}", serde_json::to_string(&x).unwrap());
+        let x = WalFile { version: CURRENT_VERSION, action: WalAction::Fail { count: 10 }, duration: Duration::from_millis(100), segment_path: None, archived: None, history: AttemptHistory::default(), file_name: "test".to_string() };
+        assert_eq!("{\"version\":5,\"action\":{\"Fail\":{\"count\":10}},\"duration\":\"100ms\"}", serde_json::to_string(&x).unwrap());
+    }
+
+    #[test]
+    fn failure_count() {
+        let mut w = WalFile::generate_wal_file(1, WalAction::Timeout { count: 2 }, Duration::from_millis(10));
+        w.decrement_failure_count().unwrap();
+        assert_eq!(WalAction::Timeout { count: 1 }, w.action);
+        w.decrement_failure_count().unwrap();
+        assert_eq!(WalAction::Success, w.action);
+
+        w.action = WalAction::Panic;
+        w.decrement_failure_count().unwrap();
+        assert_eq!(WalAction::Success, w.action);
+
+        w.action = WalAction::PermanentError;
+        w.decrement_failure_count().unwrap();
+        assert_eq!(WalAction::PermanentError, w.action);
+
+        w.action = WalAction::Slow { factor: 2.5 };
+        assert_eq!("{\"version\":5,\"action\":{\"Slow\":{\"factor\":2.5}},\"duration\":\"10ms\"}", serde_json::to_string(&w).unwrap());
+    }
+
+    #[test]
+    fn attempt_history() {
+        // status files written before the history existed count their failures in it.
+        let w = WalFile::parse(b"{\"action\":{\"Fail\":{\"count\":2}},\"duration\":10,\"failures\":1}").unwrap();
+        assert_eq!(AttemptHistory { attempts: 1, failures: 1, ..AttemptHistory::default() }, w.history);
+
+        let mut history = AttemptHistory::default();
+        history.record_attempt(1_000);
+        history.record_attempt(2_500);
+        history.last_error = Some(FailureReason { attempt: 2, ..FailureReason::new(ErrorClass::Unknown, "the worker panicked") });
+        history.next_attempt_at = Some(3_000);
+        assert_eq!((2, Some(1_000), Some(2_500)), (history.attempts, history.first_attempt_at, history.last_attempt_at));
+
+        let w = WalFile { history, ..WalFile::generate_wal_file(1, WalAction::Success, Duration::from_millis(10)) };
+        let y: WalFile = serde_json::from_str(&serde_json::to_string(&w).unwrap()).unwrap();
+        assert_eq!(w.history, y.history);
+    }
+
+    #[test]
+    fn status_formats() {
+        let mut w = WalFile::generate_wal_file(1, WalAction::Slow { factor: 2.5 }, Duration::from_millis(10));
+        w.history.record_attempt(1_000);
+        w.history.last_error = Some(FailureReason::new(ErrorClass::Retryable, "timed out"));
+        for format in Format::ALL {
+            let y = WalFile::parse(&format.encode(&w).unwrap()).unwrap();
+            assert_eq!((&w.action, &w.history), (&y.action, &y.history), "{}", format);
+        }
+    }
+
+    #[test]
+    fn status_file_in_memory() {
+        let fs = std::sync::Arc::new(filesystem::MemoryFileSystem::default());
+        fs.create_dir_all(Path::new(&utilities::layout().status_dir)).unwrap();
+        filesystem::with(fs.clone(), || {
+            let w = WalFile::generate_wal_file(1, WalAction::Fail { count: 2 }, Duration::from_millis(10));
+            w.flush_to_file().unwrap();
+            assert_eq!(w.action, WalFile::try_read(&w.file_name).unwrap().action);
+
+            w.generate_done_file().unwrap();
+            w.mark_done().unwrap();
+            assert!(WalFile::try_read(&w.file_name).is_err());
+        });
+
+        let status_dir = Path::new(&utilities::layout().status_dir);
+        assert_eq!(vec![status_dir.join("000000010000000000000001.done")], fs.read_dir(status_dir).unwrap());
+        let source_dir = Path::new(&utilities::layout().source_dir);
+        assert_eq!(vec![source_dir.join("000000010000000000000001.done")], fs.read_dir(source_dir).unwrap());
+    }
+
+    #[test]
+    fn failure_reason() {
+        assert_eq!(ErrorClass::Permanent, ErrorClass::of(&io::Error::from(io::ErrorKind::PermissionDenied)));
+        assert_eq!(ErrorClass::Retryable, ErrorClass::of(&io::Error::from(io::ErrorKind::TimedOut)));
+        assert_eq!(ErrorClass::Unknown, ErrorClass::of(&io::Error::other("disk on fire")));
+
+        let reason = FailureReason { attempt: 2, elapsed: Duration::from_millis(15), ..FailureReason::new(ErrorClass::Retryable, "timed out") };
+        assert_eq!("attempt 2 failed after 15ms (retryable): timed out", reason.to_string());
+        assert_eq!(
+            "{\"class\":\"retryable\",\"message\":\"timed out\",\"attempt\":2,\"elapsed\":\"15ms\"}",
+            serde_json::to_string(&reason).unwrap());
     }
 
     #[test]
     fn wal_file_number() {
-        let w = WalFile::generate_wal_file(1, WalAction::Success, 10);
-        let expected_w = format!("{}/000000010000000000000001.ready", utilities::STATUS_DIR);
+        let w = WalFile::generate_wal_file(1, WalAction::Success, Duration::from_millis(10));
+        let expected_w = format!("{}/000000010000000000000001.ready", utilities::layout().status_dir);
+
+        assert_eq!(expected_w, w.file_name);
+
+        let w = WalFile::generate_wal_file(255, WalAction::Success, Duration::from_millis(10));
+        let expected_w = format!("{}/0000000100000000000000FF.ready", utilities::layout().status_dir);
 
         assert_eq!(expected_w, w.file_name);
 
-        let w = WalFile::generate_wal_file(255, WalAction::Success, 10);
-        let expected_w = format!("{}/0000000100000000000000FF.ready", utilities::STATUS_DIR);
+        let w = WalFile::generate_wal_file(256, WalAction::Success, Duration::from_millis(10));
+        let expected_w = format!("{}/000000010000000100000000.ready", utilities::layout().status_dir);
 
         assert_eq!(expected_w, w.file_name);
     }
+
+    #[test]
+    fn external_segment() {
+        let w = WalFile::for_segment("000000010000000000000003", "pg_wal/000000010000000000000003");
+        assert_eq!(format!("{}/000000010000000000000003.ready", utilities::layout().status_dir), w.file_name);
+        assert_eq!("000000010000000000000003", w.segment_name());
+        assert_eq!(Some(PathBuf::from("pg_wal/000000010000000000000003")), w.payload_path());
+        assert_eq!(
+            "{\"version\":5,\"action\":\"Success\",\"duration\":\"0s\",\"segment_path\":\"pg_wal/000000010000000000000003\"}",
+            serde_json::to_string(&w).unwrap());
+    }
 }
\ No newline at end of file